@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Local};
+
+use crate::alert::Alert;
+use crate::config::BreakerConfig;
+use crate::output::{Event, Measurement, Reading};
+use crate::wisun_module::PhaseCurrent;
+
+/// Readings needed before a trend is estimated.
+const MIN_TREND_SAMPLES: usize = 3;
+
+/// Levels at which the warnings are raised.
+struct Limits {
+    contract: f64,
+    warning: f64,
+    hysteresis: f64,
+    window: Duration,
+    horizon: Duration,
+}
+
+/// Warning state of one phase.
+struct Phase {
+    name: &'static str,
+    rule: &'static str,
+    /// Currents read within the trend window, the oldest first.
+    samples: VecDeque<(DateTime<Local>, f64)>,
+    overloaded: bool,
+    rising: bool,
+}
+
+impl Phase {
+    fn new(name: &'static str, rule: &'static str) -> Self {
+        Phase { name, rule, samples: VecDeque::new(), overloaded: false, rising: false }
+    }
+
+    /// Least squares fit of the samples: the current at the latest sample and the slope in A/s.
+    fn trend(&self) -> Option<(f64, f64)> {
+        if self.samples.len() < MIN_TREND_SAMPLES {
+            return None;
+        }
+        let (latest, _) = *self.samples.back()?;
+        let points: Vec<(f64, f64)> = self.samples.iter()
+            .map(|(t, a)| ((*t - latest).num_milliseconds() as f64 / 1000.0, *a))
+            .collect();
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_a = points.iter().map(|(_, a)| a).sum::<f64>() / n;
+        let variance: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+        let slope = points.iter().map(|(t, a)| (t - mean_t) * (a - mean_a)).sum::<f64>() / variance;
+        Some((mean_a - slope * mean_t, slope))
+    }
+
+    fn check(&mut self, limits: &Limits, timestamp: DateTime<Local>, ampere: f64) -> Vec<Alert> {
+        let alert = |condition, firing, message| Alert { rule: self.rule.to_string(), condition, firing, timestamp, message };
+        let mut alerts = Vec::new();
+
+        if !self.overloaded && ampere >= limits.warning {
+            alerts.push(alert("overload", true, format!("{} phase current is {:.1} A, {:.0}% of the {} A contract",
+                                                        self.name, ampere, ampere / limits.contract * 100.0, limits.contract)));
+            self.overloaded = true;
+        } else if self.overloaded && ampere < limits.warning - limits.hysteresis {
+            alerts.push(alert("overload", false, format!("{} phase current is {:.1} A", self.name, ampere)));
+            self.overloaded = false;
+        }
+
+        self.samples.push_back((timestamp, ampere));
+        while self.samples.front().is_some_and(|(t, _)| timestamp - *t > limits.window) {
+            self.samples.pop_front();
+        }
+        // The current predicted at the end of the horizon, and the seconds until the contract current is reached.
+        let predicted = match self.trend() {
+            Some((fitted, slope)) if slope > 0.0 => {
+                Some((fitted + slope * limits.horizon.num_seconds() as f64, (limits.contract - fitted) / slope))
+            }
+            _ => None,
+        };
+        if !self.rising {
+            if let Some((_, seconds)) = predicted.filter(|(a, _)| *a >= limits.contract) {
+                alerts.push(alert("rising_trend", true, format!("{} phase current is rising and may reach the {} A contract in {:.0} s",
+                                                                self.name, limits.contract, seconds.max(0.0))));
+                self.rising = true;
+            }
+        } else if predicted.is_none_or(|(a, _)| a < limits.contract - limits.hysteresis) {
+            alerts.push(alert("rising_trend", false, format!("{} phase current is no longer rising toward the {} A contract",
+                                                             self.name, limits.contract)));
+            self.rising = false;
+        }
+        alerts
+    }
+
+    /// An overflowed current is above any level, but has no value to add to the trend.
+    fn check_overflow(&mut self, limits: &Limits, timestamp: DateTime<Local>) -> Option<Alert> {
+        if self.overloaded {
+            return None;
+        }
+        self.overloaded = true;
+        Some(Alert {
+            rule: self.rule.to_string(),
+            condition: "overload",
+            firing: true,
+            timestamp,
+            message: format!("{} phase current is over the measurable range, above the {} A contract", self.name, limits.contract),
+        })
+    }
+}
+
+/// Warns before the breaker of the contract current trips.
+///
+/// A phase raises an `overload` alert when its current is above the warning percentage of the contract current, and
+/// a `rising_trend` alert when the trend of its current over the trend window reaches the contract current within
+/// the prediction horizon. Both are resolved once the current, or the prediction, falls `hysteresis` amperes below
+/// the level which raised them.
+pub struct BreakerMonitor {
+    limits: Limits,
+    phases: [Phase; 2],
+}
+
+impl BreakerMonitor {
+    pub fn new(config: &BreakerConfig) -> Self {
+        BreakerMonitor {
+            limits: Limits {
+                contract: config.contract_ampere,
+                warning: config.contract_ampere * config.warning_percent / 100.0,
+                hysteresis: config.hysteresis,
+                window: Duration::seconds(config.trend_window as i64),
+                horizon: Duration::seconds(config.prediction_horizon as i64),
+            },
+            phases: [Phase::new("R", "breaker_r_phase"), Phase::new("T", "breaker_t_phase")],
+        }
+    }
+
+    /// The alerts raised or resolved by the current in `event`.
+    pub fn observe(&mut self, event: &Event) -> Vec<Alert> {
+        let (timestamp, current) = match event {
+            Event::Reading(Reading { timestamp, measurement: Measurement::InstantaneousCurrent(c), .. }) => (*timestamp, c),
+            _ => return Vec::new(),
+        };
+        let mut alerts = Vec::new();
+        for (phase, value) in self.phases.iter_mut().zip([Some(current.r_phase), current.t_phase]) {
+            match value {
+                Some(PhaseCurrent::Value(a)) => alerts.extend(phase.check(&self.limits, timestamp, a)),
+                Some(PhaseCurrent::Overflow) => alerts.extend(phase.check_overflow(&self.limits, timestamp)),
+                _ => {}
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::{received, time};
+    use crate::wisun_module::InstantaneousCurrent;
+
+    use super::*;
+
+    fn config() -> BreakerConfig {
+        BreakerConfig {
+            contract_ampere: 40.0,
+            warning_percent: 90.0,
+            hysteresis: 2.0,
+            trend_window: 300,
+            prediction_horizon: 300,
+        }
+    }
+
+    fn current(r: f64, t: f64, timestamp: i64) -> Event {
+        received(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(r),
+            t_phase: Some(PhaseCurrent::Value(t)),
+        }), time(timestamp), time(timestamp))
+    }
+
+    fn summary(alerts: Vec<Alert>) -> Vec<(String, &'static str, bool)> {
+        alerts.into_iter().map(|a| (a.rule, a.condition, a.firing)).collect()
+    }
+
+    #[test]
+    fn overload() {
+        let mut monitor = BreakerMonitor::new(&config());
+        assert!(monitor.observe(&current(30.0, 10.0, 0)).is_empty());
+        let alerts = monitor.observe(&current(30.0, 38.0, 1000));
+        assert_eq!("T phase current is 38.0 A, 95% of the 40 A contract", alerts[0].message);
+        assert_eq!(vec![("breaker_t_phase".to_string(), "overload", true)], summary(alerts));
+        assert!(monitor.observe(&current(30.0, 35.0, 2000)).is_empty());
+        assert_eq!(vec![("breaker_t_phase".to_string(), "overload", false)], summary(monitor.observe(&current(30.0, 33.0, 3000))));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut monitor = BreakerMonitor::new(&config());
+        let overflow = |timestamp| received(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Overflow,
+            t_phase: Some(PhaseCurrent::Value(10.0)),
+        }), time(timestamp), time(timestamp));
+        let alerts = monitor.observe(&overflow(0));
+        assert_eq!("R phase current is over the measurable range, above the 40 A contract", alerts[0].message);
+        assert_eq!(vec![("breaker_r_phase".to_string(), "overload", true)], summary(alerts));
+        assert!(monitor.observe(&overflow(60)).is_empty());
+        assert_eq!(vec![("breaker_r_phase".to_string(), "overload", false)], summary(monitor.observe(&current(30.0, 10.0, 120))));
+    }
+
+    #[test]
+    fn rising_trend() {
+        let mut monitor = BreakerMonitor::new(&config());
+        assert!(monitor.observe(&current(20.0, 5.0, 0)).is_empty());
+        assert!(monitor.observe(&current(24.0, 5.0, 60)).is_empty());
+        let alerts = monitor.observe(&current(28.0, 5.0, 120));
+        assert_eq!("R phase current is rising and may reach the 40 A contract in 180 s", alerts[0].message);
+        assert_eq!(vec![("breaker_r_phase".to_string(), "rising_trend", true)], summary(alerts));
+        assert!(monitor.observe(&current(29.0, 5.0, 180)).is_empty());
+        assert_eq!(vec![("breaker_r_phase".to_string(), "rising_trend", false)],
+                   summary(monitor.observe(&current(20.0, 5.0, 240))));
+        assert!(monitor.observe(&current(20.0, 5.0, 600)).is_empty());
+    }
+}
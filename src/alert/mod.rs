@@ -0,0 +1,5 @@
+mod breaker;
+mod rule;
+
+pub use breaker::BreakerMonitor;
+pub use rule::{Alert, AlertRule};
@@ -0,0 +1,213 @@
+use chrono::{DateTime, Duration, Local};
+use serde_json::{json, Value};
+
+use crate::config::{AlertCondition, AlertRuleConfig};
+use crate::output::{Event, Measurement};
+use crate::wisun_module::ConnectionState;
+
+/// A rule which started or stopped firing.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Alert {
+    pub rule: String,
+    pub condition: &'static str,
+    /// Whether the rule started firing, rather than being resolved.
+    pub firing: bool,
+    pub timestamp: DateTime<Local>,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "rule": self.rule,
+            "condition": self.condition,
+            "status": if self.firing { "firing" } else { "resolved" },
+            "timestamp": self.timestamp.to_rfc3339(),
+            "message": self.message,
+        })
+    }
+}
+
+/// Fires when its condition has held for the configured duration, and is resolved when the condition no longer holds.
+///
+/// A `power_above` alert is only resolved once the power has fallen `hysteresis` watts below the limit, so that
+/// a power hovering around the limit does not raise an alert on every reading.
+pub struct AlertRule {
+    name: String,
+    condition: AlertCondition,
+    limit: i32,
+    hysteresis: i32,
+    duration: Duration,
+    /// Since when the condition has held. For `no_reading`, when the last reading was.
+    since: Option<DateTime<Local>>,
+    /// Whether the power has fallen enough to resolve a `power_above` alert.
+    power_cleared: bool,
+    power: Option<i32>,
+    firing: bool,
+}
+
+impl AlertRule {
+    pub fn new(config: &AlertRuleConfig, now: DateTime<Local>) -> Self {
+        AlertRule {
+            name: config.name.clone(),
+            condition: config.condition,
+            limit: config.limit.unwrap_or_default(),
+            hysteresis: config.hysteresis,
+            duration: Duration::seconds(config.duration as i64),
+            since: match config.condition {
+                AlertCondition::NoReading => Some(now),
+                _ => None,
+            },
+            power_cleared: true,
+            power: None,
+            firing: false,
+        }
+    }
+
+    pub fn observe(&mut self, event: &Event, now: DateTime<Local>) {
+        match (self.condition, event) {
+            (AlertCondition::PowerAbove, Event::Reading(r)) => {
+                if let Measurement::InstantaneousPower(w) = r.measurement {
+                    self.power = Some(w);
+                    if w > self.limit {
+                        self.since.get_or_insert(r.timestamp);
+                        self.power_cleared = false;
+                    } else {
+                        self.since = None;
+                        self.power_cleared = w <= self.limit - self.hysteresis;
+                    }
+                }
+            }
+            (AlertCondition::NoReading, Event::Reading(r)) => self.since = Some(r.received_at),
+            (AlertCondition::SessionLost, Event::StateChanged { current, .. }) => match current {
+                ConnectionState::Lost => {
+                    self.since.get_or_insert(now);
+                }
+                ConnectionState::Connected => self.since = None,
+                ConnectionState::Disconnected | ConnectionState::Connecting => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// The alert to send if the rule started or stopped firing at `now`.
+    pub fn evaluate(&mut self, now: DateTime<Local>) -> Option<Alert> {
+        let held = self.since.is_some_and(|s| now - s >= self.duration);
+        let cleared = match self.condition {
+            AlertCondition::PowerAbove => self.power_cleared,
+            AlertCondition::NoReading => !held,
+            AlertCondition::SessionLost => self.since.is_none(),
+        };
+        if !self.firing && held {
+            self.firing = true;
+        } else if self.firing && cleared {
+            self.firing = false;
+        } else {
+            return None;
+        }
+        Some(Alert {
+            rule: self.name.clone(),
+            condition: self.condition.as_str(),
+            firing: self.firing,
+            timestamp: now,
+            message: self.message(),
+        })
+    }
+
+    fn message(&self) -> String {
+        let since = self.since.map(|s| s.to_rfc3339()).unwrap_or_default();
+        let power = self.power.unwrap_or_default();
+        match (self.condition, self.firing) {
+            (AlertCondition::PowerAbove, true) => format!("instantaneous power is {} W, above {} W since {}", power, self.limit, since),
+            (AlertCondition::PowerAbove, false) => format!("instantaneous power is {} W", power),
+            (AlertCondition::NoReading, true) => format!("nothing has been read since {}", since),
+            (AlertCondition::NoReading, false) => format!("read again at {}", since),
+            (AlertCondition::SessionLost, true) => format!("the PANA session has been lost since {}", since),
+            (AlertCondition::SessionLost, false) => "the PANA session has been established again".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::{received, time};
+
+    use super::*;
+
+    fn rule(condition: AlertCondition, limit: Option<i32>, hysteresis: i32, duration: u64) -> AlertRule {
+        AlertRule::new(&AlertRuleConfig { name: "rule".to_string(), condition, limit, hysteresis, duration }, time(0))
+    }
+
+    fn power(w: i32, timestamp: i64) -> Event {
+        received(Measurement::InstantaneousPower(w), time(timestamp), time(timestamp))
+    }
+
+    /// Feed the power readings to the rule, returning when it fired (true) or was resolved (false).
+    fn alerts(rule: &mut AlertRule, readings: &[(i32, i64)]) -> Vec<(bool, i64)> {
+        readings.iter()
+            .filter_map(|(w, t)| {
+                rule.observe(&power(*w, *t), time(*t));
+                rule.evaluate(time(*t)).map(|a| (a.firing, *t))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sustained_power() {
+        let mut rule = rule(AlertCondition::PowerAbove, Some(4000), 500, 60);
+        assert_eq!(vec![(true, 80)], alerts(&mut rule, &[(4100, 0), (3900, 10), (4100, 20), (4200, 50), (4100, 80)]));
+
+        let alert = rule.evaluate(time(90));
+        assert_eq!(None, alert);
+        rule.observe(&power(3000, 100), time(100));
+        let alert = rule.evaluate(time(100)).unwrap();
+        assert_eq!(json!({
+            "rule": "rule",
+            "condition": "power_above",
+            "status": "resolved",
+            "timestamp": time(100).to_rfc3339(),
+            "message": "instantaneous power is 3000 W",
+        }), alert.to_json());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut rule = rule(AlertCondition::PowerAbove, Some(4000), 500, 0);
+        assert_eq!(vec![(true, 0), (false, 40)], alerts(&mut rule, &[(4100, 0), (3900, 10), (4100, 20), (3600, 30), (3500, 40), (3900, 50)]));
+    }
+
+    #[test]
+    fn no_reading() {
+        let mut rule = rule(AlertCondition::NoReading, None, 0, 600);
+        assert_eq!(None, rule.evaluate(time(599)));
+        let alert = rule.evaluate(time(600)).unwrap();
+        assert!(alert.firing);
+        assert_eq!(format!("nothing has been read since {}", time(0).to_rfc3339()), alert.message);
+        assert_eq!(None, rule.evaluate(time(700)));
+        assert_eq!(vec![(false, 710)], alerts(&mut rule, &[(500, 710), (500, 720)]));
+    }
+
+    #[test]
+    fn no_reading_with_old_timestamp() {
+        let mut rule = rule(AlertCondition::NoReading, None, 0, 600);
+        rule.observe(&power(500, 500), time(500));
+        // A notified reading measured at the last half-hour boundary, received at 1000.
+        rule.observe(&received(Measurement::InstantaneousPower(500), time(-800), time(1000)), time(1000));
+        assert_eq!(None, rule.evaluate(time(1599)));
+        let alert = rule.evaluate(time(1600)).unwrap();
+        assert_eq!(format!("nothing has been read since {}", time(1000).to_rfc3339()), alert.message);
+    }
+
+    #[test]
+    fn session_lost() {
+        let mut rule = rule(AlertCondition::SessionLost, None, 0, 30);
+        let state = |previous, current| Event::StateChanged { previous, current };
+        rule.observe(&state(ConnectionState::Connecting, ConnectionState::Connected), time(0));
+        rule.observe(&state(ConnectionState::Connected, ConnectionState::Lost), time(10));
+        rule.observe(&state(ConnectionState::Lost, ConnectionState::Connecting), time(11));
+        assert_eq!(None, rule.evaluate(time(39)));
+        assert!(rule.evaluate(time(40)).unwrap().firing);
+        rule.observe(&state(ConnectionState::Connecting, ConnectionState::Connected), time(50));
+        assert!(!rule.evaluate(time(50)).unwrap().firing);
+    }
+}
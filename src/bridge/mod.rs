@@ -0,0 +1,4 @@
+mod node;
+mod server;
+
+pub use server::{read_properties, EchonetBridge};
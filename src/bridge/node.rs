@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::echonet::{EchonetObject, EchonetPacket, EchonetService, Edata, PropertyCode, PropertyMap, Property};
+
+/// Properties of the meter which never change, kept in the cache forever.
+const STATIC_PROPERTIES: [u8; 11] = [0x82, 0x83, 0x8A, 0x8C, 0x8D, 0x9D, 0x9E, 0x9F, 0xD3, 0xD7, 0xE1];
+/// Properties of the node profile which are read from the meter.
+const NODE_METER_PROPERTIES: [u8; 2] = [0x83, 0x8A];
+/// Only the instance list is announced by the node profile itself; the meter's notifications are forwarded as they are.
+const NODE_ANNOUNCED_PROPERTIES: [u8; 1] = [0xD5];
+const NODE_OPERATING: u8 = 0x30;
+/// ECHONET Lite version 1.13, type 1 message format only.
+const NODE_VERSION: [u8; 4] = [0x01, 0x0D, 0x01, 0x00];
+/// The smart meter is the only instance of this node.
+const INSTANCE_LIST: [u8; 4] = [0x01, 0x02, 0x88, 0x01];
+const CLASS_LIST: [u8; 3] = [0x01, 0x02, 0x88];
+
+/// Values of the meter read through the B-route, reused while they are fresh.
+struct PropertyCache {
+    entries: HashMap<u8, (Vec<u8>, Instant)>,
+    ttl: Duration,
+}
+
+impl PropertyCache {
+    fn get(&self, epc: u8, now: Instant) -> Option<&Vec<u8>> {
+        let (data, read_at) = self.entries.get(&epc)?;
+        if STATIC_PROPERTIES.contains(&epc) || now.saturating_duration_since(*read_at) < self.ttl {
+            return Some(data);
+        }
+        None
+    }
+
+    fn insert(&mut self, epc: u8, data: Vec<u8>, now: Instant) {
+        self.entries.insert(epc, (data, now));
+    }
+}
+
+/// The node seen by the controllers on the LAN: a node profile (0x0EF001) and the smart meter (0x028801).
+///
+/// Node profile properties are answered locally, except for the identification, which is the meter's.
+/// Properties of the meter come from the cache or are read through the B-route.
+pub struct VirtualNode {
+    cache: PropertyCache,
+}
+
+impl VirtualNode {
+    pub fn new(cache_ttl: Duration) -> Self {
+        VirtualNode {
+            cache: PropertyCache { entries: HashMap::new(), ttl: cache_ttl },
+        }
+    }
+
+    fn node_profile_property(&self, epc: u8) -> Option<Vec<u8>> {
+        match epc {
+            0x80 => Some(vec![NODE_OPERATING]),
+            0x82 => Some(NODE_VERSION.to_vec()),
+            0x9D => Some(PropertyMap::new(NODE_ANNOUNCED_PROPERTIES).dump()),
+            0x9E => Some(PropertyMap::new([]).dump()),
+            0x9F => Some(PropertyMap::new([0x80, 0x82, 0x83, 0x8A, 0x9D, 0x9E, 0x9F, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7]).dump()),
+            0xD3 => Some(vec![0x00, 0x00, 0x01]),
+            0xD4 => Some(vec![0x00, 0x02]),
+            0xD5 | 0xD6 => Some(INSTANCE_LIST.to_vec()),
+            0xD7 => Some(CLASS_LIST.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Answer a Get request (ESV 0x62) from the LAN, calling `read_meter` for the values of the meter which are not
+    /// cached. `read_meter` returns the properties it could read.
+    /// Set requests (ESV 0x60, 0x61) are refused, since no property of the node can be written.
+    ///
+    /// Returns `None` for requests which are not answered.
+    pub fn respond<F>(&mut self, request: &EchonetPacket<PropertyCode>, now: Instant, read_meter: F) -> Option<EchonetPacket<PropertyCode>>
+        where F: FnOnce(&[PropertyCode]) -> Vec<Property<PropertyCode>> {
+        let destination = request.data.destination_object;
+        let object = [EchonetObject::NODE_PROFILE, EchonetObject::SMART_METER].into_iter()
+            .find(|o| o.matches(&destination))?;
+        let refused = match request.data.echonet_service {
+            EchonetService::ReadPropertyRequest => None,
+            EchonetService::WritePropertyRequest => Some(EchonetService::WritePropertyFailResponse),
+            EchonetService::WritePropertyNoResponseRequest => Some(EchonetService::WritePropertyNoResponseFailResponse),
+            _ => return None,
+        };
+        if let Some(service) = refused {
+            // Every property is returned with the data of the request, which marks it as not written.
+            return Some(EchonetPacket::new(request.transaction_id, Edata {
+                source_object: object,
+                destination_object: request.data.source_object,
+                echonet_service: service,
+                properties: request.data.properties.clone(),
+                read_properties: Vec::new(),
+            }));
+        }
+
+        let mut values: Vec<Option<Vec<u8>>> = request.data.properties.iter()
+            .map(|p| match object {
+                EchonetObject::NODE_PROFILE if !NODE_METER_PROPERTIES.contains(&p.epc.0) => self.node_profile_property(p.epc.0),
+                _ => self.cache.get(p.epc.0, now).cloned(),
+            })
+            .collect();
+        let missing: Vec<PropertyCode> = request.data.properties.iter().zip(&values)
+            .filter(|(p, v)| v.is_none() && (object == EchonetObject::SMART_METER || NODE_METER_PROPERTIES.contains(&p.epc.0)))
+            .map(|(p, _)| p.epc)
+            .collect();
+        if !missing.is_empty() {
+            for p in read_meter(&missing) {
+                if !p.data.is_empty() {
+                    self.cache.insert(p.epc.0, p.data, now);
+                }
+            }
+            for (p, v) in request.data.properties.iter().zip(values.iter_mut()) {
+                if v.is_none() {
+                    *v = self.cache.get(p.epc.0, now).cloned();
+                }
+            }
+        }
+
+        let service = if values.iter().all(Option::is_some) {
+            EchonetService::ReadPropertyResponse
+        } else {
+            EchonetService::ReadPropertyFailResponse
+        };
+        let properties = request.data.properties.iter().zip(values)
+            .map(|(p, v)| Property { epc: p.epc, data: v.unwrap_or_default() })
+            .collect();
+        Some(EchonetPacket::new(request.transaction_id, Edata {
+            source_object: object,
+            destination_object: request.data.source_object,
+            echonet_service: service,
+            properties,
+            read_properties: Vec::new(),
+        }))
+    }
+
+    /// Announcement of the instance list (ESV 0x73), sent to the ECHONET Lite multicast group on startup.
+    pub fn instance_list_notification(&self) -> EchonetPacket<PropertyCode> {
+        EchonetPacket::new(0, Edata {
+            source_object: EchonetObject::NODE_PROFILE,
+            destination_object: EchonetObject::NODE_PROFILE,
+            echonet_service: EchonetService::PropertyNotification,
+            properties: vec![Property { epc: PropertyCode(0xD5), data: INSTANCE_LIST.to_vec() }],
+            read_properties: Vec::new(),
+        })
+    }
+}
+
+/// Notification (ESV 0x73) of properties notified by the smart meter, sent from the meter object of the node.
+pub fn meter_notification(properties: Vec<Property<PropertyCode>>) -> EchonetPacket<PropertyCode> {
+    EchonetPacket::new(0, Edata {
+        source_object: EchonetObject::SMART_METER,
+        destination_object: EchonetObject::NODE_PROFILE,
+        echonet_service: EchonetService::PropertyNotification,
+        properties,
+        read_properties: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn request(object: EchonetObject, epcs: &[u8]) -> EchonetPacket<PropertyCode> {
+        EchonetPacket::new(0x1234, Edata {
+            source_object: EchonetObject::HEMS_CONTROLLER,
+            destination_object: object,
+            echonet_service: EchonetService::ReadPropertyRequest,
+            properties: epcs.iter().map(|e| Property { epc: PropertyCode(*e), data: Vec::new() }).collect(),
+            read_properties: Vec::new(),
+        })
+    }
+
+    fn property(epc: u8, data: &[u8]) -> Property<PropertyCode> {
+        Property { epc: PropertyCode(epc), data: data.to_vec() }
+    }
+
+    /// Meter answering E7 and 8A, recording the requested properties.
+    fn meter(requested: &RefCell<Vec<Vec<u8>>>) -> impl FnOnce(&[PropertyCode]) -> Vec<Property<PropertyCode>> + '_ {
+        move |epcs| {
+            requested.borrow_mut().push(epcs.iter().map(|e| e.0).collect());
+            epcs.iter()
+                .filter_map(|e| match e.0 {
+                    0xE7 => Some(property(0xE7, &[0x00, 0x00, 0x02, 0x0E])),
+                    0x8A => Some(property(0x8A, &[0x00, 0x00, 0x16])),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn respond_node_profile() {
+        let requested = RefCell::new(Vec::new());
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        let response = node.respond(&request(EchonetObject::NODE_PROFILE, &[0xD6, 0x8A, 0x9F]), Instant::now(), meter(&requested)).unwrap();
+        assert_eq!(EchonetService::ReadPropertyResponse, response.data.echonet_service);
+        assert_eq!(EchonetObject::NODE_PROFILE, response.data.source_object);
+        assert_eq!(EchonetObject::HEMS_CONTROLLER, response.data.destination_object);
+        assert_eq!(0x1234, response.transaction_id);
+        assert_eq!(vec![
+            property(0xD6, &[0x01, 0x02, 0x88, 0x01]),
+            property(0x8A, &[0x00, 0x00, 0x16]),
+            property(0x9F, &[0x0C, 0x80, 0x82, 0x83, 0x8A, 0x9D, 0x9E, 0x9F, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7]),
+        ], response.data.properties);
+        assert_eq!(vec![vec![0x8A]], *requested.borrow());
+    }
+
+    #[test]
+    fn respond_from_cache() {
+        let requested = RefCell::new(Vec::new());
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let response = node.respond(&request(EchonetObject::SMART_METER, &[0xE7, 0x8A]), start, meter(&requested)).unwrap();
+        assert_eq!(vec![property(0xE7, &[0x00, 0x00, 0x02, 0x0E]), property(0x8A, &[0x00, 0x00, 0x16])], response.data.properties);
+
+        node.respond(&request(EchonetObject::SMART_METER, &[0xE7, 0x8A]), start + Duration::from_secs(5), meter(&requested)).unwrap();
+        node.respond(&request(EchonetObject::SMART_METER, &[0xE7, 0x8A]), start + Duration::from_secs(10), meter(&requested)).unwrap();
+        assert_eq!(vec![vec![0xE7, 0x8A], vec![0xE7]], *requested.borrow());
+    }
+
+    #[test]
+    fn respond_unavailable() {
+        let requested = RefCell::new(Vec::new());
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        let response = node.respond(&request(EchonetObject::SMART_METER, &[0xE7, 0xE8]), Instant::now(), meter(&requested)).unwrap();
+        assert_eq!(EchonetService::ReadPropertyFailResponse, response.data.echonet_service);
+        assert_eq!(vec![property(0xE7, &[0x00, 0x00, 0x02, 0x0E]), property(0xE8, &[])], response.data.properties);
+    }
+
+    #[test]
+    fn refuse_set_requests() {
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        for (service, expected) in [(EchonetService::WritePropertyRequest, EchonetService::WritePropertyFailResponse),
+                                    (EchonetService::WritePropertyNoResponseRequest, EchonetService::WritePropertyNoResponseFailResponse)] {
+            let mut packet = request(EchonetObject::SMART_METER, &[]);
+            packet.data.echonet_service = service;
+            packet.data.properties = vec![property(0xE5, &[0x01])];
+            let response = node.respond(&packet, Instant::now(), |_| panic!("meter read")).unwrap();
+            assert_eq!(expected, response.data.echonet_service);
+            assert_eq!(EchonetObject::SMART_METER, response.data.source_object);
+            assert_eq!(EchonetObject::HEMS_CONTROLLER, response.data.destination_object);
+            assert_eq!(vec![property(0xE5, &[0x01])], response.data.properties);
+        }
+    }
+
+    #[test]
+    fn ignore_other_requests() {
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        let mut packet = request(EchonetObject::SMART_METER, &[0xE7]);
+        packet.data.echonet_service = EchonetService::PropertyNotification;
+        assert_eq!(None, node.respond(&packet, Instant::now(), |_| panic!("meter read")));
+        assert_eq!(None, node.respond(&request(EchonetObject::HEMS_CONTROLLER, &[0x80]), Instant::now(), |_| panic!("meter read")));
+        assert_eq!(None, node.respond(&request(EchonetObject::SMART_METER.with_instance(2), &[0xE7]), Instant::now(), |_| panic!("meter read")));
+    }
+
+    #[test]
+    fn respond_to_every_instance() {
+        let mut node = VirtualNode::new(Duration::from_secs(10));
+        let response = node.respond(&request(EchonetObject::NODE_PROFILE.with_instance(0), &[0xD6]), Instant::now(), |_| panic!("meter read")).unwrap();
+        assert_eq!(EchonetObject::NODE_PROFILE, response.data.source_object);
+        assert_eq!(vec![property(0xD6, &[0x01, 0x02, 0x88, 0x01])], response.data.properties);
+    }
+}
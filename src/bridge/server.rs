@@ -0,0 +1,217 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bridge::node::{self, VirtualNode};
+use crate::config::BridgeConfig;
+use crate::echonet::{EchonetPacket, Property, PropertyCode};
+use crate::serial::Connection;
+use crate::wisun_module::{Notification, Result, WiSunClient};
+
+const ECHONET_PORT: u16 = 3610;
+const ECHONET_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 23, 0);
+/// How long a request from the LAN waits for the meter to be read.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PACKET_SIZE: usize = 1500;
+
+/// Properties of the meter requested by the bridge, read on the polling thread which owns the client.
+pub struct ProxyRequest {
+    properties: Vec<PropertyCode>,
+    reply: Sender<Vec<Property<PropertyCode>>>,
+}
+
+/// Bridges the smart meter to ECHONET Lite controllers on the LAN.
+///
+/// The bridge listens on UDP port 3610 and the ECHONET Lite multicast group, answering Get requests to the virtual
+/// node. Values which are not cached are requested from the polling thread, which has to call `serve_proxy_requests`
+/// regularly. The meter's notifications are announced through a `NotificationForwarder`.
+pub struct EchonetBridge {
+    requests: Receiver<ProxyRequest>,
+    socket: UdpSocket,
+    announce_to: SocketAddr,
+}
+
+/// Announces the properties notified by the meter to the LAN, to be subscribed to the client's notifications.
+pub struct NotificationForwarder {
+    socket: UdpSocket,
+    announce_to: SocketAddr,
+}
+
+impl NotificationForwarder {
+    pub fn forward(&self, notification: &Notification) {
+        // The decoded energies are notified as raw properties too, which are forwarded as they are.
+        if let Notification::Property(p) = notification {
+            if let Err(e) = self.socket.send_to(&node::meter_notification(vec![p.clone()]).dump(), self.announce_to) {
+                log::warn!("failed to forward the notification of {:?}: {}", p.epc, e);
+            }
+        }
+    }
+}
+
+impl EchonetBridge {
+    pub fn start(config: &BridgeConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, ECHONET_PORT))?;
+        socket.join_multicast_v4(&ECHONET_MULTICAST_ADDRESS, &config.interface)?;
+        let multicast = SocketAddr::V4(SocketAddrV4::new(ECHONET_MULTICAST_ADDRESS, ECHONET_PORT));
+        EchonetBridge::start_with_socket(socket, multicast, Duration::from_secs(config.cache_ttl))
+    }
+
+    fn start_with_socket(socket: UdpSocket, announce_to: SocketAddr, cache_ttl: Duration) -> io::Result<Self> {
+        let node = VirtualNode::new(cache_ttl);
+        socket.send_to(&node.instance_list_notification().dump(), announce_to)?;
+
+        let (sender, requests) = mpsc::channel();
+        let server_socket = socket.try_clone()?;
+        thread::spawn(move || serve(server_socket, node, sender));
+        Ok(EchonetBridge { requests, socket, announce_to })
+    }
+
+    pub fn notification_forwarder(&self) -> io::Result<NotificationForwarder> {
+        Ok(NotificationForwarder { socket: self.socket.try_clone()?, announce_to: self.announce_to })
+    }
+
+    /// Read the properties requested by the bridge with `read`, without waiting for new requests.
+    pub fn serve_proxy_requests<F>(&self, mut read: F)
+        where F: FnMut(&[PropertyCode]) -> Result<Vec<Property<PropertyCode>>> {
+        for request in self.requests.try_iter() {
+            let properties = match read(&request.properties) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("failed to read {:?} for the bridge: {:?}", request.properties, e);
+                    Vec::new()
+                }
+            };
+            let _ = request.reply.send(properties);
+        }
+    }
+}
+
+/// Read the properties of the meter requested through the bridge, leaving out the ones it does not have.
+pub fn read_properties<T: Connection>(client: &mut WiSunClient<T>, properties: &[PropertyCode]) -> Result<Vec<Property<PropertyCode>>> {
+    let supported: Vec<PropertyCode> = match client.property_map() {
+        Some(map) => properties.iter().filter(|p| map.get_property_ids().contains(&p.0)).copied().collect(),
+        None => properties.to_vec(),
+    };
+    if supported.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(client.get_properties(&supported)?.data.properties)
+}
+
+fn serve(socket: UdpSocket, mut node: VirtualNode, proxy: Sender<ProxyRequest>) {
+    let mut buffer = [0u8; MAX_PACKET_SIZE];
+    loop {
+        let (size, source) = match socket.recv_from(&mut buffer) {
+            Ok(r) => r,
+            Err(e) => {
+                log::warn!("failed to receive from the LAN: {}", e);
+                continue;
+            }
+        };
+        let request = match EchonetPacket::<PropertyCode>::parse(&buffer[..size]) {
+            Ok(p) => p,
+            Err(e) => {
+                log::debug!("ignoring a packet from {}: {:?}", source, e);
+                continue;
+            }
+        };
+        let response = node.respond(&request, Instant::now(), |properties| {
+            let (reply, response) = mpsc::channel();
+            if proxy.send(ProxyRequest { properties: properties.to_vec(), reply }).is_err() {
+                return Vec::new();
+            }
+            response.recv_timeout(PROXY_TIMEOUT).unwrap_or_default()
+        });
+        if let Some(r) = response {
+            if let Err(e) = socket.send_to(&r.dump(), source) {
+                log::warn!("failed to respond to {}: {}", source, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::echonet::{EchonetObject, EchonetService, Edata, PropertyCode};
+    use crate::wisun_module::emulator::ModuleEmulator;
+
+    use super::*;
+
+    fn local_socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> EchonetPacket<PropertyCode> {
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        let size = socket.recv(&mut buffer).unwrap();
+        EchonetPacket::parse(&buffer[..size]).unwrap()
+    }
+
+    #[test]
+    fn read_supported_properties() {
+        let mut client = WiSunClient::new(ModuleEmulator::default()).unwrap();
+        client.connect("00112233445566778899AABBCCDDEEFF", "0123456789AB").unwrap();
+        let properties = read_properties(&mut client, &[PropertyCode(0x8A), PropertyCode(0xF5)]).unwrap();
+        assert_eq!(vec![PropertyCode(0x8A)], properties.iter().map(|p| p.epc).collect::<Vec<PropertyCode>>());
+        assert!(read_properties(&mut client, &[PropertyCode(0xF5)]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn answer_through_proxy() {
+        let controller = local_socket();
+        let bridge_socket = local_socket();
+        let bridge_addr = bridge_socket.local_addr().unwrap();
+        let bridge = EchonetBridge::start_with_socket(bridge_socket, controller.local_addr().unwrap(), Duration::from_secs(10)).unwrap();
+
+        let announcement = receive(&controller);
+        assert_eq!(EchonetService::PropertyNotification, announcement.data.echonet_service);
+        assert_eq!(PropertyCode(0xD5), announcement.data.properties[0].epc);
+
+        let request = EchonetPacket::new(0x0102, Edata {
+            source_object: EchonetObject::HEMS_CONTROLLER,
+            destination_object: EchonetObject::SMART_METER,
+            echonet_service: EchonetService::ReadPropertyRequest,
+            properties: vec![Property { epc: PropertyCode(0xE7), data: Vec::new() }],
+            read_properties: Vec::new(),
+        });
+        controller.send_to(&request.dump(), bridge_addr).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut served = Vec::new();
+        while served.is_empty() {
+            assert!(Instant::now() < deadline, "timed out");
+            bridge.serve_proxy_requests(|properties| {
+                served.push(properties.to_vec());
+                Ok(vec![Property { epc: PropertyCode(0xE7), data: vec![0x00, 0x00, 0x02, 0x0E] }])
+            });
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec![vec![PropertyCode(0xE7)]], served);
+
+        let response = receive(&controller);
+        assert_eq!(0x0102, response.transaction_id);
+        assert_eq!(EchonetService::ReadPropertyResponse, response.data.echonet_service);
+        assert_eq!(vec![Property { epc: PropertyCode(0xE7), data: vec![0x00, 0x00, 0x02, 0x0E] }], response.data.properties);
+    }
+
+    #[test]
+    fn forward_notifications() {
+        let controller = local_socket();
+        let bridge = EchonetBridge::start_with_socket(local_socket(), controller.local_addr().unwrap(), Duration::from_secs(10)).unwrap();
+        receive(&controller);
+
+        let forwarder = bridge.notification_forwarder().unwrap();
+        let data = hex::decode("07E703011200000001E240").unwrap();
+        forwarder.forward(&Notification::Property(Property { epc: PropertyCode(0xEA), data: data.clone() }));
+
+        let notification = receive(&controller);
+        assert_eq!(EchonetService::PropertyNotification, notification.data.echonet_service);
+        assert_eq!(EchonetObject::SMART_METER, notification.data.source_object);
+        assert_eq!(EchonetObject::NODE_PROFILE, notification.data.destination_object);
+        assert_eq!(vec![Property { epc: PropertyCode(0xEA), data }], notification.data.properties);
+    }
+}
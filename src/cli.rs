@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, FixedOffset};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::store::Quantity;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Reads a low-voltage smart meter over the Wi-SUN B-route")]
+pub struct Cli {
+    /// TOML configuration file. Without it, the settings are read from the environment variables.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// List every PAN found by an active scan.
+    Scan {
+        /// SKSCAN duration; each channel is scanned for 0.01 * (2^duration + 1) seconds.
+        #[arg(short, long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(0..=14))]
+        duration: u8,
+    },
+    /// Print the module version and the values of the properties the meter supports.
+    Info,
+    /// Read the given properties once and print their values.
+    Read {
+        #[arg(required = true, value_enum)]
+        properties: Vec<ReadProperty>,
+
+        /// Days before today for the energy logs.
+        #[arg(short, long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=99))]
+        day: u8,
+    },
+    /// Poll the meter periodically. This is the default when no command is given.
+    Monitor,
+    /// Print the readings and the connection events recorded in the store.
+    History {
+        /// power, energy, reverse_energy, current_r or current_t. Every quantity is printed when omitted.
+        #[arg(short, long, value_parser = parse_quantity)]
+        quantity: Option<Quantity>,
+
+        /// Start of the range in RFC 3339, such as 2024-01-01T00:00:00+09:00. Defaults to a day before `to`.
+        #[arg(long)]
+        from: Option<DateTime<FixedOffset>>,
+
+        /// End of the range in RFC 3339. Defaults to now.
+        #[arg(long)]
+        to: Option<DateTime<FixedOffset>>,
+    },
+}
+
+fn parse_quantity(s: &str) -> Result<Quantity, String> {
+    s.parse().map_err(|_| format!("unknown quantity {}", s))
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum ReadProperty {
+    /// Instantaneous electric power (0xE7)
+    Power,
+    /// Instantaneous current per phase (0xE8)
+    Current,
+    /// Normal direction cumulative electric energy (0xE0)
+    Energy,
+    /// Reverse direction cumulative electric energy (0xE3)
+    ReverseEnergy,
+    /// Normal direction cumulative electric energy log (0xE2)
+    EnergyLog,
+    /// Reverse direction cumulative electric energy log (0xE4)
+    ReverseEnergyLog,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_default() {
+        let cli = Cli::try_parse_from(["smart_meter_receiver"]).unwrap();
+        assert_eq!(None, cli.config);
+        assert_eq!(None, cli.command);
+    }
+
+    #[test]
+    fn parse_scan() {
+        let cli = Cli::try_parse_from(["smart_meter_receiver", "-c", "receiver.toml", "scan", "--duration", "8"]).unwrap();
+        assert_eq!(Some(PathBuf::from("receiver.toml")), cli.config);
+        assert_eq!(Some(Command::Scan { duration: 8 }), cli.command);
+        assert!(Cli::try_parse_from(["smart_meter_receiver", "scan", "--duration", "15"]).is_err());
+    }
+
+    #[test]
+    fn parse_read() {
+        let cli = Cli::try_parse_from(["smart_meter_receiver", "read", "power", "reverse-energy-log", "--day", "1"]).unwrap();
+        assert_eq!(Some(Command::Read { properties: vec![ReadProperty::Power, ReadProperty::ReverseEnergyLog], day: 1 }), cli.command);
+        assert!(Cli::try_parse_from(["smart_meter_receiver", "read"]).is_err());
+        assert!(Cli::try_parse_from(["smart_meter_receiver", "read", "voltage"]).is_err());
+    }
+
+    #[test]
+    fn parse_history() {
+        let cli = Cli::try_parse_from(["smart_meter_receiver", "history", "-q", "reverse_energy",
+            "--from", "2024-01-01T00:00:00+09:00"]).unwrap();
+        assert_eq!(Some(Command::History {
+            quantity: Some(Quantity::ReverseEnergy),
+            from: Some(DateTime::parse_from_rfc3339("2024-01-01T00:00:00+09:00").unwrap()),
+            to: None,
+        }), cli.command);
+        assert!(Cli::try_parse_from(["smart_meter_receiver", "history", "-q", "voltage"]).is_err());
+        assert!(Cli::try_parse_from(["smart_meter_receiver", "history", "--from", "yesterday"]).is_err());
+    }
+}
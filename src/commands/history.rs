@@ -0,0 +1,59 @@
+use chrono::{DateTime, Duration, FixedOffset, Local};
+
+use crate::config::Config;
+use crate::store::{self, Database, Quantity};
+use crate::wisun_module::{Error, Result};
+
+pub fn history(config: &Config, quantity: Option<Quantity>,
+               from: Option<DateTime<FixedOffset>>, to: Option<DateTime<FixedOffset>>) -> Result<()> {
+    let store = config.store.as_ref()
+        .ok_or_else(|| Error::CommandError("no store is configured".to_string()))?;
+    let to = to.map(|t| t.with_timezone(&Local)).unwrap_or_else(Local::now);
+    let from = from.map(|t| t.with_timezone(&Local)).unwrap_or(to - Duration::days(1));
+    let text = Database::open(&store.path)
+        .and_then(|db| describe(&db, quantity, from, to))
+        .map_err(|e| Error::CommandError(format!("failed to read {}: {}", store.path.display(), e)))?;
+    print!("{}", text);
+    Ok(())
+}
+
+fn describe(database: &Database, quantity: Option<Quantity>,
+            from: DateTime<Local>, to: DateTime<Local>) -> store::Result<String> {
+    let mut text = String::new();
+    for s in database.samples(quantity, from, to, None)? {
+        text += &format!("{} {} {}\n", s.timestamp.to_rfc3339(), s.quantity.as_str(), s.value);
+    }
+    if quantity.is_none() {
+        for e in database.connection_events(from, to)? {
+            text += &format!("{} connection {}\n", e.timestamp.to_rfc3339(), e.kind.as_str());
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use crate::store::{ConnectionEvent, ConnectionEventKind, Sample};
+
+    use super::*;
+
+    #[test]
+    fn describe_history() {
+        let time = |t| Local.timestamp_opt(t, 0).unwrap();
+        let mut database = Database::open_in_memory().unwrap();
+        database.insert_samples(&[
+            Sample { timestamp: time(1700000000), quantity: Quantity::Power, value: 526.0 },
+            Sample { timestamp: time(1700000060), quantity: Quantity::Energy, value: 12345.6 },
+        ]).unwrap();
+        database.insert_connection_event(&ConnectionEvent { timestamp: time(1699999990), kind: ConnectionEventKind::Join }).unwrap();
+
+        let text = describe(&database, None, time(1699999900), time(1700000100)).unwrap();
+        assert_eq!(format!("{} power 526\n{} energy 12345.6\n{} connection join\n",
+                           time(1700000000).to_rfc3339(), time(1700000060).to_rfc3339(), time(1699999990).to_rfc3339()),
+                   text);
+        let text = describe(&database, Some(Quantity::Energy), time(1699999900), time(1700000100)).unwrap();
+        assert_eq!(format!("{} energy 12345.6\n", time(1700000060).to_rfc3339()), text);
+    }
+}
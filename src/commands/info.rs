@@ -0,0 +1,129 @@
+use crate::config::Config;
+use crate::echonet::mra::{ClassSchema, Coefficient};
+use crate::echonet::{smart_meter_property_name, EchonetObject, PropertyCode};
+use crate::serial::Connection;
+use crate::wisun_module::{ReadResult, Result, WiSunClient};
+
+pub fn info(config: &Config) -> Result<()> {
+    let mut client = super::connect(config)?;
+    print!("{}", describe(&mut client)?);
+    Ok(())
+}
+
+/// Self-node instance list S (0xD6) of the node profile.
+const INSTANCE_LIST: PropertyCode = PropertyCode(0xD6);
+/// Properties read with one Get request, keeping the response well within a UDP frame of the module.
+const PROPERTIES_PER_REQUEST: usize = 4;
+
+fn describe<T: Connection>(client: &mut WiSunClient<T>) -> Result<String> {
+    let mut text = format!("Version: {}\n", client.get_version()?);
+    let instances = client.get_object_properties(EchonetObject::NODE_PROFILE.with_instance(0), &[INSTANCE_LIST])?;
+    let objects: Vec<String> = match instances.get_property(INSTANCE_LIST) {
+        Some(p) if !p.data.is_empty() => p.data[1..].chunks_exact(3)
+            .map(|o| EchonetObject::new(o[0], o[1], o[2]).to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+    text += &format!("Objects: {}\nProperties:\n", objects.join(" "));
+    if client.property_map().is_none() {
+        client.get_property_map()?;
+    }
+    let mut epcs: Vec<u8> = match client.property_map() {
+        Some(m) => m.get_property_ids().iter().copied().collect(),
+        None => Vec::new(),
+    };
+    epcs.sort_unstable();
+    let codes: Vec<PropertyCode> = epcs.iter().map(|e| PropertyCode(*e)).collect();
+    text += &describe_properties(client, &codes);
+    Ok(text)
+}
+
+/// One line per property of `codes`. A request which fails, such as one timing out, is reported on the lines of its
+/// properties, and the others are still read.
+fn describe_properties<T: Connection>(client: &mut WiSunClient<T>, codes: &[PropertyCode]) -> String {
+    let mut results = Vec::with_capacity(codes.len());
+    for chunk in codes.chunks(PROPERTIES_PER_REQUEST) {
+        match client.read_properties(chunk) {
+            Ok(r) => results.extend(r.into_iter().map(|r| (u8::from(r.epc()), Ok(r)))),
+            Err(e) => results.extend(chunk.iter().map(|c| (c.0, Err(e.to_string())))),
+        }
+    }
+    // The values of every property, for the coefficients of the others.
+    let values: Vec<(u8, &[u8])> = results.iter()
+        .filter_map(|(epc, r)| match r {
+            Ok(ReadResult::Value(p)) => Some((*epc, p.data.as_slice())),
+            _ => None,
+        })
+        .collect();
+    results.iter()
+        .map(|(epc, result)| {
+            let name = smart_meter_property_name(*epc).unwrap_or_else(|| "unknown".to_string());
+            let value = match result {
+                Ok(r) => describe_value(r, &values),
+                Err(e) => e.clone(),
+            };
+            format!("  0x{:02X} {}: {}\n", epc, name, value)
+        })
+        .collect()
+}
+
+/// The value decoded with the schema of the smart meter and multiplied by its coefficient, or its raw data when it
+/// has no schema. Numbers whose coefficient properties are not in `values` are shown as they are.
+fn describe_value(result: &ReadResult<PropertyCode>, values: &[(u8, &[u8])]) -> String {
+    let property = match result {
+        ReadResult::Value(p) => p,
+        ReadResult::Refused(_) => return "refused".to_string(),
+    };
+    let schema = ClassSchema::smart_meter();
+    let epc = u8::from(property.epc);
+    let coefficient = match schema.property(epc) {
+        Some(p) => p.coefficient.iter()
+            .map(|c| values.iter().find(|(e, _)| e == c).copied())
+            .collect::<Option<Vec<_>>>(),
+        None => return hex::encode_upper(&property.data),
+    };
+    let coefficient = match coefficient {
+        Some(c) => schema.coefficient(&c),
+        None => Ok(Coefficient::ONE),
+    };
+    match coefficient.and_then(|c| schema.decode_scaled(epc, &property.data, &c)) {
+        Ok(v) if v.is_no_data() => "no data".to_string(),
+        Ok(v) => v.to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::wisun_module::emulator::ModuleEmulator;
+
+    use super::*;
+
+    #[test]
+    fn describe_meter() {
+        let mut client = WiSunClient::new(ModuleEmulator::default()).unwrap();
+        client.serial_connection_mut().meter.set_property(0xF0, &[0x00]);
+        client.connect("00112233445566778899AABBCCDDEEFF", "0123456789AB").unwrap();
+        let text = describe(&mut client).unwrap();
+        assert!(text.starts_with("Version: 1.2.10\nObjects: 0x028801\nProperties:\n  0x80 OperationStatus: on\n"), "{}", text);
+        assert!(text.contains("  0xE7 InstantaneousElectricPower: 526 W\n  0xE8 InstantaneousCurrent: rPhase: 5 A, tPhase: 2 A\n"), "{}", text);
+        assert!(text.contains("  0xE1 UnitForCumulativeElectricEnergy: 0.1kWh\n"), "{}", text);
+        assert!(text.ends_with("  0xF0 unknown: 00\n"), "{}", text);
+        assert!(text.contains("  0xE0 NormalDirectionCumulativeElectricEnergy: 12345.6 kWh\n"), "{}", text);
+    }
+
+    #[test]
+    fn describe_after_timeout() {
+        let mut client = WiSunClient::new(ModuleEmulator::default()).unwrap();
+        client.connect("00112233445566778899AABBCCDDEEFF", "0123456789AB").unwrap();
+        client.set_response_timeout(Duration::from_millis(10));
+        client.serial_connection_mut().meter.set_silent(true);
+        let codes: Vec<PropertyCode> = [0x80, 0x8A, 0xD3, 0xE0, 0xE1].iter().map(|e| PropertyCode(*e)).collect();
+        let text = describe_properties(&mut client, &codes);
+        assert_eq!(5, text.lines().count(), "{}", text);
+        assert!(text.starts_with("  0x80 OperationStatus: timeout\n"), "{}", text);
+        assert!(text.ends_with("  0xE1 UnitForCumulativeElectricEnergy: timeout\n"), "{}", text);
+    }
+}
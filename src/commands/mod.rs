@@ -0,0 +1,36 @@
+mod history;
+mod info;
+mod monitor;
+mod read;
+mod scan;
+
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::serial::{self, Connection};
+use crate::wisun_module::{Result, WiSunClient};
+
+pub use history::history;
+pub use info::info;
+pub use monitor::monitor;
+pub use read::read;
+pub use scan::scan;
+
+fn open_client(config: &Config) -> Result<WiSunClient<impl Connection>> {
+    let mut client = WiSunClient::new(serial::new(&config.serial.device, config.serial.baud_rate)?)?;
+    client.set_response_timeout(Duration::from_secs(config.wisun.response_timeout));
+    Ok(client)
+}
+
+fn connect(config: &Config) -> Result<WiSunClient<impl Connection>> {
+    let mut client = open_client(config)?;
+    if let Some(path) = &config.wisun.scan_cache {
+        client.set_pan_cache(path);
+    }
+    if config.wisun.rescan {
+        client.connect_with_scan(&config.wisun.bid, &config.wisun.password)?;
+    } else {
+        client.connect(&config.wisun.bid, &config.wisun.password)?;
+    }
+    Ok(client)
+}
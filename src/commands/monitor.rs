@@ -0,0 +1,227 @@
+use std::sync::mpsc::{self, Receiver};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, TimeZone};
+
+use crate::alert::BreakerMonitor;
+use crate::bridge::{self, EchonetBridge};
+use crate::config::{Config, PollingConfig};
+use crate::output::{Event, HistoryRecorder, HttpApi, InfluxDbWriter, LogSink, Measurement, MeterInfo, ModbusServer, MqttPublisher, Outputs, PrometheusExporter, Reading, WebhookNotifier};
+use crate::schedule::Schedule;
+use crate::serial;
+use crate::wisun_module::{Error, Notification, Result, Supervisor};
+
+/// Longest wait for notifications while the bridge may have requests to the meter.
+const BRIDGE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum PolledValue {
+    InstantaneousPower,
+    InstantaneousCurrent,
+    CumulativeEnergy,
+    ReverseCumulativeEnergy,
+    /// Both cumulative energies, read with one request when they are due together.
+    CumulativeEnergies,
+}
+
+/// The values to read, combining the two cumulative energies when both are due.
+fn combine_energies(mut due: Vec<PolledValue>) -> Vec<PolledValue> {
+    if due.contains(&PolledValue::CumulativeEnergy) && due.contains(&PolledValue::ReverseCumulativeEnergy) {
+        due.retain(|v| !matches!(v, PolledValue::CumulativeEnergy | PolledValue::ReverseCumulativeEnergy));
+        due.push(PolledValue::CumulativeEnergies);
+    }
+    due
+}
+
+fn polling_schedule(config: &PollingConfig) -> Schedule<PolledValue> {
+    let tasks = [
+        (PolledValue::InstantaneousPower, config.instantaneous_power),
+        (PolledValue::InstantaneousCurrent, config.instantaneous_current),
+        (PolledValue::CumulativeEnergy, config.cumulative_energy),
+        (PolledValue::ReverseCumulativeEnergy, config.reverse_cumulative_energy),
+    ];
+    Schedule::new(tasks.into_iter()
+                      .filter_map(|(r, s)| PollingConfig::interval(s).map(|i| (r, i)))
+                      .collect(), Instant::now())
+}
+
+fn outputs(config: &Config) -> Result<Outputs> {
+    let mut outputs = Outputs::default();
+    if config.output.log.enabled {
+        outputs.add(LogSink);
+    }
+    if let Some(c) = &config.output.prometheus {
+        let exporter = PrometheusExporter::start(&c.listen)
+            .map_err(|e| Error::CommandError(format!("failed to listen on {}: {}", c.listen, e)))?;
+        if let Some(addr) = exporter.local_addr() {
+            log::info!("serving metrics on http://{}/metrics", addr);
+        }
+        outputs.add(exporter);
+    }
+    if let Some(c) = &config.output.api {
+        let api = HttpApi::start(&c.listen, config.store.as_ref().map(|s| s.path.clone()))
+            .map_err(|e| Error::CommandError(format!("failed to listen on {}: {}", c.listen, e)))?;
+        if let Some(addr) = api.local_addr() {
+            log::info!("serving the API on http://{}/api/v1/", addr);
+        }
+        outputs.add(api);
+    }
+    if let Some(c) = &config.output.modbus {
+        let server = ModbusServer::start(c.listen.as_str())
+            .map_err(|e| Error::CommandError(format!("failed to listen on {}: {}", c.listen, e)))?;
+        log::info!("serving Modbus TCP on {}", server.local_addr());
+        outputs.add(server);
+    }
+    if let Some(c) = &config.output.mqtt {
+        outputs.add(MqttPublisher::start(c));
+    }
+    if let Some(c) = &config.store {
+        let recorder = HistoryRecorder::start(c)
+            .map_err(|e| Error::CommandError(format!("failed to open {}: {}", c.path.display(), e)))?;
+        outputs.add(recorder);
+    }
+    if let Some(c) = &config.output.influxdb {
+        let writer = InfluxDbWriter::start(c, &config.wisun.bid)
+            .map_err(|e| Error::CommandError(format!("failed to set up the InfluxDB output: {}", e)))?;
+        outputs.add(writer);
+    }
+    if let Some(c) = &config.alert {
+        outputs.add(WebhookNotifier::start(c));
+    }
+    Ok(outputs)
+}
+
+fn notified_reading(notification: &Notification) -> Option<Reading> {
+    let (entry, measurement): (_, fn(f64) -> Measurement) = match notification {
+        Notification::NormalDirectionCumulativeEnergy(e) => (e, Measurement::CumulativeEnergy),
+        Notification::ReverseDirectionCumulativeEnergy(e) => (e, Measurement::ReverseCumulativeEnergy),
+        Notification::Property(p) => {
+            log::debug!("notified property: {:?}", p);
+            return None;
+        }
+    };
+    Some(Reading {
+        timestamp: Local.from_local_datetime(&entry.timestamp).earliest()?,
+        received_at: Local::now(),
+        measurement: measurement(entry.energy?),
+    })
+}
+
+/// Pass the queued events to the outputs, followed by the breaker warnings they raise.
+fn dispatch(events: &Receiver<Event>, outputs: &mut Outputs, breaker: &mut Option<BreakerMonitor>) {
+    for event in events.try_iter() {
+        outputs.dispatch(&event);
+        if let Some(b) = breaker {
+            for alert in b.observe(&event) {
+                outputs.dispatch(&Event::Alert(alert));
+            }
+        }
+    }
+}
+
+/// Poll the smart meter forever, reconnecting whenever the session is lost, and pass the readings to the outputs.
+pub fn monitor(config: &Config) -> Result<()> {
+    let mut outputs = outputs(config)?;
+    let mut breaker = config.breaker.as_ref().map(BreakerMonitor::new);
+    let bridge = match &config.bridge {
+        Some(c) => Some(EchonetBridge::start(c)
+            .map_err(|e| Error::CommandError(format!("failed to start the ECHONET Lite bridge: {}", e)))?),
+        None => None,
+    };
+    let serial_config = config.serial.clone();
+    let mut supervisor = Supervisor::new(move || serial::new(&serial_config.device, serial_config.baud_rate),
+                                         &config.wisun.bid, &config.wisun.password)?;
+    if let Some(path) = &config.wisun.scan_cache {
+        supervisor.client().set_pan_cache(path);
+    }
+    supervisor.set_rescan(config.wisun.rescan);
+    supervisor.set_backoff(Duration::from_secs(config.wisun.initial_backoff), Duration::from_secs(config.wisun.max_backoff));
+    supervisor.set_max_consecutive_timeouts(config.wisun.max_consecutive_timeouts);
+    supervisor.client().set_response_timeout(Duration::from_secs(config.wisun.response_timeout));
+    let version = supervisor.client().get_version()?;
+    log::info!("Version: {}", version);
+
+    // The handlers run inside the supervisor, so their events are queued and dispatched from the loop below.
+    let (sender, events) = mpsc::channel();
+    let s = sender.clone();
+    supervisor.subscribe_state_changes(move |previous, current| {
+        let _ = s.send(Event::StateChanged { previous, current });
+    });
+    let s = sender.clone();
+    supervisor.client().subscribe_scans(move |pan| {
+        let _ = s.send(Event::Scanned { channel: pan.channel, pan_id: pan.pan_id });
+    });
+    let s = sender.clone();
+    supervisor.client().subscribe_notifications(move |n| {
+        if let Some(r) = notified_reading(n) {
+            let _ = s.send(Event::Reading(r));
+        }
+    });
+
+    if let Some(b) = &bridge {
+        let forwarder = b.notification_forwarder()
+            .map_err(|e| Error::CommandError(format!("failed to forward notifications to the bridge: {}", e)))?;
+        supervisor.client().subscribe_notifications(move |n| forwarder.forward(n));
+    }
+
+    let mut schedule = polling_schedule(&config.polling);
+    let mut identified = false;
+    loop {
+        if !identified {
+            match supervisor.run(|c| c.get_identification()) {
+                Ok(identification) => {
+                    identified = true;
+                    let _ = sender.send(Event::Identified(MeterInfo { identification, module_version: version.clone() }));
+                }
+                Err(e) => {
+                    log::warn!("failed to identify the meter: {:?}", e);
+                }
+            }
+        }
+        for value in combine_energies(schedule.take_due(Instant::now())) {
+            let result = match value {
+                PolledValue::InstantaneousPower => supervisor.run(|c| c.get_power_consumption())
+                    .map(|w| vec![Measurement::InstantaneousPower(w)]),
+                PolledValue::InstantaneousCurrent => supervisor.run(|c| c.get_instantaneous_current())
+                    .map(|c| vec![Measurement::InstantaneousCurrent(c)]),
+                PolledValue::CumulativeEnergy => supervisor.run(|c| c.get_cumulative_electric_energy())
+                    .map(|e| vec![Measurement::CumulativeEnergy(e)]),
+                PolledValue::ReverseCumulativeEnergy => supervisor.run(|c| c.get_reverse_cumulative_electric_energy())
+                    .map(|e| vec![Measurement::ReverseCumulativeEnergy(e)]),
+                PolledValue::CumulativeEnergies => supervisor.run(|c| c.get_cumulative_electric_energies())
+                    .map(|e| vec![Measurement::CumulativeEnergy(e.normal_direction),
+                                  Measurement::ReverseCumulativeEnergy(e.reverse_direction)]),
+            };
+            match result {
+                Ok(measurements) => {
+                    let timestamp = Local::now();
+                    for measurement in measurements {
+                        let _ = sender.send(Event::Reading(Reading { timestamp, received_at: timestamp, measurement }));
+                    }
+                }
+                Err(e) => {
+                    log::warn!("failed to retrieve {:?}: {:?}", value, e);
+                    let _ = sender.send(Event::ReadError(e.variant_name()));
+                }
+            }
+        }
+        dispatch(&events, &mut outputs, &mut breaker);
+
+        if let Some(b) = &bridge {
+            b.serve_proxy_requests(|properties| supervisor.run(|c| bridge::read_properties(c, properties)));
+        }
+
+        let mut wait = schedule.next_due()
+            .map(|t| t.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::from_secs(10));
+        if bridge.is_some() {
+            wait = wait.min(BRIDGE_POLL_INTERVAL);
+        }
+        if let Err(e) = supervisor.run(|c| c.poll_notifications(wait)) {
+            log::warn!("failed to receive notifications: {:?}", e);
+            sleep(wait);
+        }
+        dispatch(&events, &mut outputs, &mut breaker);
+    }
+}
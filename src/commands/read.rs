@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+
+use crate::cli::ReadProperty;
+use crate::config::Config;
+use crate::serial::Connection;
+use crate::wisun_module::{Result, WiSunClient};
+
+pub fn read(config: &Config, properties: &[ReadProperty], day: u8) -> Result<()> {
+    let mut client = super::connect(config)?;
+    for property in properties {
+        print!("{}", read_property(&mut client, *property, day)?);
+    }
+    Ok(())
+}
+
+fn read_property<T: Connection>(client: &mut WiSunClient<T>, property: ReadProperty, day: u8) -> Result<String> {
+    let name = property.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default();
+    let value = match property {
+        ReadProperty::Power => format!("{} W\n", client.get_power_consumption()?),
+        ReadProperty::Current => format!("{}\n", client.get_instantaneous_current()?),
+        ReadProperty::Energy => format!("{} kWh\n", client.get_cumulative_electric_energy()?),
+        ReadProperty::ReverseEnergy => format!("{} kWh\n", client.get_reverse_cumulative_electric_energy()?),
+        ReadProperty::EnergyLog | ReadProperty::ReverseEnergyLog => {
+            let log = match property {
+                ReadProperty::EnergyLog => client.get_cumulative_electric_energy_log(day)?,
+                _ => client.get_reverse_cumulative_electric_energy_log(day)?,
+            };
+            let mut text = "\n".to_string();
+            for entry in log {
+                match entry.energy {
+                    Some(e) => text += &format!("  {} {} kWh\n", entry.timestamp, e),
+                    None => text += &format!("  {} no data\n", entry.timestamp),
+                }
+            }
+            text
+        }
+    };
+    Ok(format!("{}: {}", name, value))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Days, Local};
+
+    use crate::wisun_module::emulator::ModuleEmulator;
+
+    use super::*;
+
+    fn connected_client() -> WiSunClient<ModuleEmulator> {
+        let mut client = WiSunClient::new(ModuleEmulator::default()).unwrap();
+        client.connect("00112233445566778899AABBCCDDEEFF", "0123456789AB").unwrap();
+        client
+    }
+
+    #[test]
+    fn read_values() {
+        let mut client = connected_client();
+        assert_eq!("power: 526 W\n", read_property(&mut client, ReadProperty::Power, 0).unwrap());
+        assert_eq!("energy: 12345.6 kWh\n", read_property(&mut client, ReadProperty::Energy, 0).unwrap());
+        assert_eq!("reverse-energy: 1234.5 kWh\n", read_property(&mut client, ReadProperty::ReverseEnergy, 0).unwrap());
+        assert_eq!("current: R: 5.0 A, T: 2.0 A\n", read_property(&mut client, ReadProperty::Current, 0).unwrap());
+    }
+
+    #[test]
+    fn read_energy_log() {
+        let mut client = connected_client();
+        let mut values = vec![0xFFFFFFFE; 48];
+        values[0] = 123456;
+        client.serial_connection_mut().meter.set_energy_log(0xE2, 1, &values);
+        let text = read_property(&mut client, ReadProperty::EnergyLog, 1).unwrap();
+        let yesterday = Local::now().date_naive().checked_sub_days(Days::new(1)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(49, lines.len());
+        assert_eq!("energy-log: ", lines[0]);
+        assert_eq!(format!("  {} 00:00:00 12345.6 kWh", yesterday), lines[1]);
+        assert_eq!(format!("  {} 00:30:00 no data", yesterday), lines[2]);
+    }
+}
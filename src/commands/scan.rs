@@ -0,0 +1,54 @@
+use crate::config::Config;
+use crate::parser::event::PanDescBody;
+use crate::serial::Connection;
+use crate::wisun_module::{Result, WiSunClient};
+
+pub fn scan(config: &Config, duration: u8) -> Result<()> {
+    let mut client = super::open_client(config)?;
+    print!("{}", scan_pans(&mut client, &config.wisun.bid, duration)?);
+    Ok(())
+}
+
+fn scan_pans<T: Connection>(client: &mut WiSunClient<T>, bid: &str, duration: u8) -> Result<String> {
+    let pans = client.scan_pans(bid, duration)?;
+    if pans.is_empty() {
+        return Ok("no PAN found\n".to_string());
+    }
+    Ok(format_pans(&pans))
+}
+
+fn format_pans(pans: &[PanDescBody]) -> String {
+    let mut text = "CHANNEL  PAN ID  ADDRESS           LQI\n".to_string();
+    for pan in pans {
+        text += &format!("{:<7}  {:04X}    {:<16}  {}\n", format!("{:02X}", pan.channel), pan.pan_id, hex::encode_upper(pan.addr), pan.lqi);
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use crate::wisun_module::emulator::ModuleEmulator;
+
+    use super::*;
+
+    const BID: &str = "00112233445566778899AABBCCDDEEFF";
+
+    #[test]
+    fn list_pans() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.add_neighbour_pan(0x21, 0x1234, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77], 0x2A);
+        let mut client = WiSunClient::new(emulator).unwrap();
+        assert_eq!("CHANNEL  PAN ID  ADDRESS           LQI\n\
+                    2F       3077    C0F9450040213077  115\n\
+                    21       1234    0011223344556677  42\n",
+                   scan_pans(&mut client, BID, 6).unwrap());
+    }
+
+    #[test]
+    fn no_pan() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.set_scan_duration_required(0x0E);
+        let mut client = WiSunClient::new(emulator).unwrap();
+        assert_eq!("no PAN found\n", scan_pans(&mut client, BID, 6).unwrap());
+    }
+}
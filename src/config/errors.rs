@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use thiserror::Error as ThisError;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("failed to read {path}: {source}")]
+    IoError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("invalid configuration file {path}: {source}")]
+    ParseError {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("invalid value for {key}: {message}")]
+    InvalidValueError {
+        key: String,
+        message: String,
+    },
+
+    #[error("{0} must be specified")]
+    MissingValueError(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
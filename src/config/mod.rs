@@ -0,0 +1,6 @@
+mod errors;
+mod settings;
+
+pub use settings::{AlertCondition, AlertConfig, AlertRuleConfig, BreakerConfig, BridgeConfig, Config, InfluxDbOutputConfig, LogConfig, MqttOutputConfig, PollingConfig, StoreConfig};
+#[cfg(test)]
+pub use settings::{InfluxDbFileConfig, InfluxDbHttpConfig};
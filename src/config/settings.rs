@@ -0,0 +1,1101 @@
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::config::errors::{Error, Result};
+
+const BAUD_RATES: [u32; 7] = [2400, 4800, 9600, 19200, 38400, 57600, 115200];
+const BID_LENGTH: usize = 32;
+const MAX_PASSWORD_LENGTH: usize = 32;
+
+/// Settings of the receiver, read from a TOML file:
+///
+/// ```toml
+/// [serial]
+/// device = "/dev/ttyUSB0"
+/// baud_rate = 115200
+///
+/// [wisun]
+/// bid = "00112233445566778899AABBCCDDEEFF"
+/// password_file = "/etc/smart-meter-receiver/password"
+/// scan_cache = "/var/lib/smart-meter-receiver/pan"
+/// response_timeout = 20
+/// max_backoff = 600
+///
+/// [polling]
+/// instantaneous_power = 10
+/// cumulative_energy = 60
+///
+/// [log]
+/// level = "info"
+///
+/// [output.log]
+/// enabled = true
+///
+/// [output.prometheus]
+/// listen = "0.0.0.0:9464"
+///
+/// [output.api]
+/// listen = "0.0.0.0:8080"
+///
+/// [output.modbus]
+/// listen = "0.0.0.0:502"
+///
+/// [output.mqtt]
+/// host = "localhost"
+/// base_topic = "smart_meter"
+///
+/// [output.mqtt.home_assistant]
+/// discovery_prefix = "homeassistant"
+///
+/// [store]
+/// path = "/var/lib/smart-meter-receiver/history.db"
+/// raw_days = 7
+///
+/// [bridge]
+/// interface = "192.168.1.10"
+///
+/// [breaker]
+/// contract_ampere = 40
+///
+/// [alert]
+/// webhooks = ["https://example.com/hooks/smart-meter"]
+///
+/// [[alert.rules]]
+/// name = "high_power"
+/// condition = "power_above"
+/// limit = 4000
+/// hysteresis = 500
+/// duration = 60
+///
+/// [output.influxdb]
+/// measurement = "smart_meter"
+///
+/// [output.influxdb.http]
+/// url = "http://localhost:8086"
+/// org = "home"
+/// bucket = "smart_meter"
+/// token = "..."
+/// ```
+///
+/// Every value is optional in the file, and the environment variables below take precedence over it.
+///
+/// | variable              | value                  |
+/// |-----------------------|------------------------|
+/// | `SERIAL_PORT`         | `serial.device`        |
+/// | `SERIAL_BAUD_RATE`    | `serial.baud_rate`     |
+/// | `WISUN_BID`           | `wisun.bid`            |
+/// | `WISUN_PASSWORD`      | `wisun.password`       |
+/// | `WISUN_PASSWORD_FILE` | `wisun.password_file`  |
+/// | `WISUN_SCAN_CACHE`    | `wisun.scan_cache`     |
+/// | `LOG_LEVEL`           | `log.level`            |
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub serial: SerialConfig,
+    pub wisun: WiSunConfig,
+    pub polling: PollingConfig,
+    pub log: LogConfig,
+    pub output: OutputConfig,
+    pub store: Option<StoreConfig>,
+    pub bridge: Option<BridgeConfig>,
+    pub alert: Option<AlertConfig>,
+    pub breaker: Option<BreakerConfig>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub device: String,
+    pub baud_rate: u32,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            device: "/dev/ttyS0".to_string(),
+            baud_rate: 115200,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WiSunConfig {
+    /// Route B ID issued by the electric power company.
+    pub bid: String,
+    /// Route B password. Filled from `password_file` when that is given instead.
+    pub password: String,
+    pub password_file: Option<PathBuf>,
+    /// File remembering the PAN found by the last scan.
+    pub scan_cache: Option<PathBuf>,
+    /// Scan for the PAN on every reconnection.
+    pub rescan: bool,
+    /// Seconds to wait for the smart meter to answer a request.
+    pub response_timeout: u64,
+    /// Seconds to wait after the first failed connection attempt, doubled after each further failure.
+    pub initial_backoff: u64,
+    /// Upper limit of the wait between connection attempts in seconds.
+    pub max_backoff: u64,
+    /// Number of timeouts in a row after which the session is considered lost.
+    pub max_consecutive_timeouts: u32,
+}
+
+impl Default for WiSunConfig {
+    fn default() -> Self {
+        WiSunConfig {
+            bid: String::new(),
+            password: String::new(),
+            password_file: None,
+            scan_cache: None,
+            rescan: false,
+            response_timeout: 20,
+            initial_backoff: 5,
+            max_backoff: 600,
+            max_consecutive_timeouts: 3,
+        }
+    }
+}
+
+/// Seconds between reads of each property. 0 disables polling of the property.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollingConfig {
+    pub instantaneous_power: u64,
+    pub instantaneous_current: u64,
+    pub cumulative_energy: u64,
+    pub reverse_cumulative_energy: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            instantaneous_power: 10,
+            instantaneous_current: 0,
+            cumulative_energy: 60,
+            reverse_cumulative_energy: 0,
+        }
+    }
+}
+
+impl PollingConfig {
+    pub fn interval(seconds: u64) -> Option<Duration> {
+        match seconds {
+            0 => None,
+            s => Some(Duration::from_secs(s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    /// Also write the log to this file.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LevelFilter::Info,
+            file: None,
+        }
+    }
+}
+
+/// Keeps the history of the readings and the connection events in a local SQLite database.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub path: PathBuf,
+    /// Days for which every sample is kept as it was read.
+    pub raw_days: u64,
+    /// Seconds covered by one sample after `raw_days`. 0 keeps every sample.
+    pub downsample_interval: u64,
+    /// Days after which the history is deleted. 0 keeps it forever.
+    pub retention_days: u64,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            path: PathBuf::from("/var/lib/smart-meter-receiver/history.db"),
+            raw_days: 7,
+            downsample_interval: 900,
+            retention_days: 0,
+        }
+    }
+}
+
+/// Exposes the meter to ECHONET Lite controllers on the LAN.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// Address of the interface joining the multicast group. The default lets the system choose.
+    pub interface: Ipv4Addr,
+    /// Seconds for which values read from the meter answer further requests.
+    pub cache_ttl: u64,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        BridgeConfig {
+            interface: Ipv4Addr::UNSPECIFIED,
+            cache_ttl: 10,
+        }
+    }
+}
+
+/// Warns before the breaker of the contract current trips, from the instantaneous current of each phase.
+/// The warnings are sent to the outputs as alerts.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerConfig {
+    /// Contract current in A, such as 30, 40 or 60.
+    pub contract_ampere: f64,
+    /// Percentage of the contract current above which a phase raises a warning.
+    pub warning_percent: f64,
+    /// Amperes below the warning current to which a phase has to fall before the warning is resolved.
+    pub hysteresis: f64,
+    /// Seconds of readings from which the trend of the current is estimated.
+    pub trend_window: u64,
+    /// Seconds ahead in which a rising current reaching the contract current raises a warning.
+    pub prediction_horizon: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            contract_ampere: 0.0,
+            warning_percent: 90.0,
+            hysteresis: 2.0,
+            trend_window: 300,
+            prediction_horizon: 300,
+        }
+    }
+}
+
+/// Rules raising alerts, which are posted as JSON to every webhook.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    pub webhooks: Vec<String>,
+    /// Attempts to deliver an alert to each webhook before it is given up.
+    pub max_attempts: u32,
+    pub rules: Vec<AlertRuleConfig>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            webhooks: Vec::new(),
+            max_attempts: 5,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// An alert raised when `condition` has held for `duration` seconds.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRuleConfig {
+    pub name: String,
+    pub condition: AlertCondition,
+    /// Watts above which `power_above` holds.
+    pub limit: Option<i32>,
+    /// Watts below `limit` to which the power has to fall before a `power_above` alert is resolved.
+    #[serde(default)]
+    pub hysteresis: i32,
+    #[serde(default)]
+    pub duration: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The instantaneous power is above `limit`.
+    PowerAbove,
+    /// Nothing has been read successfully.
+    NoReading,
+    /// The PANA session has been lost and not established again.
+    SessionLost,
+}
+
+impl AlertCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertCondition::PowerAbove => "power_above",
+            AlertCondition::NoReading => "no_reading",
+            AlertCondition::SessionLost => "session_lost",
+        }
+    }
+}
+
+/// Where the readings are sent to. Optional outputs are enabled by adding their section.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub log: LogOutputConfig,
+    pub prometheus: Option<PrometheusOutputConfig>,
+    pub api: Option<ApiOutputConfig>,
+    pub modbus: Option<ModbusOutputConfig>,
+    pub mqtt: Option<MqttOutputConfig>,
+    pub influxdb: Option<InfluxDbOutputConfig>,
+}
+
+/// Writes every reading to the log.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogOutputConfig {
+    pub enabled: bool,
+}
+
+impl Default for LogOutputConfig {
+    fn default() -> Self {
+        LogOutputConfig { enabled: true }
+    }
+}
+
+/// Serves the metrics on `http://<listen>/metrics`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PrometheusOutputConfig {
+    pub listen: String,
+}
+
+/// Serves the latest readings, and the history when the store is enabled, as JSON on `http://<listen>/api/v1/`.
+/// New readings and connection state changes are streamed as Server-Sent Events on `/api/v1/stream`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiOutputConfig {
+    pub listen: String,
+}
+
+/// Serves the latest readings as Modbus TCP registers on `<listen>`, laid out as documented on `ModbusServer`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusOutputConfig {
+    pub listen: String,
+}
+
+/// Publishes the readings to an MQTT broker.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttOutputConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of the topics which are not configured explicitly.
+    pub base_topic: String,
+    pub topics: MqttTopicsConfig,
+    pub qos: u8,
+    /// Number of messages kept while the broker is unreachable.
+    pub queue_size: usize,
+    pub home_assistant: Option<HomeAssistantConfig>,
+}
+
+impl Default for MqttOutputConfig {
+    fn default() -> Self {
+        MqttOutputConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "smart-meter-receiver".to_string(),
+            username: None,
+            password: None,
+            base_topic: "smart_meter".to_string(),
+            topics: MqttTopicsConfig::default(),
+            qos: 1,
+            queue_size: 1000,
+            home_assistant: None,
+        }
+    }
+}
+
+/// Publishes Home Assistant MQTT discovery payloads for the meter.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    pub discovery_prefix: String,
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        HomeAssistantConfig { discovery_prefix: "homeassistant".to_string() }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttTopicsConfig {
+    pub power: Option<String>,
+    pub current: Option<String>,
+    pub energy: Option<String>,
+    pub reverse_energy: Option<String>,
+    pub connection: Option<String>,
+    pub status: Option<String>,
+    pub alert: Option<String>,
+}
+
+/// Writes the readings as InfluxDB line protocol to one of `http`, `udp` or `file`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxDbOutputConfig {
+    pub measurement: String,
+    /// Number of lines written at once.
+    pub batch_size: usize,
+    /// Seconds to wait before writing an incomplete batch.
+    pub flush_interval: u64,
+    /// Number of lines kept while the target is unreachable.
+    pub max_buffered_lines: usize,
+    pub http: Option<InfluxDbHttpConfig>,
+    pub udp: Option<InfluxDbUdpConfig>,
+    pub file: Option<InfluxDbFileConfig>,
+}
+
+impl Default for InfluxDbOutputConfig {
+    fn default() -> Self {
+        InfluxDbOutputConfig {
+            measurement: "smart_meter".to_string(),
+            batch_size: 100,
+            flush_interval: 10,
+            max_buffered_lines: 10000,
+            http: None,
+            udp: None,
+            file: None,
+        }
+    }
+}
+
+/// InfluxDB v2 write API.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbHttpConfig {
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+}
+
+/// InfluxDB UDP listener, such as `localhost:8089`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbUdpConfig {
+    pub address: String,
+}
+
+/// Local file the lines are appended to.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbFileConfig {
+    pub path: PathBuf,
+}
+
+impl Config {
+    /// Read the configuration from `path`, or start from the defaults when no file is given,
+    /// then apply the environment variables and check the result.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        Config::load_with_env(path, |key| env::var(key).ok())
+    }
+
+    fn load_with_env<E>(path: Option<&Path>, env: E) -> Result<Config>
+        where E: Fn(&str) -> Option<String> {
+        let mut config = match path {
+            Some(p) => Config::read(p)?,
+            None => Config::default(),
+        };
+        config.apply_env(env)?;
+        config.read_password_file()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::IoError { path: path.to_path_buf(), source: e })?;
+        let config: Config = toml::from_str(&text)
+            .map_err(|e| Error::ParseError { path: path.to_path_buf(), source: e })?;
+        if !config.wisun.password.is_empty() && config.wisun.password_file.is_some() {
+            return Err(invalid_value("wisun.password", "only one of password and password_file can be specified"));
+        }
+        Ok(config)
+    }
+
+    fn apply_env<E>(&mut self, env: E) -> Result<()>
+        where E: Fn(&str) -> Option<String> {
+        if let Some(v) = env("SERIAL_PORT") {
+            self.serial.device = v;
+        }
+        if let Some(v) = env("SERIAL_BAUD_RATE") {
+            self.serial.baud_rate = v.parse()
+                .map_err(|_| invalid_value("SERIAL_BAUD_RATE", &format!("{} is not a number", v)))?;
+        }
+        if let Some(v) = env("WISUN_BID") {
+            self.wisun.bid = v;
+        }
+        if let Some(v) = env("WISUN_PASSWORD") {
+            self.wisun.password = v;
+            self.wisun.password_file = None;
+        }
+        if let Some(v) = env("WISUN_PASSWORD_FILE") {
+            self.wisun.password = String::new();
+            self.wisun.password_file = Some(PathBuf::from(v));
+        }
+        if let Some(v) = env("WISUN_SCAN_CACHE") {
+            self.wisun.scan_cache = Some(PathBuf::from(v));
+        }
+        if let Some(v) = env("LOG_LEVEL") {
+            self.log.level = LevelFilter::from_str(&v)
+                .map_err(|_| invalid_value("LOG_LEVEL", &format!("unknown level {}", v)))?;
+        }
+        Ok(())
+    }
+
+    fn read_password_file(&mut self) -> Result<()> {
+        if let Some(path) = &self.wisun.password_file {
+            let text = fs::read_to_string(path)
+                .map_err(|e| Error::IoError { path: path.clone(), source: e })?;
+            self.wisun.password = text.trim_end_matches(['\r', '\n']).to_string();
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.serial.device.is_empty() {
+            return Err(Error::MissingValueError("serial.device".to_string()));
+        }
+        if !BAUD_RATES.contains(&self.serial.baud_rate) {
+            return Err(invalid_value("serial.baud_rate", &format!("{} is not one of {:?}", self.serial.baud_rate, BAUD_RATES)));
+        }
+
+        let bid = &self.wisun.bid;
+        if bid.is_empty() {
+            return Err(Error::MissingValueError("wisun.bid".to_string()));
+        }
+        if bid.len() != BID_LENGTH || !bid.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid_value("wisun.bid", &format!("expected {} alphanumeric characters", BID_LENGTH)));
+        }
+
+        let password = &self.wisun.password;
+        if password.is_empty() {
+            return Err(Error::MissingValueError("wisun.password or wisun.password_file".to_string()));
+        }
+        if password.len() > MAX_PASSWORD_LENGTH || !password.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(invalid_value("wisun.password", &format!("expected at most {} printable ASCII characters", MAX_PASSWORD_LENGTH)));
+        }
+
+        if self.wisun.response_timeout == 0 {
+            return Err(invalid_value("wisun.response_timeout", "must be at least 1"));
+        }
+        if self.wisun.initial_backoff == 0 {
+            return Err(invalid_value("wisun.initial_backoff", "must be at least 1"));
+        }
+        if self.wisun.max_backoff < self.wisun.initial_backoff {
+            return Err(invalid_value("wisun.max_backoff", "must be at least initial_backoff"));
+        }
+        if self.wisun.max_consecutive_timeouts == 0 {
+            return Err(invalid_value("wisun.max_consecutive_timeouts", "must be at least 1"));
+        }
+
+        if let Some(prometheus) = &self.output.prometheus {
+            validate_listen_address("output.prometheus.listen", &prometheus.listen)?;
+        }
+        if let Some(api) = &self.output.api {
+            validate_listen_address("output.api.listen", &api.listen)?;
+        }
+        if let Some(modbus) = &self.output.modbus {
+            validate_listen_address("output.modbus.listen", &modbus.listen)?;
+        }
+        if let Some(mqtt) = &self.output.mqtt {
+            if mqtt.host.is_empty() {
+                return Err(Error::MissingValueError("output.mqtt.host".to_string()));
+            }
+            if mqtt.qos > 2 {
+                return Err(invalid_value("output.mqtt.qos", &format!("{} is not 0, 1 or 2", mqtt.qos)));
+            }
+            if mqtt.queue_size == 0 {
+                return Err(invalid_value("output.mqtt.queue_size", "must be at least 1"));
+            }
+            if mqtt.password.is_some() && mqtt.username.is_none() {
+                return Err(invalid_value("output.mqtt.password", "username must be specified with the password"));
+            }
+        }
+        if let Some(store) = &self.store {
+            if store.retention_days != 0 && store.retention_days < store.raw_days {
+                return Err(invalid_value("store.retention_days", "must be 0 or at least raw_days"));
+            }
+        }
+        if let Some(influxdb) = &self.output.influxdb {
+            let targets = [influxdb.http.is_some(), influxdb.udp.is_some(), influxdb.file.is_some()];
+            match targets.iter().filter(|t| **t).count() {
+                0 => return Err(Error::MissingValueError("output.influxdb.http, udp or file".to_string())),
+                1 => {}
+                _ => return Err(invalid_value("output.influxdb", "only one of http, udp and file can be specified")),
+            }
+            if influxdb.batch_size == 0 {
+                return Err(invalid_value("output.influxdb.batch_size", "must be at least 1"));
+            }
+            if influxdb.max_buffered_lines < influxdb.batch_size {
+                return Err(invalid_value("output.influxdb.max_buffered_lines", "must be at least batch_size"));
+            }
+            if influxdb.flush_interval == 0 {
+                return Err(invalid_value("output.influxdb.flush_interval", "must be at least 1"));
+            }
+        }
+        if let Some(alert) = &self.alert {
+            if alert.webhooks.is_empty() {
+                return Err(Error::MissingValueError("alert.webhooks".to_string()));
+            }
+            if alert.max_attempts == 0 {
+                return Err(invalid_value("alert.max_attempts", "must be at least 1"));
+            }
+            for (i, rule) in alert.rules.iter().enumerate() {
+                let key = |name: &str| format!("alert.rules[{}].{}", i, name);
+                if rule.name.is_empty() {
+                    return Err(Error::MissingValueError(key("name")));
+                }
+                if alert.rules[..i].iter().any(|r| r.name == rule.name) {
+                    return Err(invalid_value(&key("name"), &format!("{} is used by another rule", rule.name)));
+                }
+                match (rule.condition, rule.limit) {
+                    (AlertCondition::PowerAbove, None) => return Err(Error::MissingValueError(key("limit"))),
+                    (AlertCondition::PowerAbove, Some(_)) => {}
+                    (_, Some(_)) => return Err(invalid_value(&key("limit"), "only power_above has a limit")),
+                    (_, None) => {}
+                }
+                if rule.hysteresis < 0 {
+                    return Err(invalid_value(&key("hysteresis"), "must not be negative"));
+                }
+                if rule.condition == AlertCondition::NoReading && rule.duration == 0 {
+                    return Err(invalid_value(&key("duration"), "must be at least 1 for no_reading"));
+                }
+            }
+        }
+        if let Some(breaker) = &self.breaker {
+            if breaker.contract_ampere <= 0.0 {
+                return Err(Error::MissingValueError("breaker.contract_ampere".to_string()));
+            }
+            if breaker.warning_percent <= 0.0 || breaker.warning_percent > 100.0 {
+                return Err(invalid_value("breaker.warning_percent", "must be above 0 and at most 100"));
+            }
+            if breaker.hysteresis < 0.0 {
+                return Err(invalid_value("breaker.hysteresis", "must not be negative"));
+            }
+            if breaker.trend_window == 0 {
+                return Err(invalid_value("breaker.trend_window", "must be at least 1"));
+            }
+            if self.polling.instantaneous_current == 0 {
+                return Err(invalid_value("polling.instantaneous_current", "must be polled for the breaker warning"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_listen_address(key: &str, address: &str) -> Result<()> {
+    match address.parse::<SocketAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(invalid_value(key, &format!("{} is not an address and port such as 0.0.0.0:9464", address))),
+    }
+}
+
+fn invalid_value(key: &str, message: &str) -> Error {
+    Error::InvalidValueError {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::test_util::temp_path;
+
+    use super::*;
+
+    const BID: &str = "00112233445566778899AABBCCDDEEFF";
+
+    fn write_config(name: &str, text: &str) -> PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(path: Option<&Path>, vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::load_with_env(path, |key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn load_file() {
+        let path = write_config("load_file.toml", r#"
+[serial]
+device = "/dev/ttyUSB0"
+baud_rate = 9600
+
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+scan_cache = "/var/lib/smart-meter-receiver/pan"
+response_timeout = 30
+max_backoff = 300
+
+[polling]
+instantaneous_power = 5
+instantaneous_current = 30
+
+[log]
+level = "debug"
+
+[output.log]
+enabled = false
+
+[output.prometheus]
+listen = "127.0.0.1:9464"
+
+[output.modbus]
+listen = "127.0.0.1:5020"
+
+[output.mqtt]
+host = "broker.local"
+base_topic = "home/meter"
+
+[output.mqtt.topics]
+power = "home/power"
+
+[output.mqtt.home_assistant]
+
+[store]
+path = "/tmp/history.db"
+
+[bridge]
+interface = "192.168.1.10"
+"#);
+        let config = load(Some(&path), &[]).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("/dev/ttyUSB0", config.serial.device);
+        assert_eq!(9600, config.serial.baud_rate);
+        assert_eq!(BID, config.wisun.bid);
+        assert_eq!("0123456789AB", config.wisun.password);
+        assert_eq!(Some(PathBuf::from("/var/lib/smart-meter-receiver/pan")), config.wisun.scan_cache);
+        assert_eq!(30, config.wisun.response_timeout);
+        assert_eq!(5, config.wisun.initial_backoff);
+        assert_eq!(300, config.wisun.max_backoff);
+        assert_eq!(3, config.wisun.max_consecutive_timeouts);
+        assert_eq!(5, config.polling.instantaneous_power);
+        assert_eq!(30, config.polling.instantaneous_current);
+        assert_eq!(60, config.polling.cumulative_energy);
+        assert_eq!(LevelFilter::Debug, config.log.level);
+        assert!(!config.output.log.enabled);
+        assert_eq!(Some(PrometheusOutputConfig { listen: "127.0.0.1:9464".to_string() }), config.output.prometheus);
+        assert_eq!(Some(ModbusOutputConfig { listen: "127.0.0.1:5020".to_string() }), config.output.modbus);
+        let mqtt = config.output.mqtt.unwrap();
+        assert_eq!("broker.local", mqtt.host);
+        assert_eq!(1883, mqtt.port);
+        assert_eq!("home/meter", mqtt.base_topic);
+        assert_eq!(Some("home/power".to_string()), mqtt.topics.power);
+        assert_eq!(None, mqtt.topics.energy);
+        assert_eq!(Some(HomeAssistantConfig::default()), mqtt.home_assistant);
+        let store = config.store.unwrap();
+        assert_eq!(PathBuf::from("/tmp/history.db"), store.path);
+        assert_eq!(7, store.raw_days);
+        assert_eq!(Some(BridgeConfig { interface: Ipv4Addr::new(192, 168, 1, 10), cache_ttl: 10 }), config.bridge);
+    }
+
+    #[test]
+    fn load_env_only() {
+        let config = load(None, &[("WISUN_BID", BID), ("WISUN_PASSWORD", "0123456789AB")]).unwrap();
+        assert_eq!(SerialConfig::default(), config.serial);
+        assert_eq!(PollingConfig::default(), config.polling);
+        assert_eq!(LevelFilter::Info, config.log.level);
+        assert!(config.output.log.enabled);
+        assert_eq!(None, config.output.prometheus);
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let path = write_config("env_overrides_file.toml", r#"
+[serial]
+device = "/dev/ttyUSB0"
+
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[log]
+level = "warn"
+"#);
+        let config = load(Some(&path), &[
+            ("SERIAL_PORT", "/dev/ttyAMA0"),
+            ("SERIAL_BAUD_RATE", "38400"),
+            ("WISUN_PASSWORD", "BA9876543210"),
+            ("LOG_LEVEL", "TRACE"),
+        ]).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("/dev/ttyAMA0", config.serial.device);
+        assert_eq!(38400, config.serial.baud_rate);
+        assert_eq!("BA9876543210", config.wisun.password);
+        assert_eq!(LevelFilter::Trace, config.log.level);
+    }
+
+    #[test]
+    fn password_file() {
+        let password = write_config("password", "0123456789AB\n");
+        let config = load(None, &[
+            ("WISUN_BID", BID),
+            ("WISUN_PASSWORD_FILE", password.to_str().unwrap()),
+        ]).unwrap();
+        fs::remove_file(&password).unwrap();
+        assert_eq!("0123456789AB", config.wisun.password);
+    }
+
+    #[test]
+    fn missing_password_file() {
+        let result = load(None, &[
+            ("WISUN_BID", BID),
+            ("WISUN_PASSWORD_FILE", temp_path("no_such_password").to_str().unwrap()),
+        ]);
+        assert!(matches!(result, Err(Error::IoError { .. })));
+    }
+
+    #[test]
+    fn both_password_and_file() {
+        let path = write_config("both_password_and_file.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+password_file = "/etc/smart-meter-receiver/password"
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("wisun.password", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn missing_credentials() {
+        match load(None, &[]) {
+            Err(Error::MissingValueError(key)) => assert_eq!("wisun.bid", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match load(None, &[("WISUN_BID", BID)]) {
+            Err(Error::MissingValueError(key)) => assert_eq!("wisun.password or wisun.password_file", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_values() {
+        let cases: [(&[(&str, &str)], &str); 5] = [
+            (&[("WISUN_BID", "0011"), ("WISUN_PASSWORD", "0123456789AB")], "wisun.bid"),
+            (&[("WISUN_BID", BID), ("WISUN_PASSWORD", "0123 456789AB")], "wisun.password"),
+            (&[("WISUN_BID", BID), ("WISUN_PASSWORD", "0123456789AB"), ("SERIAL_BAUD_RATE", "fast")], "SERIAL_BAUD_RATE"),
+            (&[("WISUN_BID", BID), ("WISUN_PASSWORD", "0123456789AB"), ("SERIAL_BAUD_RATE", "12345")], "serial.baud_rate"),
+            (&[("WISUN_BID", BID), ("WISUN_PASSWORD", "0123456789AB"), ("LOG_LEVEL", "verbose")], "LOG_LEVEL"),
+        ];
+        for (vars, expected) in cases {
+            match load(None, vars) {
+                Err(Error::InvalidValueError { key, .. }) => assert_eq!(expected, key),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_key() {
+        let path = write_config("unknown_key.toml", r#"
+[serial]
+device = "/dev/ttyUSB0"
+baudrate = 9600
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(e @ Error::ParseError { .. }) => assert!(e.to_string().contains("baudrate"), "{}", e),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_listen_address() {
+        let path = write_config("invalid_listen_address.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[output.prometheus]
+listen = "9464"
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("output.prometheus.listen", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_backoff() {
+        let path = write_config("invalid_backoff.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+initial_backoff = 60
+max_backoff = 30
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("wisun.max_backoff", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_mqtt_qos() {
+        let path = write_config("invalid_mqtt_qos.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[output.mqtt]
+qos = 3
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("output.mqtt.qos", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn influxdb_targets() {
+        let path = write_config("influxdb_targets.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[output.influxdb.udp]
+address = "localhost:8089"
+"#);
+        let config = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        let influxdb = config.unwrap().output.influxdb.unwrap();
+        assert_eq!("smart_meter", influxdb.measurement);
+        assert_eq!(Some(InfluxDbUdpConfig { address: "localhost:8089".to_string() }), influxdb.udp);
+
+        let path = write_config("influxdb_two_targets.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[output.influxdb.udp]
+address = "localhost:8089"
+
+[output.influxdb.file]
+path = "/var/lib/smart-meter-receiver/lines"
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("output.influxdb", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn alert_rules() {
+        let path = write_config("alert_rules.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[alert]
+webhooks = ["http://localhost:8000/hook"]
+
+[[alert.rules]]
+name = "high_power"
+condition = "power_above"
+limit = 4000
+hysteresis = 500
+duration = 60
+
+[[alert.rules]]
+name = "session_lost"
+condition = "session_lost"
+"#);
+        let config = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        let alert = config.unwrap().alert.unwrap();
+        assert_eq!(5, alert.max_attempts);
+        assert_eq!(vec![
+            AlertRuleConfig { name: "high_power".to_string(), condition: AlertCondition::PowerAbove, limit: Some(4000), hysteresis: 500, duration: 60 },
+            AlertRuleConfig { name: "session_lost".to_string(), condition: AlertCondition::SessionLost, limit: None, hysteresis: 0, duration: 0 },
+        ], alert.rules);
+
+        let path = write_config("alert_rules_without_limit.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[alert]
+webhooks = ["http://localhost:8000/hook"]
+
+[[alert.rules]]
+name = "high_power"
+condition = "power_above"
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::MissingValueError(key)) => assert_eq!("alert.rules[0].limit", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn breaker_needs_current() {
+        let path = write_config("breaker_needs_current.toml", r#"
+[wisun]
+bid = "00112233445566778899AABBCCDDEEFF"
+password = "0123456789AB"
+
+[breaker]
+contract_ampere = 40
+"#);
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InvalidValueError { key, .. }) => assert_eq!("polling.instantaneous_current", key),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_log_level() {
+        let path = write_config("invalid_log_level.toml", "[log]\nlevel = \"verbose\"\n");
+        let result = load(Some(&path), &[]);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::ParseError { .. })));
+    }
+
+    #[test]
+    fn polling_interval() {
+        assert_eq!(None, PollingConfig::interval(0));
+        assert_eq!(Some(Duration::from_secs(10)), PollingConfig::interval(10));
+    }
+}
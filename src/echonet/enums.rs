@@ -1,6 +1,6 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
-use crate::echonet::{Error, Result};
+use crate::echonet::Error;
 
 pub trait EchonetProperty: Copy + Clone + PartialEq + Debug + Sized + TryFromPrimitive<Primitive=u8> + Into<u8> {}
 
@@ -10,22 +10,87 @@ impl<P: EchonetProperty> From<TryFromPrimitiveError<P>> for Error {
     }
 }
 
-#[repr(u64)]
-#[derive(Debug, PartialEq, TryFromPrimitive, Copy, Clone)]
-pub enum EchonetObject {
-    SmartMeter = 0x028801,
-    HemsController = 0x05FF01,
+/// ECHONET object (EOJ): the class group code, the class code and the instance code.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct EchonetObject {
+    pub class_group: u8,
+    pub class: u8,
+    /// 0x01 to 0x7F. 0x00 addresses every instance of the class.
+    pub instance: u8,
+}
+
+impl EchonetObject {
+    /// Low-voltage smart electric energy meter.
+    pub const SMART_METER: EchonetObject = EchonetObject::new(0x02, 0x88, 0x01);
+    /// Controller, the class the receiver uses as a HEMS controller.
+    pub const HEMS_CONTROLLER: EchonetObject = EchonetObject::new(0x05, 0xFF, 0x01);
+    /// Node profile, describing the objects of a node.
+    pub const NODE_PROFILE: EchonetObject = EchonetObject::new(0x0E, 0xF0, 0x01);
+
+    pub const fn new(class_group: u8, class: u8, instance: u8) -> Self {
+        EchonetObject { class_group, class, instance }
+    }
+
+    /// The object of the same class with another instance code.
+    pub const fn with_instance(self, instance: u8) -> Self {
+        EchonetObject::new(self.class_group, self.class, instance)
+    }
+
+    pub fn is_same_class(&self, other: &EchonetObject) -> bool {
+        self.class_group == other.class_group && self.class == other.class
+    }
+
+    /// Whether a frame from or to `other` concerns this object, the instance code 0x00 matching every instance.
+    pub fn matches(&self, other: &EchonetObject) -> bool {
+        self.is_same_class(other) && (self.instance == other.instance || self.instance == 0 || other.instance == 0)
+    }
+}
+
+impl fmt::Display for EchonetObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:02X}{:02X}{:02X}", self.class_group, self.class, self.instance)
+    }
 }
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, TryFromPrimitive, Copy, Clone, IntoPrimitive)]
 pub enum EchonetService {
+    /// SetI_SNA
+    WritePropertyNoResponseFailResponse = 0x50,
+    /// SetC_SNA
+    WritePropertyFailResponse = 0x51,
+    /// Get_SNA
     ReadPropertyFailResponse = 0x52,
+    /// SetGet_SNA
+    WriteReadPropertyFailResponse = 0x5E,
+    /// SetI, answered only when a property is rejected.
+    WritePropertyNoResponseRequest = 0x60,
+    /// SetC
+    WritePropertyRequest = 0x61,
+    /// Get
     ReadPropertyRequest = 0x62,
+    /// SetGet
+    WriteReadPropertyRequest = 0x6E,
+    /// Set_Res
+    WritePropertyResponse = 0x71,
+    /// Get_Res
     ReadPropertyResponse = 0x72,
+    /// INF
     PropertyNotification = 0x73,
+    /// INFC
     PropertyNotificationResponseRequired = 0x74,
+    /// INFC_Res
     PropertyNotificationResponse = 0x7A,
+    /// SetGet_Res
+    WriteReadPropertyResponse = 0x7E,
+}
+
+impl EchonetService {
+    /// Whether the frame carries two property lists, the properties written followed by the properties read.
+    pub fn is_write_read(self) -> bool {
+        matches!(self, EchonetService::WriteReadPropertyRequest | EchonetService::WriteReadPropertyResponse
+            | EchonetService::WriteReadPropertyFailResponse)
+    }
 }
 
 
@@ -37,8 +102,14 @@ pub enum EchonetSmartMeterProperty {
     NormalDirectionCumulativeElectricEnergy = 0xE0,
     UnitForCumulativeElectricEnergy = 0xE1,
     NormalDirectionCumulativeElectricEnergyLog1 = 0xE2,
+    ReverseDirectionCumulativeElectricEnergy = 0xE3,
+    ReverseDirectionCumulativeElectricEnergyLog1 = 0xE4,
+    DayForCumulativeElectricEnergyLog1 = 0xE5,
     InstantaneousElectricPower = 0xE7,
     InstantaneousCurrent = 0xE8,
+    NormalDirectionCumulativeElectricEnergyAtFixedTime = 0xEA,
+    ReverseDirectionCumulativeElectricEnergyAtFixedTime = 0xEB,
+    DayForCumulativeElectricEnergyLog2 = 0xED,
 }
 
 impl EchonetProperty for EchonetSmartMeterProperty {}
@@ -46,32 +117,82 @@ impl EchonetProperty for EchonetSmartMeterProperty {}
 #[repr(u8)]
 #[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive, Copy, Clone)]
 pub enum EchonetSuperClassProperty {
+    OperationStatus = 0x80,
+    InstallationLocation = 0x81,
+    StandardVersionInformation = 0x82,
+    IdentificationNumber = 0x83,
+    FaultStatus = 0x88,
+    ManufacturerCode = 0x8A,
+    ProductCode = 0x8C,
+    ProductionNumber = 0x8D,
+    CurrentTimeSetting = 0x97,
+    CurrentDateSetting = 0x98,
+    StatusChangeAnnouncementPropertyMap = 0x9D,
+    SetPropertyMap = 0x9E,
     GetPropertyMap = 0x9F,
 }
 
 impl EchonetProperty for EchonetSuperClassProperty {}
 
-impl Into<[u8; 3]> for EchonetObject {
-    fn into(self) -> [u8; 3] {
-        let u = self as u64;
-        let b0 = (u >> 16) as u8;
-        let b1 = (u >> 8) as u8;
-        let b2 = u as u8;
-        [b0, b1, b2]
+/// Any EPC, kept as the raw code: a property specific to the smart meter class, one inherited from the super class,
+/// one of another object such as the node profile, or one this receiver does not know. Parsing with it never fails
+/// on the EPC, and one packet can carry properties of both enums.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct PropertyCode(pub u8);
+
+impl PropertyCode {
+    /// The property specific to the smart meter class with this code, if any.
+    pub fn smart_meter(self) -> Option<EchonetSmartMeterProperty> {
+        EchonetSmartMeterProperty::try_from_primitive(self.0).ok()
     }
 }
 
-impl TryFrom<[u8; 3]> for EchonetObject {
-    type Error = Error;
+impl TryFromPrimitive for PropertyCode {
+    type Primitive = u8;
+    const NAME: &'static str = "PropertyCode";
+
+    fn try_from_primitive(number: u8) -> std::result::Result<Self, TryFromPrimitiveError<Self>> {
+        Ok(PropertyCode(number))
+    }
+}
+
+impl From<PropertyCode> for u8 {
+    fn from(code: PropertyCode) -> u8 {
+        code.0
+    }
+}
 
-    fn try_from(value: [u8; 3]) -> Result<Self> {
-        let num = ((value[0] as u64) << 16) + ((value[1] as u64) << 8) + value[2] as u64;
-        match num.try_into() {
-            Ok(e) => Ok(e),
-            Err(_) => {
-                return Err(Error::InvalidEchonetObjectIdError(format!("{:?}", value)));
-            }
-        }
+impl From<EchonetSmartMeterProperty> for PropertyCode {
+    fn from(p: EchonetSmartMeterProperty) -> Self {
+        PropertyCode(p.into())
+    }
+}
+
+impl From<EchonetSuperClassProperty> for PropertyCode {
+    fn from(p: EchonetSuperClassProperty) -> Self {
+        PropertyCode(p.into())
+    }
+}
+
+impl EchonetProperty for PropertyCode {}
+
+/// Name of a property of the smart meter object, either specific to the class or inherited from the super class.
+pub fn smart_meter_property_name(epc: u8) -> Option<String> {
+    if let Ok(p) = EchonetSmartMeterProperty::try_from_primitive(epc) {
+        return Some(format!("{:?}", p));
+    }
+    EchonetSuperClassProperty::try_from_primitive(epc).ok().map(|p| format!("{:?}", p))
+}
+
+impl From<EchonetObject> for [u8; 3] {
+    fn from(object: EchonetObject) -> [u8; 3] {
+        [object.class_group, object.class, object.instance]
+    }
+}
+
+impl From<[u8; 3]> for EchonetObject {
+    fn from(value: [u8; 3]) -> Self {
+        EchonetObject::new(value[0], value[1], value[2])
     }
 }
 
@@ -88,15 +209,44 @@ mod test {
     #[test]
     fn into_slice_test() {
         let expected = hex::decode("028801").unwrap();
-        let actual: [u8; 3] = EchonetObject::SmartMeter.into();
+        let actual: [u8; 3] = EchonetObject::SMART_METER.into();
         let actual = actual.to_vec();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn smart_meter_property_name_test() {
+        use crate::echonet::enums::smart_meter_property_name;
+        assert_eq!(Some("InstantaneousElectricPower".to_string()), smart_meter_property_name(0xE7));
+        assert_eq!(Some("ManufacturerCode".to_string()), smart_meter_property_name(0x8A));
+        assert_eq!(None, smart_meter_property_name(0xF0));
+    }
+
+    #[test]
+    fn property_code() {
+        use crate::echonet::enums::*;
+        assert_eq!(PropertyCode::from(EchonetSmartMeterProperty::InstantaneousElectricPower), PropertyCode(0xE7));
+        assert_eq!(PropertyCode::from(EchonetSuperClassProperty::OperationStatus), PropertyCode(0x80));
+        assert_eq!(Some(EchonetSmartMeterProperty::InstantaneousElectricPower), PropertyCode(0xE7).smart_meter());
+        assert_eq!(None, PropertyCode(0x80).smart_meter());
+        assert_eq!(None, PropertyCode(0xF0).smart_meter());
+    }
+
     #[test]
     fn from_slice_test() {
-        let expected = EchonetObject::SmartMeter;
-        let actual = [0x02, 0x88, 0x01].try_into().unwrap();
+        let expected = EchonetObject::SMART_METER;
+        let actual = [0x02, 0x88, 0x01].into();
         assert_eq!(expected, actual);
+        assert_eq!(EchonetObject::new(0x01, 0x30, 0x02), [0x01, 0x30, 0x02].into());
+    }
+
+    #[test]
+    fn match_instances() {
+        let all_meters = EchonetObject::SMART_METER.with_instance(0);
+        assert!(EchonetObject::SMART_METER.matches(&all_meters));
+        assert!(all_meters.matches(&EchonetObject::SMART_METER.with_instance(2)));
+        assert!(!EchonetObject::SMART_METER.matches(&EchonetObject::SMART_METER.with_instance(2)));
+        assert!(!EchonetObject::SMART_METER.matches(&EchonetObject::NODE_PROFILE));
+        assert_eq!("0x0EF001", EchonetObject::NODE_PROFILE.to_string());
     }
 }
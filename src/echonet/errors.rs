@@ -9,14 +9,14 @@ pub enum Error {
     #[error("unknown value: {0}")]
     InvalidValueError(String),
 
-    #[error("invalid echonet object id: {0}")]
-    InvalidEchonetObjectIdError(String),
-
     #[error("invalid echonet service id: {0}")]
     InvalidEchonetServiceError(u8),
 
     #[error("invalid echonet property id: {0}")]
     InvalidEchonetProperty(u8),
+
+    #[error("invalid value of property 0x{0:02X} ({1}): {2}")]
+    InvalidPropertyValueError(u8, String, String),
 }
 
 impl From<TryFromSliceError> for Error {
@@ -2,8 +2,9 @@ mod packet;
 mod errors;
 mod enums;
 mod property_map;
+pub mod mra;
 
 pub use errors::{Error, Result};
 pub use packet::{EchonetPacket, Edata, Property};
-pub use enums::{EchonetProperty, EchonetSmartMeterProperty, EchonetObject, EchonetService, EchonetSuperClassProperty};
+pub use enums::{EchonetProperty, EchonetSmartMeterProperty, EchonetObject, EchonetService, EchonetSuperClassProperty, PropertyCode, smart_meter_property_name};
 pub use property_map::PropertyMap;
@@ -0,0 +1,377 @@
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::{Map, Value as Json};
+
+/// Name of the state meaning that the meter has not measured the value.
+const NO_DATA: &str = "noData";
+
+/// Integer encodings of `number` data.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NumberFormat {
+    Int8,
+    Int16,
+    Int32,
+    Uint8,
+    Uint16,
+    Uint32,
+}
+
+impl NumberFormat {
+    fn parse(format: &str) -> Option<NumberFormat> {
+        match format {
+            "int8" => Some(NumberFormat::Int8),
+            "int16" => Some(NumberFormat::Int16),
+            "int32" => Some(NumberFormat::Int32),
+            "uint8" => Some(NumberFormat::Uint8),
+            "uint16" => Some(NumberFormat::Uint16),
+            "uint32" => Some(NumberFormat::Uint32),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            NumberFormat::Int8 | NumberFormat::Uint8 => 1,
+            NumberFormat::Int16 | NumberFormat::Uint16 => 2,
+            NumberFormat::Int32 | NumberFormat::Uint32 => 4,
+        }
+    }
+
+    fn read(self, data: &[u8]) -> i64 {
+        let unsigned = data.iter().fold(0u64, |v, b| (v << 8) | *b as u64);
+        match self {
+            NumberFormat::Int8 => unsigned as u8 as i8 as i64,
+            NumberFormat::Int16 => unsigned as u16 as i16 as i64,
+            NumberFormat::Int32 => unsigned as u32 as i32 as i64,
+            NumberFormat::Uint8 | NumberFormat::Uint16 | NumberFormat::Uint32 => unsigned as i64,
+        }
+    }
+}
+
+/// An integer in the valid range, scaled by `multiple` into `unit`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Number {
+    pub format: NumberFormat,
+    pub minimum: i64,
+    pub maximum: i64,
+    pub unit: Option<String>,
+    /// `multipleOf` in the appendix, such as 0.1 for currents in units of 0.1 A.
+    pub multiple: Factor,
+}
+
+/// Layout of the data of a property, the subset of the Machine Readable Appendix data types the meter uses.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DataType {
+    Number(Number),
+    /// Values with a name, such as `noData`, by EDT.
+    State { size: usize, states: Vec<(u64, String)> },
+    Raw { min_size: usize, max_size: usize },
+    /// YYYY MM DD.
+    Date,
+    /// hh mm, followed by ss when `size` is 3.
+    Time { size: usize },
+    /// YYYY MM DD hh mm, followed by ss when `size` is 7.
+    DateTime { size: usize },
+    /// Elements following each other.
+    Object(Vec<(String, DataType)>),
+    Array { items: Box<DataType>, min_items: usize, max_items: usize },
+    /// The first alternative the data matches, such as a number or one of the states outside its range.
+    OneOf(Vec<DataType>),
+}
+
+impl DataType {
+    /// Build the data type from its JSON description, whose `$ref` have been resolved.
+    pub(super) fn from_json(json: &Json) -> Result<DataType, String> {
+        let object = json.as_object().ok_or_else(|| format!("data MUST BE an object: {}", json))?;
+        if let Some(alternatives) = object.get("oneOf").and_then(Json::as_array) {
+            return Ok(DataType::OneOf(alternatives.iter().map(DataType::from_json).collect::<Result<_, _>>()?));
+        }
+        let data_type = match object.get("type").and_then(Json::as_str) {
+            Some("number") => DataType::Number(Number {
+                format: string(object, "format").and_then(|f| NumberFormat::parse(&f).ok_or_else(|| format!("unknown format {}", f)))?,
+                minimum: integer(object, "minimum")?,
+                maximum: integer(object, "maximum")?,
+                unit: object.get("unit").and_then(Json::as_str).map(str::to_string),
+                multiple: match object.get("multipleOf") {
+                    Some(m) => Factor::parse(&m.to_string()).ok_or_else(|| format!("invalid multipleOf {}", m))?,
+                    None => Factor::ONE,
+                },
+            }),
+            Some("state") => DataType::State {
+                size: size(object, "size")?,
+                states: object.get("enum").and_then(Json::as_array).ok_or("state without enum")?.iter()
+                    .map(|s| {
+                        let s = s.as_object().ok_or("state MUST BE an object")?;
+                        let edt = string(s, "edt")?;
+                        let edt = u64::from_str_radix(edt.trim_start_matches("0x"), 16).map_err(|_| format!("invalid edt {}", edt))?;
+                        Ok((edt, string(s, "name")?))
+                    })
+                    .collect::<Result<_, String>>()?,
+            },
+            Some("raw") => DataType::Raw { min_size: size(object, "minSize")?, max_size: size(object, "maxSize")? },
+            Some("date") => DataType::Date,
+            Some("time") => DataType::Time { size: size(object, "size")? },
+            Some("date-time") => DataType::DateTime { size: size(object, "size")? },
+            Some("object") => DataType::Object(
+                object.get("properties").and_then(Json::as_array).ok_or("object without properties")?.iter()
+                    .map(|e| {
+                        let e = e.as_object().ok_or("element MUST BE an object")?;
+                        Ok((string(e, "shortName")?, DataType::from_json(e.get("element").ok_or("element without data")?)?))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            Some("array") => DataType::Array {
+                items: Box::new(DataType::from_json(object.get("items").ok_or("array without items")?)?),
+                min_items: size(object, "minItems")?,
+                max_items: size(object, "maxItems")?,
+            },
+            t => return Err(format!("unsupported type {:?}", t)),
+        };
+        Ok(data_type)
+    }
+
+    /// Number of bytes of the data, or `None` when it varies.
+    fn size(&self) -> Option<usize> {
+        match self {
+            DataType::Number(n) => Some(n.format.size()),
+            DataType::State { size, .. } | DataType::Time { size } | DataType::DateTime { size } => Some(*size),
+            DataType::Raw { min_size, max_size } if min_size == max_size => Some(*min_size),
+            DataType::Raw { .. } => None,
+            DataType::Date => Some(4),
+            DataType::Object(elements) => elements.iter().map(|(_, e)| e.size()).sum(),
+            DataType::Array { items, min_items, max_items } if min_items == max_items => items.size().map(|s| s * min_items),
+            DataType::Array { .. } => None,
+            DataType::OneOf(alternatives) => {
+                let size = alternatives.first()?.size();
+                alternatives.iter().all(|a| a.size() == size).then_some(size)?
+            }
+        }
+    }
+
+    /// Decode `data`, which must match the type exactly, or give the reason it does not.
+    pub fn decode(&self, data: &[u8]) -> Result<Value, String> {
+        if let Some(size) = self.size() {
+            if data.len() != size {
+                return Err(format!("expected {} bytes, got {}", size, data.len()));
+            }
+        }
+        match self {
+            DataType::Number(n) => {
+                let raw = n.format.read(data);
+                if raw < n.minimum || raw > n.maximum {
+                    return Err(format!("{} is out of the range {} to {}", raw, n.minimum, n.maximum));
+                }
+                Ok(Value::Number { value: n.multiple.apply(raw as f64), unit: n.unit.clone() })
+            }
+            DataType::State { size, states } => {
+                let edt = data.iter().fold(0u64, |v, b| (v << 8) | *b as u64);
+                match states.iter().find(|(e, _)| *e == edt) {
+                    Some((_, name)) => Ok(Value::State(name.clone())),
+                    None => Err(format!("unknown state 0x{:0width$X}", edt, width = size * 2)),
+                }
+            }
+            DataType::Raw { min_size, max_size } => {
+                if data.len() < *min_size || data.len() > *max_size {
+                    return Err(format!("expected {} to {} bytes, got {}", min_size, max_size, data.len()));
+                }
+                Ok(Value::Raw(data.to_vec()))
+            }
+            DataType::Date => date(data).map(Value::Date).ok_or_else(|| format!("invalid date {}", hex::encode_upper(data))),
+            DataType::Time { .. } => time(data).map(Value::Time).ok_or_else(|| format!("invalid time {}", hex::encode_upper(data))),
+            DataType::DateTime { .. } => date(&data[..4]).zip(time(&data[4..]))
+                .map(|(d, t)| Value::DateTime(d.and_time(t)))
+                .ok_or_else(|| format!("invalid date and time {}", hex::encode_upper(data))),
+            DataType::Object(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                let mut pos = 0;
+                for (i, (name, element)) in elements.iter().enumerate() {
+                    // Only the last element may have a variable size.
+                    let size = match element.size() {
+                        Some(s) => s,
+                        None if i == elements.len() - 1 => data.len().saturating_sub(pos),
+                        None => return Err(format!("{} has a variable size", name)),
+                    };
+                    if pos + size > data.len() {
+                        return Err(format!("{}: expected {} more bytes", name, pos + size - data.len()));
+                    }
+                    let value = element.decode(&data[pos..pos + size]).map_err(|e| format!("{}: {}", name, e))?;
+                    values.push((name.clone(), value));
+                    pos += size;
+                }
+                Ok(Value::Object(values))
+            }
+            DataType::Array { items, min_items, max_items } => {
+                let size = items.size().ok_or("array items have a variable size")?;
+                let count = data.len() / size;
+                if !data.len().is_multiple_of(size) || count < *min_items || count > *max_items {
+                    return Err(format!("expected {} to {} items of {} bytes, got {} bytes", min_items, max_items, size, data.len()));
+                }
+                data.chunks(size)
+                    .enumerate()
+                    .map(|(i, item)| items.decode(item).map_err(|e| format!("[{}]: {}", i, e)))
+                    .collect::<Result<_, _>>()
+                    .map(Value::Array)
+            }
+            DataType::OneOf(alternatives) => {
+                let mut first_error = None;
+                for alternative in alternatives {
+                    match alternative.decode(data) {
+                        Ok(v) => return Ok(v),
+                        Err(e) => {
+                            first_error.get_or_insert(e);
+                        }
+                    }
+                }
+                Err(first_error.unwrap_or_else(|| "no alternative".to_string()))
+            }
+        }
+    }
+}
+
+fn string(object: &Map<String, Json>, key: &str) -> Result<String, String> {
+    object.get(key).and_then(Json::as_str).map(str::to_string).ok_or_else(|| format!("missing {}", key))
+}
+
+fn integer(object: &Map<String, Json>, key: &str) -> Result<i64, String> {
+    object.get(key).and_then(Json::as_i64).ok_or_else(|| format!("missing {}", key))
+}
+
+fn size(object: &Map<String, Json>, key: &str) -> Result<usize, String> {
+    integer(object, key).map(|s| s as usize)
+}
+
+fn date(data: &[u8]) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(u16::from_be_bytes([data[0], data[1]]) as i32, data[2] as u32, data[3] as u32)
+}
+
+fn time(data: &[u8]) -> Option<NaiveTime> {
+    NaiveTime::from_hms_opt(data[0] as u32, data[1] as u32, data.get(2).copied().unwrap_or(0) as u32)
+}
+
+/// A property value decoded with its schema.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Number { value: f64, unit: Option<String> },
+    /// The name of a state, such as `noData` or `overflow`.
+    State(String),
+    Raw(Vec<u8>),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    DateTime(NaiveDateTime),
+    Object(Vec<(String, Value)>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Whether the meter has not measured the value.
+    pub fn is_no_data(&self) -> bool {
+        matches!(self, Value::State(s) if s == NO_DATA)
+    }
+
+    /// The element `name` of an object.
+    pub fn element(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(elements) => elements.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The value with every number multiplied by `coefficient` and taking its unit. Numbers which have a unit of their
+    /// own, such as the day of a log, are left as they are.
+    pub fn scale(self, coefficient: &Coefficient) -> Value {
+        match self {
+            Value::Number { value, unit: None } => Value::Number { value: coefficient.apply(value), unit: coefficient.unit.clone() },
+            Value::Object(elements) => Value::Object(elements.into_iter().map(|(n, v)| (n, v.scale(coefficient))).collect()),
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| v.scale(coefficient)).collect()),
+            v => v,
+        }
+    }
+}
+
+/// A decimal factor kept exact as `multiplier` × 10^`exponent`, such as 0.1 as 1 × 10^-1, so that applying it rounds
+/// only once.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Factor {
+    pub multiplier: i64,
+    pub exponent: i32,
+}
+
+impl Factor {
+    pub const ONE: Factor = Factor { multiplier: 1, exponent: 0 };
+
+    /// Parse a decimal number such as `10` or `0.001`.
+    fn parse(text: &str) -> Option<Factor> {
+        let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+        if integer.is_empty() || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let multiplier = format!("{}{}", integer, fraction).parse().ok()?;
+        Some(Factor { multiplier, exponent: -(fraction.len() as i32) })
+    }
+
+    fn times(self, other: Factor) -> Option<Factor> {
+        Some(Factor { multiplier: self.multiplier.checked_mul(other.multiplier)?, exponent: self.exponent + other.exponent })
+    }
+
+    /// Multiply `value` by the multiplier, then by the power of ten, dividing for negative exponents.
+    pub fn apply(self, value: f64) -> f64 {
+        let value = value * self.multiplier as f64;
+        if self.exponent < 0 { value / 10f64.powi(-self.exponent) } else { value * 10f64.powi(self.exponent) }
+    }
+}
+
+/// Factor and unit of the numbers of a property, given by the properties listed in its `coefficient`, such as the
+/// coefficient (0xD3) and the unit (0xE1) of the cumulative energies.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Coefficient {
+    pub factor: Factor,
+    pub unit: Option<String>,
+}
+
+impl Coefficient {
+    pub const ONE: Coefficient = Coefficient { factor: Factor::ONE, unit: None };
+
+    /// Multiply the coefficient by the value of a coefficient property: an integer, or a state named after a unit such
+    /// as `0.1kWh`. Returns `None` for the other values.
+    pub fn multiply(self, value: &Value) -> Option<Coefficient> {
+        match value {
+            Value::Number { value, .. } if value.fract() == 0.0 => {
+                let factor = self.factor.times(Factor { multiplier: *value as i64, exponent: 0 })?;
+                Some(Coefficient { factor, unit: self.unit })
+            }
+            Value::State(name) => {
+                let split = name.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+                let factor = self.factor.times(Factor::parse(&name[..split])?)?;
+                Some(Coefficient { factor, unit: Some(name[split..].to_string()) })
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(&self, value: f64) -> f64 {
+        self.factor.apply(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number { value, unit: Some(unit) } => write!(f, "{} {}", value, unit),
+            Value::Number { value, unit: None } => write!(f, "{}", value),
+            Value::State(name) => write!(f, "{}", name),
+            Value::Raw(data) => write!(f, "{}", hex::encode_upper(data)),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::Time(time) => write!(f, "{}", time),
+            Value::DateTime(date_time) => write!(f, "{}", date_time),
+            Value::Object(elements) => {
+                let elements: Vec<String> = elements.iter().map(|(name, v)| format!("{}: {}", name, v)).collect();
+                write!(f, "{}", elements.join(", "))
+            }
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
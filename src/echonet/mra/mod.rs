@@ -0,0 +1,213 @@
+//! Property schemas of the ECHONET Consortium's Machine Readable Appendix (MRA).
+//!
+//! The JSON files follow the layout of the appendix: `definitions.json` holds the data types shared through `$ref`,
+//! `superClass.json` and `0x0288.json` list the properties of the device super class and of the low voltage smart
+//! electric energy meter. They keep only the properties and the data types the meter uses.
+
+mod data;
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde_json::{Map, Value as Json};
+
+use crate::echonet::{Error, Result};
+
+pub use data::{Coefficient, DataType, Value};
+
+const DEFINITIONS: &str = include_str!("definitions.json");
+const SUPER_CLASS: &str = include_str!("superClass.json");
+const SMART_METER: &str = include_str!("0x0288.json");
+
+/// Description of a property in the appendix.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PropertySchema {
+    pub short_name: String,
+    pub data: DataType,
+    /// EPCs of the properties the numbers of this one are multiplied by.
+    pub coefficient: Vec<u8>,
+}
+
+/// Properties of a class, including the ones inherited from the super class.
+#[derive(Debug)]
+pub struct ClassSchema {
+    properties: BTreeMap<u8, PropertySchema>,
+}
+
+impl ClassSchema {
+    /// The low voltage smart electric energy meter (0x0288).
+    pub fn smart_meter() -> &'static ClassSchema {
+        static SCHEMA: OnceLock<ClassSchema> = OnceLock::new();
+        SCHEMA.get_or_init(|| ClassSchema::load(&[SUPER_CLASS, SMART_METER]).expect("invalid machine readable appendix"))
+    }
+
+    /// Load the properties of `classes`, a class overriding the properties of the ones before it.
+    fn load(classes: &[&str]) -> Result<ClassSchema> {
+        let definitions: Json = serde_json::from_str(DEFINITIONS).map_err(|e| Error::ParseError(e.to_string()))?;
+        let definitions = definitions.get("definitions").and_then(Json::as_object)
+            .ok_or_else(|| Error::ParseError("definitions not found".to_string()))?;
+        let mut properties = BTreeMap::new();
+        for class in classes {
+            let class: Json = serde_json::from_str(class).map_err(|e| Error::ParseError(e.to_string()))?;
+            let class_properties = class.get("elProperties").and_then(Json::as_array)
+                .ok_or_else(|| Error::ParseError("elProperties not found".to_string()))?;
+            for p in class_properties {
+                let (epc, property) = parse_property(p, definitions).map_err(Error::ParseError)?;
+                properties.insert(epc, property);
+            }
+        }
+        Ok(ClassSchema { properties })
+    }
+
+    pub fn property(&self, epc: u8) -> Option<&PropertySchema> {
+        self.properties.get(&epc)
+    }
+
+    /// Decode `data` of `epc`, validating its size, its range and its states against the schema.
+    pub fn decode(&self, epc: u8, data: &[u8]) -> Result<Value> {
+        let property = self.property(epc).ok_or(Error::InvalidEchonetProperty(epc))?;
+        property.data.decode(data).map_err(|reason| Error::InvalidPropertyValueError(epc, property.short_name.clone(), reason))
+    }
+
+    /// Decode `data` of `epc` like `decode`, and multiply its numbers by `coefficient`.
+    pub fn decode_scaled(&self, epc: u8, data: &[u8], coefficient: &Coefficient) -> Result<Value> {
+        Ok(self.decode(epc, data)?.scale(coefficient))
+    }
+
+    /// The coefficient given by `values`, the data of coefficient properties by EPC.
+    pub fn coefficient(&self, values: &[(u8, &[u8])]) -> Result<Coefficient> {
+        values.iter().try_fold(Coefficient::ONE, |coefficient, (epc, data)| {
+            let value = self.decode(*epc, data)?;
+            coefficient.multiply(&value).ok_or_else(|| Error::InvalidPropertyValueError(
+                *epc, self.property(*epc).map(|p| p.short_name.clone()).unwrap_or_default(), format!("{} is not a coefficient", value)))
+        })
+    }
+}
+
+fn parse_property(json: &Json, definitions: &Map<String, Json>) -> std::result::Result<(u8, PropertySchema), String> {
+    let epc = json.get("epc").and_then(Json::as_str).ok_or("property without epc")?;
+    let epc = u8::from_str_radix(epc.trim_start_matches("0x"), 16).map_err(|_| format!("invalid epc {}", epc))?;
+    let short_name = json.get("shortName").and_then(Json::as_str).ok_or_else(|| format!("0x{:02X} without shortName", epc))?;
+    let data = json.get("data").ok_or_else(|| format!("0x{:02X} without data", epc))?;
+    let data = DataType::from_json(&resolve(data, definitions)?).map_err(|e| format!("0x{:02X}: {}", epc, e))?;
+    let coefficient = match json.get("coefficient").and_then(Json::as_array) {
+        Some(epcs) => epcs.iter()
+            .map(|c| c.as_str().and_then(|c| u8::from_str_radix(c.trim_start_matches("0x"), 16).ok())
+                .ok_or_else(|| format!("0x{:02X}: invalid coefficient {}", epc, c)))
+            .collect::<std::result::Result<_, String>>()?,
+        None => Vec::new(),
+    };
+    Ok((epc, PropertySchema { short_name: short_name.to_string(), data, coefficient }))
+}
+
+/// Replace every `{"$ref": "#/definitions/..."}` with the definition it refers to.
+fn resolve(json: &Json, definitions: &Map<String, Json>) -> std::result::Result<Json, String> {
+    match json {
+        Json::Object(object) => match object.get("$ref").and_then(Json::as_str) {
+            Some(reference) => {
+                let definition = reference.strip_prefix("#/definitions/")
+                    .and_then(|name| definitions.get(name))
+                    .ok_or_else(|| format!("unknown reference {}", reference))?;
+                resolve(definition, definitions)
+            }
+            None => object.iter()
+                .map(|(k, v)| Ok((k.clone(), resolve(v, definitions)?)))
+                .collect::<std::result::Result<Map<_, _>, String>>()
+                .map(Json::Object),
+        },
+        Json::Array(items) => items.iter().map(|v| resolve(v, definitions)).collect::<std::result::Result<_, _>>().map(Json::Array),
+        _ => Ok(json.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use super::data::Factor;
+
+    fn decode(epc: u8, data: &str) -> Result<Value> {
+        ClassSchema::smart_meter().decode(epc, &hex::decode(data).unwrap())
+    }
+
+    fn number(value: f64, unit: &str) -> Value {
+        Value::Number { value, unit: Some(unit.to_string()) }
+    }
+
+    #[test]
+    fn load_smart_meter() {
+        let schema = ClassSchema::smart_meter();
+        assert_eq!("operationStatus", schema.property(0x80).unwrap().short_name);
+        assert_eq!("instantaneousElectricPower", schema.property(0xE7).unwrap().short_name);
+        assert_eq!(None, schema.property(0xF0));
+    }
+
+    #[test]
+    fn decode_numbers() {
+        assert_eq!(number(-526.0, "W"), decode(0xE7, "FFFFFDF2").unwrap());
+        assert!(decode(0xE7, "7FFFFFFE").unwrap().is_no_data());
+        assert_eq!(Value::State("overflow".to_string()), decode(0xE7, "7FFFFFFF").unwrap());
+        assert_eq!(Value::Object(vec![("rPhase".to_string(), number(3.3, "A")), ("tPhase".to_string(), Value::State("noData".to_string()))]),
+                   decode(0xE8, "00217FFE").unwrap());
+        assert_eq!("rPhase: 3.3 A, tPhase: noData", decode(0xE8, "00217FFE").unwrap().to_string());
+    }
+
+    #[test]
+    fn decode_states_and_dates() {
+        assert_eq!(Value::State("fault".to_string()), decode(0x88, "41").unwrap());
+        assert_eq!(Value::State("0.1kWh".to_string()), decode(0xE1, "01").unwrap());
+        assert_eq!(Value::Object(vec![
+            ("dateAndTime".to_string(), Value::DateTime(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().and_hms_opt(18, 0, 0).unwrap())),
+            ("energy".to_string(), Value::Number { value: 123456.0, unit: None }),
+        ]), decode(0xEA, "07E703011200000001E240").unwrap());
+        assert_eq!("dateAndTime: 2023-03-01 18:30:00, numberOfCollectionSegments: 12", decode(0xED, "07E70301121E0C").unwrap().to_string());
+        let log = decode(0xE2, &format!("0001{}{}", "0001E240", "FFFFFFFE".repeat(47))).unwrap();
+        match log {
+            Value::Object(elements) => match &elements[1].1 {
+                Value::Array(items) => {
+                    assert_eq!(48, items.len());
+                    assert!(items[1].is_no_data());
+                }
+                v => panic!("unexpected value {:?}", v),
+            },
+            v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn decode_with_coefficient() {
+        let schema = ClassSchema::smart_meter();
+        assert_eq!(vec![0xD3, 0xE1], schema.property(0xE0).unwrap().coefficient);
+        assert!(schema.property(0xE7).unwrap().coefficient.is_empty());
+        let coefficient = schema.coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x01]), (0xE1, &[0x01])]).unwrap();
+        assert_eq!(Coefficient { factor: Factor { multiplier: 1, exponent: -1 }, unit: Some("kWh".to_string()) }, coefficient);
+        assert_eq!(number(12345.6, "kWh"), schema.decode_scaled(0xE0, &hex::decode("0001E240").unwrap(), &coefficient).unwrap());
+        assert!(schema.decode_scaled(0xE0, &hex::decode("FFFFFFFE").unwrap(), &coefficient).unwrap().is_no_data());
+        let energy = schema.decode_scaled(0xEA, &hex::decode("07E703011200000001E240").unwrap(), &coefficient).unwrap();
+        assert_eq!(Some(&number(12345.6, "kWh")), energy.element("energy"));
+        assert_eq!(Coefficient { factor: Factor { multiplier: 10, exponent: 0 }, unit: Some("kWh".to_string()) },
+                   schema.coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x0A]), (0xE1, &[0x00])]).unwrap());
+        // Coefficients whose factor has no whole inverse, 0.3 and 0.7 kWh.
+        let three = schema.coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x03]), (0xE1, &[0x01])]).unwrap();
+        assert_eq!(number(37036.8, "kWh"), schema.decode_scaled(0xE0, &hex::decode("0001E240").unwrap(), &three).unwrap());
+        let seven = schema.coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x07]), (0xE1, &[0x01])]).unwrap();
+        assert_eq!(number(86419.2, "kWh"), schema.decode_scaled(0xE0, &hex::decode("0001E240").unwrap(), &seven).unwrap());
+        let thousandth = schema.coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x07]), (0xE1, &[0x03])]).unwrap();
+        assert_eq!(number(864.192, "kWh"), schema.decode_scaled(0xE0, &hex::decode("0001E240").unwrap(), &thousandth).unwrap());
+        let log = schema.decode_scaled(0xE2, &hex::decode(format!("0001{}", "0001E240".repeat(48))).unwrap(), &coefficient).unwrap();
+        assert_eq!(Some(&number(1.0, "day")), log.element("day"));
+        assert!(schema.coefficient(&[(0x80, &[0x30])]).is_err());
+    }
+
+    #[test]
+    fn validation_errors_name_the_epc() {
+        let message = |epc, data| decode(epc, data).unwrap_err().to_string();
+        assert_eq!("invalid value of property 0xE5 (dayForTheHistoricalData1): 100 is out of the range 0 to 99", message(0xE5, "64"));
+        assert_eq!("invalid value of property 0xE7 (instantaneousElectricPower): expected 4 bytes, got 2", message(0xE7, "0000"));
+        assert_eq!("invalid value of property 0x80 (operationStatus): unknown state 0x32", message(0x80, "32"));
+        assert_eq!("invalid value of property 0xEA (normalDirectionCumulativeElectricEnergyAtEvery30Min): \
+                    dateAndTime: invalid date and time 07E70D01120000", message(0xEA, "07E70D011200000001E240"));
+        assert!(matches!(decode(0xF0, "00"), Err(Error::InvalidEchonetProperty(0xF0))));
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt::Debug;
 use std::mem;
 
 use crate::echonet::{Error, Result};
-use crate::echonet::enums::{EchonetObject, EchonetProperty, EchonetService};
+use crate::echonet::enums::{EchonetObject, EchonetProperty, EchonetService, PropertyCode};
 
 const ECHONET_LITE_EHD1: u8 = 0x10;
 const ECHONET_FORMAT_1: u8 = 0x81;
@@ -28,7 +28,11 @@ pub struct Edata<P: EchonetProperty> {
     pub source_object: EchonetObject,
     pub destination_object: EchonetObject,
     pub echonet_service: EchonetService,
+    /// The properties of the service. For SetGet (0x6E) and its responses, the properties written.
     pub properties: Vec<Property<P>>,
+    /// The properties read by SetGet (0x6E) and its responses, following the properties written. Always empty for the
+    /// other services.
+    pub read_properties: Vec<Property<P>>,
 }
 
 #[repr(packed)]
@@ -39,7 +43,7 @@ struct EdataHeader {
     opc: u8,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Property<P: EchonetProperty> {
     pub epc: P,
     pub data: Vec<u8>,
@@ -106,24 +110,26 @@ impl<P: EchonetProperty> Edata<P> {
         let header: [u8; 8] = bin[..8].try_into()?;
 
         let header: EdataHeader = unsafe { mem::transmute(header) };
-        let mut edata = Edata {
-            source_object: header.seoj.try_into()?,
-            destination_object: header.deoj.try_into()?,
-            echonet_service: header.esv.try_into()?,
-            properties: Vec::new(),
-        };
-
-        let mut pos = 8;
-        for _ in 0..header.opc {
-            if pos >= bin.len() {
-                return Err(Error::ParseError(String::from("data length too short")));
+        let echonet_service: EchonetService = header.esv.try_into()?;
+        let (pos, properties) = Property::parse_list(bin, 8, header.opc)?;
+        let read_properties = if echonet_service.is_write_read() {
+            match bin.get(pos) {
+                Some(opc) => Property::parse_list(bin, pos + 1, *opc)?.1,
+                None => {
+                    return Err(Error::ParseError(String::from("data length too short")));
+                }
             }
-            let (num, prop) = Property::parse(&bin[pos..])?;
-            pos += num;
-            edata.properties.push(prop);
-        }
+        } else {
+            Vec::new()
+        };
 
-        Ok(edata)
+        Ok(Edata {
+            source_object: header.seoj.into(),
+            destination_object: header.deoj.into(),
+            echonet_service,
+            properties,
+            read_properties,
+        })
     }
 
     fn dump(&self) -> Vec<u8> {
@@ -140,6 +146,12 @@ impl<P: EchonetProperty> Edata<P> {
         for d in &self.properties {
             bin.extend(d.dump().iter());
         }
+        if self.echonet_service.is_write_read() {
+            bin.push(self.read_properties.len() as u8);
+            for d in &self.read_properties {
+                bin.extend(d.dump().iter());
+            }
+        }
 
         bin
     }
@@ -163,6 +175,20 @@ impl<P: EchonetProperty> Property<P> {
         Ok((2 + pdc, ret))
     }
 
+    /// Parse `count` properties starting at `pos`, returning the position following them.
+    fn parse_list(bin: &[u8], mut pos: usize, count: u8) -> Result<(usize, Vec<Self>)> {
+        let mut properties = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if pos >= bin.len() {
+                return Err(Error::ParseError(String::from("data length too short")));
+            }
+            let (num, prop) = Property::parse(&bin[pos..])?;
+            pos += num;
+            properties.push(prop);
+        }
+        Ok((pos, properties))
+    }
+
     fn dump(&self) -> Vec<u8> {
         let mut data = Vec::with_capacity(self.data.len() + 2);
         data.push(self.epc.into());
@@ -172,20 +198,16 @@ impl<P: EchonetProperty> Property<P> {
         data
     }
 
-    pub fn get_i32(&self) -> Option<i32> {
-        let bin: [u8; 4] = match self.data.clone().try_into() {
-            Ok(b) => b,
-            Err(_) => { return None; }
-        };
-        Some(i32::from_be_bytes(bin))
+    /// The property with its raw EPC.
+    pub fn into_raw(self) -> Property<PropertyCode> {
+        Property { epc: PropertyCode(self.epc.into()), data: self.data }
     }
+}
 
-    pub fn get_u32(&self) -> Option<u32> {
-        let bin: [u8; 4] = match self.data.clone().try_into() {
-            Ok(b) => b,
-            Err(_) => { return None; }
-        };
-        Some(u32::from_be_bytes(bin))
+impl Property<PropertyCode> {
+    /// The property with its EPC as `P`, or `None` when `P` has no such property.
+    pub fn typed<P: EchonetProperty>(self) -> Option<Property<P>> {
+        P::try_from_primitive(self.epc.0).ok().map(|epc| Property { epc, data: self.data })
     }
 }
 
@@ -217,11 +239,12 @@ mod test {
                 ehd2: 0x81,
                 transaction_id: tid,
                 data: Edata {
-                    source_object: EchonetObject::SmartMeter,
-                    destination_object: EchonetObject::HemsController,
+                    source_object: EchonetObject::SMART_METER,
+                    destination_object: EchonetObject::HEMS_CONTROLLER,
                     echonet_service: EchonetService::ReadPropertyResponse,
                     properties: vec![Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020E").unwrap() },
                                      Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020F").unwrap() }],
+                    read_properties: Vec::new(),
                 },
             };
             assert_eq!(EchonetPacket::parse(bin.as_slice()).unwrap(), expected);
@@ -259,8 +282,8 @@ mod test {
                 ehd2: 0x81,
                 transaction_id: tid,
                 data: Edata {
-                    source_object: EchonetObject::SmartMeter,
-                    destination_object: EchonetObject::HemsController,
+                    source_object: EchonetObject::SMART_METER,
+                    destination_object: EchonetObject::HEMS_CONTROLLER,
                     echonet_service: EchonetService::ReadPropertyResponse,
                     properties: vec![Property {
                         epc: EchonetSmartMeterProperty::InstantaneousElectricPower,
@@ -272,6 +295,7 @@ mod test {
                                          data: hex::decode(
                                              "0000020F").unwrap(),
                                      }],
+                    read_properties: Vec::new(),
                 },
             };
             assert_eq!(bin, packet.dump());
@@ -287,15 +311,29 @@ mod test {
         fn parse_test() {
             let bin = hex::decode("02880105FF017202E7040000020EE7040000020F").unwrap();
             let expected = Edata {
-                source_object: EchonetObject::SmartMeter,
-                destination_object: EchonetObject::HemsController,
+                source_object: EchonetObject::SMART_METER,
+                destination_object: EchonetObject::HEMS_CONTROLLER,
                 echonet_service: EchonetService::ReadPropertyResponse,
                 properties: vec![Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020E").unwrap() },
                                  Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020F").unwrap() }],
+                read_properties: Vec::new(),
             };
             assert_eq!(Edata::parse(bin.as_slice()).unwrap(), expected);
         }
 
+        #[test]
+        fn parse_mixed_and_unknown_properties() {
+            use crate::echonet::enums::PropertyCode;
+            use crate::echonet::Error;
+
+            let bin = hex::decode("02880105FF017203800130E7040000020EF50100").unwrap();
+            let edata = Edata::<PropertyCode>::parse(bin.as_slice()).unwrap();
+            let epcs: Vec<PropertyCode> = edata.properties.iter().map(|p| p.epc).collect();
+            assert_eq!(vec![PropertyCode(0x80), PropertyCode(0xE7), PropertyCode(0xF5)], epcs);
+            assert_eq!(bin, edata.dump());
+            assert!(matches!(SmartMeterEdata::parse(bin.as_slice()), Err(Error::InvalidEchonetProperty(0x80))));
+        }
+
         #[test]
         fn parse_test_less_property() {
             let bin = hex::decode("02880105FF017202E7040000020E").unwrap();
@@ -305,15 +343,40 @@ mod test {
         #[test]
         fn dump_test() {
             let data = Edata {
-                source_object: EchonetObject::SmartMeter,
-                destination_object: EchonetObject::HemsController,
+                source_object: EchonetObject::SMART_METER,
+                destination_object: EchonetObject::HEMS_CONTROLLER,
                 echonet_service: EchonetService::ReadPropertyResponse,
                 properties: vec![Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020E").unwrap() },
                                  Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: hex::decode("0000020F").unwrap() }],
+                read_properties: Vec::new(),
             };
             let bin = hex::decode("02880105FF017202E7040000020EE7040000020F").unwrap();
             assert_eq!(data.dump(), bin);
         }
+
+        #[test]
+        fn write_read_test() {
+            let data = Edata {
+                source_object: EchonetObject::HEMS_CONTROLLER,
+                destination_object: EchonetObject::SMART_METER,
+                echonet_service: EchonetService::WriteReadPropertyRequest,
+                properties: vec![Property { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1, data: vec![0x01] }],
+                read_properties: vec![Property { epc: EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyLog1, data: Vec::new() },
+                                      Property { epc: EchonetSmartMeterProperty::Coefficient, data: Vec::new() }],
+            };
+            let bin = hex::decode("05FF010288016E01E5010102E200D300").unwrap();
+            assert_eq!(bin, data.dump());
+            assert_eq!(data, Edata::parse(bin.as_slice()).unwrap());
+
+            let bin = hex::decode("02880105FF015E01E50002E200D30400000001").unwrap();
+            let response = SmartMeterEdata::parse(bin.as_slice()).unwrap();
+            assert_eq!(EchonetService::WriteReadPropertyFailResponse, response.echonet_service);
+            assert_eq!(vec![Property { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1, data: Vec::new() }], response.properties);
+            assert_eq!(vec![Property { epc: EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyLog1, data: Vec::new() },
+                            Property { epc: EchonetSmartMeterProperty::Coefficient, data: vec![0x00, 0x00, 0x00, 0x01] }],
+                       response.read_properties);
+            assert!(SmartMeterEdata::parse(&bin[..11]).is_err());
+        }
     }
 
     mod property_test {
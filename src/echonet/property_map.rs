@@ -8,6 +8,11 @@ pub struct PropertyMap {
 }
 
 impl PropertyMap {
+    /// Build a map of `properties`, leaving out the codes below 0x80 which are not ECHONET properties.
+    pub fn new<I: IntoIterator<Item=u8>>(properties: I) -> PropertyMap {
+        PropertyMap { properties: properties.into_iter().filter(|p| *p >= 0x80).collect() }
+    }
+
     pub fn parse(bin: &[u8]) -> Result<PropertyMap> {
         if bin.len() == 0 {
             return Err(Error::ParseError(String::from("empty data")));
@@ -51,6 +56,24 @@ impl PropertyMap {
     pub fn get_property_ids(&self) -> &HashSet<u8> {
         &self.properties
     }
+
+    /// Encode the map as a list of EPCs, or as a bitmap when it has 16 properties or more.
+    pub fn dump(&self) -> Vec<u8> {
+        let mut props: Vec<u8> = self.properties.iter().copied().collect();
+        props.sort_unstable();
+        let mut bin = vec![props.len() as u8];
+        if props.len() < 16 {
+            bin.extend(props);
+            return bin;
+        }
+
+        let mut map = [0u8; 16];
+        for p in props {
+            map[(p & 0x0F) as usize] |= 0x01 << ((p >> 4) - 8);
+        }
+        bin.extend(map);
+        bin
+    }
 }
 
 #[cfg(test)]
@@ -65,6 +88,21 @@ mod test {
             assert_eq!(HashSet::from_iter(vec![0x80, 0x81, 0x82, 0x83, 0x88, 0x8A, 0x9D, 0x9E, 0x9F, 0xE0].iter().map(|i| *i)), map.properties);
         }
 
+        #[test]
+        fn dump() {
+            let short = vec![0x03, 0x80, 0x8A, 0xE0];
+            assert_eq!(short, PropertyMap::parse(&short).unwrap().dump());
+            let long = vec![0x16, 0x0B, 0x01, 0x01, 0x09, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03];
+            assert_eq!(long, PropertyMap::parse(&long).unwrap().dump());
+        }
+
+        #[test]
+        fn new_without_invalid_codes() {
+            let map = PropertyMap::new(0x70..0x90);
+            assert_eq!(HashSet::from_iter(0x80..0x90), map.properties);
+            assert_eq!(vec![0x10, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01], map.dump());
+        }
+
         #[test]
         fn parse_long() {
             let map = PropertyMap::parse(&vec![0x16, 0x0B, 0x01, 0x01, 0x09, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03]).unwrap();
@@ -1,51 +1,65 @@
 extern crate core;
 
+mod alert;
+mod bridge;
+mod cli;
+mod commands;
+mod config;
 mod echonet;
+mod output;
 mod parser;
+mod schedule;
 mod serial;
+mod store;
+#[cfg(test)]
+mod test_util;
 mod wisun_module;
 
-use crate::wisun_module::WiSunClient;
-use std::env;
-use std::thread::sleep;
-use std::time::Duration;
-use simplelog::{ColorChoice, CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode};
+use crate::cli::{Cli, Command};
+use crate::config::LogConfig;
+use clap::Parser;
+use std::fs::OpenOptions;
+use std::process::exit;
+use simplelog::{ColorChoice, CombinedLogger, Config, SharedLogger, TermLogger, TerminalMode, WriteLogger};
 
-fn main() {
-    CombinedLogger::init(vec![TermLogger::new(
-        LevelFilter::Trace,
+fn init_logger(config: &LogConfig) -> std::io::Result<()> {
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        config.level,
         Config::default(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
-    )]).unwrap();
-    let device = std::env::var("SERIAL_PORT").unwrap_or("/dev/ttyS0".to_string());
-    let conn = serial::new(&device, 115200).unwrap();
-    let mut cli = WiSunClient::new(conn).unwrap();
-    let version = cli.get_version().unwrap();
-    println!("Version: {}", version);
-    let bid = env::var("WISUN_BID").expect("BID MUST BE specified with WISUN_BID");
-    let password = env::var("WISUN_PASSWORD").expect("Password MUST BE specified with WISUN_PASSWORD");
-    cli.connect(bid.as_str(), password.as_str()).unwrap();
-    let property_map = cli.get_property_map().unwrap();
-    println!("{:?}", property_map);
+    )];
+    if let Some(path) = &config.file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        loggers.push(WriteLogger::new(config.level, Config::default(), file));
+    }
+    CombinedLogger::init(loggers).unwrap();
+    Ok(())
+}
 
-    loop {
-        match cli.get_power_consumption() {
-            Ok(w) => {
-                log::info!("Power consumption: {}W",w);
-            }
-            Err(e) => {
-                log::warn!("failed to retrieve power consumption: {:?}",e);
-            }
-        }
-        match cli.get_cumulative_electric_energy() {
-            Ok(w) => {
-                log::info!("Cumulative Power consumption: {:.2}kWh",w);
-            }
-            Err(e) => {
-                log::warn!("failed to retrieve Cumulative power consumption: {:?}",e);
-            }
+fn main() {
+    let cli = Cli::parse();
+    let config = match config::Config::load(cli.config.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
         }
-        sleep(Duration::from_secs(10));
+    };
+    if let Err(e) = init_logger(&config.log) {
+        eprintln!("failed to open the log file: {}", e);
+        exit(1);
+    }
+
+    let result = match cli.command.unwrap_or(Command::Monitor) {
+        Command::Scan { duration } => commands::scan(&config, duration),
+        Command::Info => commands::info(&config),
+        Command::Read { properties, day } => commands::read(&config, &properties, day),
+        Command::Monitor => commands::monitor(&config),
+        Command::History { quantity, from, to } => commands::history(&config, quantity, from, to),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
     }
 }
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::{DateTime, Duration, Local};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::output::stream::{self, Subscribers};
+use crate::output::{Event, Measurement, MeterInfo, NewestReadings, Sink};
+use crate::store::{Database, Quantity};
+use crate::wisun_module::{ConnectionState, CumulativeEnergy, InstantaneousCurrent, PhaseCurrent};
+
+/// Range of `/history` when `from` is not given.
+const DEFAULT_HISTORY_RANGE: Duration = Duration::days(1);
+/// Most samples returned by `/history`, so that a long range does not hold up the API.
+const MAX_HISTORY_SAMPLES: usize = 10_000;
+
+/// Latest values served by the API.
+struct Latest {
+    power: Option<(i32, DateTime<Local>)>,
+    energy: Option<(f64, DateTime<Local>)>,
+    reverse_energy: Option<(f64, DateTime<Local>)>,
+    current: Option<(InstantaneousCurrent, DateTime<Local>)>,
+    meter: Option<MeterInfo>,
+    state: ConnectionState,
+    state_since: DateTime<Local>,
+    last_success: Option<DateTime<Local>>,
+    newest: NewestReadings,
+    read_errors: u64,
+}
+
+impl Latest {
+    fn new() -> Self {
+        Latest {
+            power: None,
+            energy: None,
+            reverse_energy: None,
+            current: None,
+            meter: None,
+            state: ConnectionState::Disconnected,
+            state_since: Local::now(),
+            last_success: None,
+            newest: NewestReadings::default(),
+            read_errors: 0,
+        }
+    }
+
+    fn update(&mut self, event: &Event) {
+        match event {
+            Event::Reading(r) => {
+                self.last_success = Some(r.received_at);
+                if !self.newest.accept(r) {
+                    return;
+                }
+                match r.measurement {
+                    Measurement::InstantaneousPower(w) => self.power = Some((w, r.timestamp)),
+                    Measurement::InstantaneousCurrent(c) => self.current = Some((c, r.timestamp)),
+                    Measurement::CumulativeEnergy(e) => self.energy = Some((e, r.timestamp)),
+                    Measurement::ReverseCumulativeEnergy(e) => self.reverse_energy = Some((e, r.timestamp)),
+                }
+            }
+            Event::Identified(info) => self.meter = Some(info.clone()),
+            Event::ReadError(_) => self.read_errors += 1,
+            Event::StateChanged { current, .. } => {
+                self.state = *current;
+                self.state_since = Local::now();
+            }
+            Event::Scanned { .. } | Event::Alert(_) => {}
+        }
+    }
+}
+
+/// Seconds elapsed since `timestamp`.
+fn age(timestamp: &DateTime<Local>, now: &DateTime<Local>) -> f64 {
+    (*now - *timestamp).num_milliseconds() as f64 / 1000.0
+}
+
+fn value(value: Value, unit: &str, timestamp: &DateTime<Local>, now: &DateTime<Local>) -> Value {
+    json!({"value": value, "unit": unit, "timestamp": timestamp.to_rfc3339(), "age": age(timestamp, now)})
+}
+
+pub(super) fn phase_current(current: &PhaseCurrent) -> Value {
+    match current.ampere() {
+        Some(a) => json!(a),
+        None => Value::Null,
+    }
+}
+
+/// Body of the response for `path` from the latest values, or the status code and message of the error.
+fn respond(latest: &Latest, path: &str, now: DateTime<Local>) -> Result<Value, (u16, String)> {
+    let not_read = || (503, "not read yet".to_string());
+    match path {
+        "/api/v1/power" => {
+            let (w, timestamp) = latest.power.as_ref().ok_or_else(not_read)?;
+            Ok(value(json!(w), "W", timestamp, &now))
+        }
+        "/api/v1/energy" => {
+            if latest.energy.is_none() && latest.reverse_energy.is_none() {
+                return Err(not_read());
+            }
+            let energy = |e: &Option<(f64, DateTime<Local>)>| match e {
+                Some((kwh, timestamp)) => value(json!(kwh), "kWh", timestamp, &now),
+                None => Value::Null,
+            };
+            // The net value is as old as the older of the two readings.
+            let net = match (&latest.energy, &latest.reverse_energy) {
+                (Some((normal_direction, normal_timestamp)), Some((reverse_direction, reverse_timestamp))) => {
+                    let net = CumulativeEnergy { normal_direction: *normal_direction, reverse_direction: *reverse_direction }.net();
+                    value(json!(net), "kWh", normal_timestamp.min(reverse_timestamp), &now)
+                }
+                _ => Value::Null,
+            };
+            Ok(json!({"normal": energy(&latest.energy), "reverse": energy(&latest.reverse_energy), "net": net}))
+        }
+        "/api/v1/current" => {
+            let (c, timestamp) = latest.current.as_ref().ok_or_else(not_read)?;
+            let t = c.t_phase.as_ref().map(phase_current).unwrap_or(Value::Null);
+            Ok(json!({"r": phase_current(&c.r_phase), "t": t, "unit": "A",
+                      "timestamp": timestamp.to_rfc3339(), "age": age(timestamp, &now)}))
+        }
+        "/api/v1/status" => {
+            let meter = latest.meter.as_ref().map(|m| json!({
+                "manufacturer_code": m.identification.manufacturer_code,
+                "identification_number": m.identification.identification_number,
+                "mac_address": m.identification.mac_address,
+                "module_version": m.module_version,
+            }));
+            Ok(json!({
+                "state": latest.state.as_str(),
+                "since": latest.state_since.to_rfc3339(),
+                "meter": meter,
+                "last_success": latest.last_success.map(|t| t.to_rfc3339()),
+                "age": latest.last_success.map(|t| age(&t, &now)),
+                "read_errors": latest.read_errors,
+            }))
+        }
+        _ => Err((404, "not found".to_string())),
+    }
+}
+
+fn history(store: Option<&PathBuf>, query: &HashMap<String, String>, now: DateTime<Local>) -> Result<Value, (u16, String)> {
+    let store = store.ok_or((404, "no store is configured".to_string()))?;
+    let time = |key: &str| -> Result<Option<DateTime<Local>>, (u16, String)> {
+        match query.get(key) {
+            Some(v) => DateTime::parse_from_rfc3339(v)
+                .map(|t| Some(t.with_timezone(&Local)))
+                .map_err(|e| (400, format!("invalid {}: {}", key, e))),
+            None => Ok(None),
+        }
+    };
+    let to = time("to")?.unwrap_or(now);
+    let from = time("from")?.unwrap_or(to - DEFAULT_HISTORY_RANGE);
+    let quantity: Option<Quantity> = match query.get("quantity") {
+        Some(q) => Some(q.parse().map_err(|_| (400, format!("unknown quantity {}", q)))?),
+        None => None,
+    };
+
+    let internal_error = |e| (500, format!("failed to read the history: {}", e));
+    let database = Database::open(store).map_err(internal_error)?;
+    let samples = database.samples(quantity, from, to, Some(MAX_HISTORY_SAMPLES + 1)).map_err(internal_error)?;
+    if samples.len() > MAX_HISTORY_SAMPLES {
+        return Err((413, format!("more than {} samples in the range, request a shorter one", MAX_HISTORY_SAMPLES)));
+    }
+    let samples: Vec<Value> = samples.iter()
+        .map(|s| json!({"timestamp": s.timestamp.to_rfc3339(), "quantity": s.quantity.as_str(), "value": s.value}))
+        .collect();
+    let events: Vec<Value> = database.connection_events(from, to).map_err(internal_error)?.iter()
+        .map(|e| json!({"timestamp": e.timestamp.to_rfc3339(), "kind": e.kind.as_str()}))
+        .collect();
+    Ok(json!({"from": from.to_rfc3339(), "to": to.to_rfc3339(), "samples": samples, "connection_events": events}))
+}
+
+/// Split a request URL into its path and decoded query parameters.
+fn parse_url(url: &str) -> (&str, HashMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect();
+    (path, params)
+}
+
+/// Decode `%XX` escapes. A `+` is kept as it is, so that time zone offsets need not be escaped.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Serves the latest readings and the history as JSON under `http://<listen>/api/v1/`.
+///
+/// `/api/v1/stream` streams the readings and the connection state changes as Server-Sent Events, each client on its
+/// own thread.
+pub struct HttpApi {
+    latest: Arc<Mutex<Latest>>,
+    subscribers: Arc<Subscribers>,
+    server: Arc<Server>,
+}
+
+impl HttpApi {
+    pub fn start(listen: &str, store: Option<PathBuf>) -> io::Result<Self> {
+        let server = Arc::new(Server::http(listen)
+            .map_err(io::Error::other)?);
+        let latest = Arc::new(Mutex::new(Latest::new()));
+        let subscribers = Arc::new(Subscribers::default());
+
+        let s = server.clone();
+        let l = latest.clone();
+        let subs = subscribers.clone();
+        thread::spawn(move || {
+            for request in s.incoming_requests() {
+                let (path, query) = parse_url(request.url());
+                if *request.method() == Method::Get && path == "/api/v1/stream" {
+                    let events = subs.subscribe();
+                    let writer = request.into_writer();
+                    thread::spawn(move || {
+                        if let Err(e) = stream::serve_events(writer, events) {
+                            log::debug!("a stream client disconnected: {}", e);
+                        }
+                    });
+                    continue;
+                }
+                let result = match request.method() {
+                    // The history is read without locking the latest values, which the polling thread updates.
+                    Method::Get if path == "/api/v1/history" => history(store.as_ref(), &query, Local::now()),
+                    Method::Get => {
+                        let latest = l.lock().unwrap();
+                        respond(&latest, path, Local::now())
+                    }
+                    _ => Err((405, "method not allowed".to_string())),
+                };
+                let (status, body) = match result {
+                    Ok(body) => (200, body),
+                    Err((status, message)) => (status, json!({"error": message})),
+                };
+                let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+                let response = Response::from_string(body.to_string()).with_status_code(status).with_header(content_type);
+                if let Err(e) = request.respond(response) {
+                    log::debug!("failed to respond to an API request: {:?}", e);
+                }
+            }
+        });
+        Ok(HttpApi { latest, subscribers, server })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+}
+
+impl Sink for HttpApi {
+    fn handle(&mut self, event: &Event) {
+        self.latest.lock().unwrap().update(event);
+        self.subscribers.publish(event);
+    }
+}
+
+impl Drop for HttpApi {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+
+    use crate::output::test_util::{reading, received, time};
+    use crate::store::{Sample, TempDatabase};
+    use crate::wisun_module::MeterIdentification;
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> (String, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn latest_values() {
+        let mut latest = Latest::new();
+        let now = time(1700000010);
+        let query = HashMap::new();
+        assert_eq!(Err((503, "not read yet".to_string())), respond(&latest, "/api/v1/power", now));
+
+        latest.update(&Event::Identified(MeterInfo {
+            identification: MeterIdentification {
+                manufacturer_code: "000016".to_string(),
+                identification_number: None,
+                mac_address: Some("C0F9450040213077".to_string()),
+            },
+            module_version: "1.2.10".to_string(),
+        }));
+        latest.update(&Event::StateChanged { previous: ConnectionState::Connecting, current: ConnectionState::Connected });
+        latest.update(&reading(Measurement::InstantaneousPower(526)));
+        latest.update(&reading(Measurement::CumulativeEnergy(12345.6)));
+        latest.update(&reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(5.0),
+            t_phase: Some(PhaseCurrent::Overflow),
+        })));
+        latest.update(&Event::ReadError("TimeoutError"));
+
+        let timestamp = time(1700000000).to_rfc3339();
+        assert_eq!(Ok(json!({"value": 526, "unit": "W", "timestamp": timestamp, "age": 10.0})),
+                   respond(&latest, "/api/v1/power", now));
+        assert_eq!(Ok(json!({"normal": {"value": 12345.6, "unit": "kWh", "timestamp": timestamp, "age": 10.0}, "reverse": null, "net": null})),
+                   respond(&latest, "/api/v1/energy", now));
+        assert_eq!(Ok(json!({"r": 5.0, "t": null, "unit": "A", "timestamp": timestamp, "age": 10.0})),
+                   respond(&latest, "/api/v1/current", now));
+
+        let status = respond(&latest, "/api/v1/status", now).unwrap();
+        assert_eq!("connected", status["state"]);
+        assert_eq!("000016", status["meter"]["manufacturer_code"]);
+        assert_eq!("1.2.10", status["meter"]["module_version"]);
+        assert_eq!(json!(timestamp), status["last_success"]);
+        assert_eq!(1, status["read_errors"]);
+        assert_eq!(Err((404, "no store is configured".to_string())), history(None, &query, now));
+
+        latest.update(&received(Measurement::ReverseCumulativeEnergy(2345.25), time(1700000005), time(1700000005)));
+        let energy = respond(&latest, "/api/v1/energy", now).unwrap();
+        assert!((energy["net"]["value"].as_f64().unwrap() - 10000.35).abs() < 1e-9, "{}", energy);
+        assert_eq!(json!(timestamp), energy["net"]["timestamp"]);
+    }
+
+    #[test]
+    fn keep_newest_value() {
+        let mut latest = Latest::new();
+        let now = time(1700001000);
+        latest.update(&received(Measurement::CumulativeEnergy(12345.6), time(1700000900), time(1700000900)));
+        latest.update(&received(Measurement::CumulativeEnergy(12345.1), time(1700000000), time(1700000950)));
+        let energy = respond(&latest, "/api/v1/energy", now).unwrap();
+        assert_eq!(json!(12345.6), energy["normal"]["value"]);
+        let status = respond(&latest, "/api/v1/status", now).unwrap();
+        assert_eq!(json!(time(1700000950).to_rfc3339()), status["last_success"]);
+    }
+
+    #[test]
+    fn url() {
+        let (path, query) = parse_url("/api/v1/history?from=2024-01-01T00:00:00+09:00&to=2024-01-02T00%3A00%3A00%2B09%3A00&quantity");
+        assert_eq!("/api/v1/history", path);
+        assert_eq!(Some(&"2024-01-01T00:00:00+09:00".to_string()), query.get("from"));
+        assert_eq!(Some(&"2024-01-02T00:00:00+09:00".to_string()), query.get("to"));
+        assert_eq!(Some(&String::new()), query.get("quantity"));
+        assert_eq!("/api/v1/power", parse_url("/api/v1/power").0);
+        assert_eq!("100%", percent_decode("100%"));
+    }
+
+    #[test]
+    fn serve_history() {
+        let temp = TempDatabase::new("api");
+        let mut database = Database::open(&temp.path).unwrap();
+        database.insert_samples(&[
+            Sample { timestamp: time(1700000000), quantity: Quantity::Power, value: 526.0 },
+            Sample { timestamp: time(1700003600), quantity: Quantity::Power, value: 530.0 },
+        ]).unwrap();
+        let api = HttpApi::start("127.0.0.1:0", Some(temp.path.clone())).unwrap();
+        let addr = api.local_addr().unwrap();
+
+        let from = time(1699999000).to_rfc3339().replace('+', "%2B");
+        let to = time(1700001000).to_rfc3339().replace('+', "%2B");
+        let (status, body) = get(addr, &format!("/api/v1/history?from={}&to={}&quantity=power", from, to));
+        let (invalid, _) = get(addr, "/api/v1/history?from=yesterday");
+        let (not_found, _) = get(addr, "/metrics");
+        let samples: Vec<Sample> = (0..=MAX_HISTORY_SAMPLES as i64)
+            .map(|i| Sample { timestamp: time(1700010000 + i), quantity: Quantity::Power, value: 500.0 })
+            .collect();
+        database.insert_samples(&samples).unwrap();
+        let too_many = time(1700010000).to_rfc3339().replace('+', "%2B");
+        let (too_large, _) = get(addr, &format!("/api/v1/history?from={}", too_many));
+
+        assert_eq!("HTTP/1.1 200 OK", status);
+        assert_eq!(json!([{"timestamp": time(1700000000).to_rfc3339(), "quantity": "power", "value": 526.0}]), body["samples"]);
+        assert_eq!(json!([]), body["connection_events"]);
+        assert_eq!("HTTP/1.1 400 Bad Request", invalid);
+        assert_eq!("HTTP/1.1 404 Not Found", not_found);
+        assert_eq!("HTTP/1.1 413 Payload Too Large", too_large);
+    }
+
+    #[test]
+    fn stream_events() {
+        let mut api = HttpApi::start("127.0.0.1:0", None).unwrap();
+        let mut stream = TcpStream::connect(api.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        write!(stream, "GET /api/v1/stream HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!("HTTP/1.1 200 OK\r\n", line);
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        api.handle(&reading(Measurement::InstantaneousPower(526)));
+        let mut event = String::new();
+        reader.read_line(&mut event).unwrap();
+        reader.read_line(&mut event).unwrap();
+        assert_eq!(format!("event: reading\ndata: {}\n",
+                           json!({"timestamp": time(1700000000).to_rfc3339(), "quantity": "power", "value": 526, "unit": "W"})),
+                   event);
+    }
+}
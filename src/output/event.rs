@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+
+use chrono::{DateTime, Local};
+
+use crate::alert::Alert;
+
+use crate::wisun_module::{ConnectionState, InstantaneousCurrent, MeterIdentification};
+
+/// A value read from the smart meter.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Measurement {
+    /// Instantaneous electric power in W.
+    InstantaneousPower(i32),
+    InstantaneousCurrent(InstantaneousCurrent),
+    /// Normal direction cumulative electric energy in kWh.
+    CumulativeEnergy(f64),
+    /// Reverse direction cumulative electric energy in kWh.
+    ReverseCumulativeEnergy(f64),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Reading {
+    /// When the value was read, or measured by the meter for notified values.
+    pub timestamp: DateTime<Local>,
+    /// When the value reached the receiver. Notified values arrive up to half an hour after they were measured.
+    pub received_at: DateTime<Local>,
+    pub measurement: Measurement,
+}
+
+/// Timestamp of the newest reading of each measurement, for the sinks keeping only the latest value, which must not
+/// be replaced by an older notified energy arriving after a newer polled one.
+#[derive(Debug, Default)]
+pub struct NewestReadings {
+    timestamps: HashMap<Discriminant<Measurement>, DateTime<Local>>,
+}
+
+impl NewestReadings {
+    /// Whether `reading` is at least as new as the previous ones of its measurement, recording it if so.
+    pub fn accept(&mut self, reading: &Reading) -> bool {
+        let newest = self.timestamps.entry(mem::discriminant(&reading.measurement)).or_insert(reading.timestamp);
+        if reading.timestamp < *newest {
+            return false;
+        }
+        *newest = reading.timestamp;
+        true
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MeterInfo {
+    pub identification: MeterIdentification,
+    /// Firmware version of the Wi-SUN module (`SKVER`).
+    pub module_version: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    /// The meter was identified after the first connection.
+    Identified(MeterInfo),
+    Reading(Reading),
+    /// Reading a value failed with the `wisun_module::Error` variant of this name.
+    ReadError(&'static str),
+    StateChanged {
+        previous: ConnectionState,
+        current: ConnectionState,
+    },
+    /// The PAN of the meter was looked up again by scanning while connecting.
+    Scanned {
+        channel: u8,
+        pan_id: u16,
+    },
+    /// A warning about the meter or the household, such as the breaker being about to trip.
+    Alert(Alert),
+}
+
+/// Events shared by the tests of the sinks.
+#[cfg(test)]
+pub mod test_util {
+    use chrono::{DateTime, Local, TimeZone};
+
+    use super::{Event, Measurement, Reading};
+
+    pub fn time(timestamp: i64) -> DateTime<Local> {
+        Local.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    /// Reading of `measurement` taken and received at 1700000000.
+    pub fn reading(measurement: Measurement) -> Event {
+        received(measurement, time(1700000000), time(1700000000))
+    }
+
+    /// Reading of `measurement` taken at `timestamp` and received at `received_at`.
+    pub fn received(measurement: Measurement, timestamp: DateTime<Local>, received_at: DateTime<Local>) -> Event {
+        Event::Reading(Reading { timestamp, received_at, measurement })
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::config::StoreConfig;
+use crate::output::{Event, Measurement, Reading, Sink};
+use crate::store::{self, ConnectionEvent, ConnectionEventKind, Database, Quantity, Retention, Sample};
+use crate::wisun_module::ConnectionState;
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+enum Record {
+    Samples(Vec<Sample>),
+    ConnectionEvent(ConnectionEvent),
+}
+
+fn samples(reading: &Reading) -> Vec<Sample> {
+    let sample = |quantity, value| Sample { timestamp: reading.timestamp, quantity, value };
+    match &reading.measurement {
+        Measurement::InstantaneousPower(w) => vec![sample(Quantity::Power, *w as f64)],
+        Measurement::InstantaneousCurrent(c) => {
+            let r = c.r_phase.ampere().map(|a| sample(Quantity::CurrentR, a));
+            let t = c.t_phase.as_ref().and_then(|t| t.ampere()).map(|a| sample(Quantity::CurrentT, a));
+            r.into_iter().chain(t).collect()
+        }
+        Measurement::CumulativeEnergy(e) => vec![sample(Quantity::Energy, *e)],
+        Measurement::ReverseCumulativeEnergy(e) => vec![sample(Quantity::ReverseEnergy, *e)],
+    }
+}
+
+fn record(event: &Event) -> Option<Record> {
+    let kind = match event {
+        Event::Reading(r) => return Some(Record::Samples(samples(r))),
+        Event::StateChanged { current: ConnectionState::Connected, .. } => ConnectionEventKind::Join,
+        Event::StateChanged { previous: ConnectionState::Connected, .. } => ConnectionEventKind::Loss,
+        Event::Scanned { .. } => ConnectionEventKind::Rescan,
+        Event::Identified(_) | Event::ReadError(_) | Event::StateChanged { .. } | Event::Alert(_) => return None,
+    };
+    Some(Record::ConnectionEvent(ConnectionEvent { timestamp: Local::now(), kind }))
+}
+
+/// Records the readings and the connection events in the local database.
+///
+/// Records are written from a background thread, which also applies the retention every hour.
+pub struct HistoryRecorder {
+    sender: Sender<Record>,
+}
+
+impl HistoryRecorder {
+    pub fn start(config: &StoreConfig) -> store::Result<Self> {
+        let database = Database::open(&config.path)?;
+        let retention = Retention {
+            raw: Duration::from_secs(config.raw_days * 24 * 3600),
+            downsample_interval: optional_duration(config.downsample_interval),
+            max_age: optional_duration(config.retention_days * 24 * 3600),
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || write_records(database, receiver, retention, RETENTION_INTERVAL));
+        Ok(HistoryRecorder { sender })
+    }
+}
+
+/// 0 disables the downsampling or the deletion.
+fn optional_duration(seconds: u64) -> Option<Duration> {
+    match seconds {
+        0 => None,
+        s => Some(Duration::from_secs(s)),
+    }
+}
+
+/// Write the records until the recorder is dropped, applying the retention every `interval` even while records keep
+/// arriving.
+fn write_records(mut database: Database, receiver: Receiver<Record>, retention: Retention, interval: Duration) {
+    let mut next_retention = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= next_retention {
+            if let Err(e) = database.apply_retention(&retention, Local::now()) {
+                log::warn!("failed to apply the retention to the history: {}", e);
+            }
+            next_retention = now + interval;
+        }
+        let result = match receiver.recv_timeout(next_retention.saturating_duration_since(now)) {
+            Ok(Record::Samples(s)) => database.insert_samples(&s),
+            Ok(Record::ConnectionEvent(e)) => database.insert_connection_event(&e),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if let Err(e) = result {
+            log::warn!("failed to record the history: {}", e);
+        }
+    }
+}
+
+impl Sink for HistoryRecorder {
+    fn handle(&mut self, event: &Event) {
+        if let Some(r) = record(event) {
+            let _ = self.sender.send(r);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::{reading, time};
+    use crate::store::TempDatabase;
+    use crate::wisun_module::{InstantaneousCurrent, PhaseCurrent};
+
+    use super::*;
+
+    fn state_changed(previous: ConnectionState, current: ConnectionState) -> Event {
+        Event::StateChanged { previous, current }
+    }
+
+    #[test]
+    fn record_events() {
+        let temp = TempDatabase::new("history");
+        let config = StoreConfig { path: temp.path.clone(), ..StoreConfig::default() };
+        let mut recorder = HistoryRecorder::start(&config).unwrap();
+        recorder.handle(&state_changed(ConnectionState::Disconnected, ConnectionState::Connecting));
+        recorder.handle(&Event::Scanned { channel: 0x2F, pan_id: 0x3077 });
+        recorder.handle(&state_changed(ConnectionState::Connecting, ConnectionState::Connected));
+        recorder.handle(&reading(Measurement::InstantaneousPower(526)));
+        recorder.handle(&reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(5.0),
+            t_phase: Some(PhaseCurrent::Overflow),
+        })));
+        recorder.handle(&reading(Measurement::CumulativeEnergy(12345.6)));
+        recorder.handle(&Event::ReadError("TimeoutError"));
+        recorder.handle(&state_changed(ConnectionState::Connected, ConnectionState::Lost));
+
+        let database = Database::open(&temp.path).unwrap();
+        let from = time(0);
+        let to = Local::now() + chrono::Duration::hours(1);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while database.connection_events(from, to).unwrap().len() < 3 {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let kinds: Vec<ConnectionEventKind> = database.connection_events(from, to).unwrap().iter().map(|e| e.kind).collect();
+        let samples: Vec<(Quantity, f64)> = database.samples(None, from, to, None).unwrap().iter().map(|s| (s.quantity, s.value)).collect();
+        drop(recorder);
+
+        assert_eq!(vec![ConnectionEventKind::Rescan, ConnectionEventKind::Join, ConnectionEventKind::Loss], kinds);
+        assert_eq!(vec![(Quantity::CurrentR, 5.0), (Quantity::Energy, 12345.6), (Quantity::Power, 526.0)], samples);
+    }
+
+    #[test]
+    fn apply_retention_while_recording() {
+        let temp = TempDatabase::new("history_retention");
+        let retention = Retention { raw: Duration::from_secs(3600), downsample_interval: None, max_age: Some(Duration::from_secs(24 * 3600)) };
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn({
+            let database = Database::open(&temp.path).unwrap();
+            move || write_records(database, receiver, retention, Duration::from_millis(50))
+        });
+        // Recorded after the retention applied on start, and older than the maximum age.
+        sender.send(Record::Samples(vec![Sample { timestamp: time(1700000000), quantity: Quantity::Power, value: 526.0 }])).unwrap();
+
+        let database = Database::open(&temp.path).unwrap();
+        let count = |from, to| database.samples(None, from, to, None).unwrap().len();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        // The old sample is written before the newer ones, so it has been recorded once one of them is.
+        while count(time(0), time(1700000001)) > 0 || count(time(1700000001), Local::now()) == 0 {
+            // Records keep arriving more often than the retention interval.
+            sender.send(Record::Samples(vec![Sample { timestamp: Local::now(), quantity: Quantity::Power, value: 500.0 }])).unwrap();
+            assert!(Instant::now() < deadline, "the retention was not applied");
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(sender);
+        writer.join().unwrap();
+    }
+}
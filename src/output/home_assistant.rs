@@ -0,0 +1,142 @@
+use serde_json::{json, Value};
+
+use crate::output::MeterInfo;
+use crate::output::mqtt::{Message, Topics};
+
+/// Entities created for the meter: component, object ID, name and the fields specific to the entity.
+fn entities(topics: &Topics) -> Vec<(&'static str, &'static str, &'static str, Value)> {
+    vec![
+        ("sensor", "power", "Power", json!({
+            "device_class": "power",
+            "state_class": "measurement",
+            "unit_of_measurement": "W",
+            "state_topic": topics.power,
+            "value_template": "{{ value_json.value }}",
+        })),
+        ("sensor", "energy", "Energy", json!({
+            "device_class": "energy",
+            "state_class": "total_increasing",
+            "unit_of_measurement": "kWh",
+            "state_topic": topics.energy,
+            "value_template": "{{ value_json.value }}",
+        })),
+        ("sensor", "reverse_energy", "Reverse energy", json!({
+            "device_class": "energy",
+            "state_class": "total_increasing",
+            "unit_of_measurement": "kWh",
+            "state_topic": topics.reverse_energy,
+            "value_template": "{{ value_json.value }}",
+        })),
+        ("sensor", "current_r", "Current R phase", json!({
+            "device_class": "current",
+            "state_class": "measurement",
+            "unit_of_measurement": "A",
+            "state_topic": topics.current,
+            "value_template": "{{ value_json.r }}",
+        })),
+        ("sensor", "current_t", "Current T phase", json!({
+            "device_class": "current",
+            "state_class": "measurement",
+            "unit_of_measurement": "A",
+            "state_topic": topics.current,
+            "value_template": "{{ value_json.t }}",
+        })),
+        ("binary_sensor", "connectivity", "Connectivity", json!({
+            "device_class": "connectivity",
+            "entity_category": "diagnostic",
+            "state_topic": topics.connection,
+            "value_template": "{{ 'ON' if value_json.state == 'connected' else 'OFF' }}",
+        })),
+    ]
+}
+
+/// Home Assistant MQTT discovery payloads, grouping the meter's entities under one device.
+pub fn discovery_messages(prefix: &str, topics: &Topics, info: &MeterInfo) -> Vec<Message> {
+    let node_id = format!("smart_meter_{}", info.identification.unique_id().to_lowercase());
+    let mut device = json!({
+        "identifiers": [node_id],
+        "name": "Smart Meter",
+        "manufacturer": format!("ECHONET Lite manufacturer {}", info.identification.manufacturer_code),
+        "model": "Low-voltage smart meter",
+        "sw_version": info.module_version,
+    });
+    if let Some(number) = &info.identification.identification_number {
+        device["serial_number"] = json!(number);
+    }
+
+    entities(topics).into_iter()
+        .map(|(component, object_id, name, mut config)| {
+            config["name"] = json!(name);
+            config["unique_id"] = json!(format!("{}_{}", node_id, object_id));
+            config["availability_topic"] = json!(topics.status);
+            config["device"] = device.clone();
+            Message {
+                topic: format!("{}/{}/{}/{}/config", prefix, component, node_id, object_id),
+                payload: config.to_string(),
+                retain: true,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::MqttOutputConfig;
+    use crate::wisun_module::MeterIdentification;
+
+    use super::*;
+
+    fn info() -> MeterInfo {
+        MeterInfo {
+            identification: MeterIdentification {
+                manufacturer_code: "000016".to_string(),
+                identification_number: Some("00001600000000000000000000000001".to_string()),
+                mac_address: Some("C0F9450040213077".to_string()),
+            },
+            module_version: "1.2.10".to_string(),
+        }
+    }
+
+    fn payload(messages: &[Message], topic: &str) -> Value {
+        let message = messages.iter().find(|m| m.topic == topic).unwrap();
+        assert!(message.retain);
+        serde_json::from_str(&message.payload).unwrap()
+    }
+
+    #[test]
+    fn discovery() {
+        let topics = Topics::new(&MqttOutputConfig::default());
+        let messages = discovery_messages("homeassistant", &topics, &info());
+        assert_eq!(6, messages.len());
+
+        let energy = payload(&messages, "homeassistant/sensor/smart_meter_00001600000000000000000000000001/energy/config");
+        assert_eq!("energy", energy["device_class"]);
+        assert_eq!("total_increasing", energy["state_class"]);
+        assert_eq!("kWh", energy["unit_of_measurement"]);
+        assert_eq!("smart_meter/energy", energy["state_topic"]);
+        assert_eq!("smart_meter/status", energy["availability_topic"]);
+        assert_eq!("smart_meter_00001600000000000000000000000001_energy", energy["unique_id"]);
+
+        let power = payload(&messages, "homeassistant/sensor/smart_meter_00001600000000000000000000000001/power/config");
+        assert_eq!("power", power["device_class"]);
+        assert_eq!("W", power["unit_of_measurement"]);
+        assert_eq!(energy["device"], power["device"]);
+        assert_eq!("1.2.10", power["device"]["sw_version"]);
+        assert_eq!("00001600000000000000000000000001", power["device"]["serial_number"]);
+
+        let connectivity = payload(&messages, "homeassistant/binary_sensor/smart_meter_00001600000000000000000000000001/connectivity/config");
+        assert_eq!("connectivity", connectivity["device_class"]);
+        assert_eq!("smart_meter/connection", connectivity["state_topic"]);
+    }
+
+    #[test]
+    fn discovery_without_identification_number() {
+        let mut info = info();
+        info.identification.identification_number = None;
+        let topics = Topics::new(&MqttOutputConfig::default());
+        let messages = discovery_messages("ha", &topics, &info);
+        let current = payload(&messages, "ha/sensor/smart_meter_c0f9450040213077/current_t/config");
+        assert_eq!("{{ value_json.t }}", current["value_template"]);
+        assert!(current["device"].get("serial_number").is_none());
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::InfluxDbOutputConfig;
+use crate::output::{Event, Measurement, Reading, Sink};
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// Keep datagrams below the usual MTU so that they are not fragmented.
+const MAX_DATAGRAM_SIZE: usize = 1400;
+
+/// Where the lines are written to.
+pub trait LineWriter: Send {
+    /// Fails with `ErrorKind::InvalidData` when the target rejected the lines, so that writing them again is useless.
+    fn write(&mut self, lines: &[String]) -> io::Result<()>;
+}
+
+/// InfluxDB v2 HTTP write API.
+pub struct HttpWriter {
+    url: String,
+    token: String,
+}
+
+impl HttpWriter {
+    pub fn new(url: &str, org: &str, bucket: &str, token: &str) -> Self {
+        let url = format!("{}/api/v2/write?org={}&bucket={}&precision=ns",
+                          url.trim_end_matches('/'), urlencode(org), urlencode(bucket));
+        HttpWriter { url, token: token.to_string() }
+    }
+}
+
+impl LineWriter for HttpWriter {
+    fn write(&mut self, lines: &[String]) -> io::Result<()> {
+        ureq::post(&self.url)
+            .set("Authorization", &format!("Token {}", self.token))
+            .set("Content-Type", "text/plain; charset=utf-8")
+            .send_string(&lines.join("\n"))
+            .map(|_| ())
+            .map_err(|e| match e {
+                // A client error other than rate limiting fails again on every retry.
+                ureq::Error::Status(status, response) if (400..500).contains(&status) && status != 429 => {
+                    let body = response.into_string().unwrap_or_default();
+                    io::Error::new(io::ErrorKind::InvalidData, format!("status {}: {}", status, body.trim()))
+                }
+                e => io::Error::other(e),
+            })
+    }
+}
+
+/// InfluxDB UDP listener, sending as many lines per datagram as fit.
+pub struct UdpWriter {
+    socket: UdpSocket,
+    address: String,
+}
+
+impl UdpWriter {
+    pub fn new(address: &str) -> io::Result<Self> {
+        Ok(UdpWriter {
+            socket: UdpSocket::bind("0.0.0.0:0")?,
+            address: address.to_string(),
+        })
+    }
+}
+
+impl LineWriter for UdpWriter {
+    fn write(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM_SIZE {
+                self.socket.send_to(datagram.as_bytes(), &self.address)?;
+                datagram.clear();
+            }
+            datagram += line;
+            datagram += "\n";
+        }
+        if !datagram.is_empty() {
+            self.socket.send_to(datagram.as_bytes(), &self.address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends the lines to a local file.
+pub struct FileWriter {
+    path: PathBuf,
+}
+
+impl FileWriter {
+    pub fn new(path: PathBuf) -> Self {
+        FileWriter { path }
+    }
+}
+
+impl LineWriter for FileWriter {
+    fn write(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut text = lines.join("\n");
+        text += "\n";
+        file.write_all(text.as_bytes())
+    }
+}
+
+fn urlencode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Escape commas, equal signs and spaces in tag keys and values.
+fn escape_tag(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Hash of the B-route ID, telling meters apart without exposing the ID itself.
+pub fn route_b_id_hash(bid: &str) -> String {
+    hex::encode(&Sha256::digest(bid.as_bytes())[..8])
+}
+
+/// Formats the readings as InfluxDB line protocol:
+///
+/// ```text
+/// smart_meter,bid_hash=9a2b3c4d5e6f7081,meter=00001600000000000000000000000001 power=526i 1700000000000000000
+/// ```
+pub struct LineFormatter {
+    measurement: String,
+    bid_hash: String,
+    meter: Option<String>,
+}
+
+impl LineFormatter {
+    pub fn new(measurement: &str, bid: &str) -> Self {
+        LineFormatter {
+            measurement: measurement.to_string(),
+            bid_hash: route_b_id_hash(bid),
+            meter: None,
+        }
+    }
+
+    fn format(&mut self, event: &Event) -> Option<String> {
+        let Reading { timestamp, measurement, .. } = match event {
+            Event::Identified(info) => {
+                self.meter = Some(info.identification.unique_id());
+                return None;
+            }
+            Event::Reading(r) => r,
+            Event::ReadError(_) | Event::StateChanged { .. } | Event::Scanned { .. } | Event::Alert(_) => return None,
+        };
+        let fields = match measurement {
+            Measurement::InstantaneousPower(w) => format!("power={}i", w),
+            Measurement::InstantaneousCurrent(c) => {
+                let mut fields: Vec<String> = Vec::new();
+                if let Some(a) = c.r_phase.ampere() {
+                    fields.push(format!("current_r={}", a));
+                }
+                if let Some(a) = c.t_phase.as_ref().and_then(|t| t.ampere()) {
+                    fields.push(format!("current_t={}", a));
+                }
+                if fields.is_empty() {
+                    return None;
+                }
+                fields.join(",")
+            }
+            Measurement::CumulativeEnergy(e) => format!("energy={}", e),
+            Measurement::ReverseCumulativeEnergy(e) => format!("reverse_energy={}", e),
+        };
+
+        let mut line = format!("{},bid_hash={}", escape_tag(&self.measurement), self.bid_hash);
+        if let Some(meter) = &self.meter {
+            line += &format!(",meter={}", escape_tag(meter));
+        }
+        Some(format!("{} {} {}", line, fields, timestamp.timestamp_nanos_opt()?))
+    }
+}
+
+/// Lines waiting to be written, kept while the target is unavailable.
+struct LineBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl LineBuffer {
+    fn push(&mut self, line: String) {
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+            log::warn!("InfluxDB buffer is full, dropping the oldest line");
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Write the buffered lines in batches of `batch_size`, keeping the ones which failed
+    /// and dropping the ones which the target rejected.
+    fn flush(&mut self, writer: &mut dyn LineWriter, batch_size: usize) -> io::Result<()> {
+        while !self.lines.is_empty() {
+            let batch: Vec<String> = self.lines.iter().take(batch_size).cloned().collect();
+            match writer.write(&batch) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    log::warn!("InfluxDB rejected {} lines, dropping them: {}", batch.len(), e);
+                }
+                Err(e) => return Err(e),
+            }
+            self.lines.drain(..batch.len());
+        }
+        Ok(())
+    }
+}
+
+/// Writes the readings as InfluxDB line protocol.
+///
+/// Lines are written in batches from a background thread. When a write fails, the lines are kept
+/// and written again later with an increasing delay.
+pub struct InfluxDbWriter {
+    formatter: LineFormatter,
+    sender: Sender<String>,
+}
+
+impl InfluxDbWriter {
+    pub fn start(config: &InfluxDbOutputConfig, bid: &str) -> io::Result<Self> {
+        let writer: Box<dyn LineWriter> = if let Some(http) = &config.http {
+            Box::new(HttpWriter::new(&http.url, &http.org, &http.bucket, &http.token))
+        } else if let Some(udp) = &config.udp {
+            Box::new(UdpWriter::new(&udp.address)?)
+        } else if let Some(file) = &config.file {
+            Box::new(FileWriter::new(file.path.clone()))
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no InfluxDB target"));
+        };
+        Ok(InfluxDbWriter::start_with_writer(config, bid, writer,
+                                              Duration::from_secs(config.flush_interval), INITIAL_RETRY_DELAY))
+    }
+
+    fn start_with_writer(config: &InfluxDbOutputConfig, bid: &str, writer: Box<dyn LineWriter>,
+                         flush_interval: Duration, retry_delay: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let buffer = LineBuffer {
+            lines: VecDeque::new(),
+            capacity: config.max_buffered_lines,
+        };
+        let batch_size = config.batch_size;
+        thread::spawn(move || write_lines(receiver, buffer, writer, batch_size, flush_interval, retry_delay));
+        InfluxDbWriter {
+            formatter: LineFormatter::new(&config.measurement, bid),
+            sender,
+        }
+    }
+}
+
+fn write_lines(receiver: Receiver<String>, mut buffer: LineBuffer, mut writer: Box<dyn LineWriter>,
+               batch_size: usize, flush_interval: Duration, initial_retry_delay: Duration) {
+    let mut next_flush = Instant::now() + flush_interval;
+    let mut retry_delay = initial_retry_delay;
+    loop {
+        match receiver.recv_timeout(next_flush.saturating_duration_since(Instant::now())) {
+            Ok(line) => {
+                buffer.push(line);
+                if buffer.lines.len() < batch_size {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if Instant::now() < next_flush && retry_delay != initial_retry_delay {
+            // Still waiting to retry a failed write.
+            continue;
+        }
+        match buffer.flush(writer.as_mut(), batch_size) {
+            Ok(()) => {
+                retry_delay = initial_retry_delay;
+                next_flush = Instant::now() + flush_interval;
+            }
+            Err(e) => {
+                log::warn!("failed to write to InfluxDB, retrying {} lines in {:?}: {}", buffer.lines.len(), retry_delay, e);
+                next_flush = Instant::now() + retry_delay;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+impl Sink for InfluxDbWriter {
+    fn handle(&mut self, event: &Event) {
+        if let Some(line) = self.formatter.format(event) {
+            let _ = self.sender.send(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use crate::config::{InfluxDbFileConfig, InfluxDbHttpConfig};
+    use crate::output::test_util::reading;
+    use crate::output::MeterInfo;
+    use crate::wisun_module::{InstantaneousCurrent, MeterIdentification, PhaseCurrent};
+
+    use super::*;
+
+    const BID: &str = "00112233445566778899AABBCCDDEEFF";
+
+    fn identified() -> Event {
+        Event::Identified(MeterInfo {
+            identification: MeterIdentification {
+                manufacturer_code: "000016".to_string(),
+                identification_number: Some("0000160000000001".to_string()),
+                mac_address: None,
+            },
+            module_version: "1.2.10".to_string(),
+        })
+    }
+
+    /// Fails the first `failures` writes, then records the lines.
+    struct FlakyWriter {
+        failures: usize,
+        written: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl LineWriter for FlakyWriter {
+        fn write(&mut self, lines: &[String]) -> io::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::other("unavailable"));
+            }
+            self.written.lock().unwrap().push(lines.to_vec());
+            Ok(())
+        }
+    }
+
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn format_lines() {
+        let hash = route_b_id_hash(BID);
+        assert_eq!(16, hash.len());
+        let mut formatter = LineFormatter::new("smart meter", BID);
+        assert_eq!(Some(format!("smart\\ meter,bid_hash={} power=526i 1700000000000000000", hash)),
+                   formatter.format(&reading(Measurement::InstantaneousPower(526))));
+
+        assert_eq!(None, formatter.format(&identified()));
+        assert_eq!(Some(format!("smart\\ meter,bid_hash={},meter=0000160000000001 energy=12345.6 1700000000000000000", hash)),
+                   formatter.format(&reading(Measurement::CumulativeEnergy(12345.6))));
+        assert_eq!(Some(format!("smart\\ meter,bid_hash={},meter=0000160000000001 current_r=5 1700000000000000000", hash)),
+                   formatter.format(&reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+                       r_phase: PhaseCurrent::Value(5.0),
+                       t_phase: Some(PhaseCurrent::Overflow),
+                   }))));
+        assert_eq!(None, formatter.format(&Event::ReadError("TimeoutError")));
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!("a\\,b\\=c\\ d", escape_tag("a,b=c d"));
+        assert_eq!("my%20org%2Fteam", urlencode("my org/team"));
+    }
+
+    #[test]
+    fn batch_and_retry() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let writer = FlakyWriter { failures: 1, written: written.clone() };
+        let config = InfluxDbOutputConfig { batch_size: 2, ..InfluxDbOutputConfig::default() };
+        let mut sink = InfluxDbWriter::start_with_writer(&config, BID, Box::new(writer),
+                                                         Duration::from_millis(50), Duration::from_millis(50));
+        for w in [1, 2, 3] {
+            sink.handle(&reading(Measurement::InstantaneousPower(w)));
+        }
+        wait_for(|| written.lock().unwrap().iter().map(|b| b.len()).sum::<usize>() == 3);
+        let batches = written.lock().unwrap().clone();
+        assert_eq!(vec![2, 1], batches.iter().map(|b| b.len()).collect::<Vec<usize>>());
+        assert!(batches[0][0].contains(" power=1i "));
+        assert!(batches[1][0].contains(" power=3i "));
+    }
+
+    #[test]
+    fn buffer_drops_oldest() {
+        let mut buffer = LineBuffer { lines: VecDeque::new(), capacity: 2 };
+        for line in ["a", "b", "c"] {
+            buffer.push(line.to_string());
+        }
+        assert_eq!(vec!["b", "c"], buffer.lines.iter().collect::<Vec<&String>>());
+    }
+
+    #[test]
+    fn write_file() {
+        let path = std::env::temp_dir().join(format!("smart_meter_receiver_influxdb_{}", std::process::id()));
+        let config = InfluxDbOutputConfig {
+            file: Some(InfluxDbFileConfig { path: path.clone() }),
+            batch_size: 1,
+            ..InfluxDbOutputConfig::default()
+        };
+        let mut sink = InfluxDbWriter::start(&config, BID).unwrap();
+        sink.handle(&reading(Measurement::InstantaneousPower(526)));
+        sink.handle(&reading(Measurement::CumulativeEnergy(12345.6)));
+        wait_for(|| fs::read_to_string(&path).map(|t| t.lines().count() == 2).unwrap_or(false));
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.lines().next().unwrap().ends_with(" power=526i 1700000000000000000"), "{}", text);
+    }
+
+    #[test]
+    fn write_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut writer = UdpWriter::new(&socket.local_addr().unwrap().to_string()).unwrap();
+        writer.write(&["a value=1i".to_string(), "b value=2i".to_string()]).unwrap();
+        let mut datagram = [0u8; 2048];
+        let n = socket.recv(&mut datagram).unwrap();
+        assert_eq!(b"a value=1i\nb value=2i\n", &datagram[..n]);
+    }
+
+    /// Answer one request with `status`, returning the request head and body.
+    fn serve_once(status: &'static str) -> (String, thread::JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = v.trim().parse().unwrap();
+                }
+                head.push(line.trim_end().to_string());
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn write_http() {
+        let (url, server) = serve_once("204 No Content");
+        let config = InfluxDbHttpConfig {
+            url,
+            org: "home".to_string(),
+            bucket: "energy".to_string(),
+            token: "secret".to_string(),
+        };
+        let mut writer = HttpWriter::new(&config.url, &config.org, &config.bucket, &config.token);
+        writer.write(&["a value=1i".to_string(), "b value=2i".to_string()]).unwrap();
+        let (head, body) = server.join().unwrap();
+        assert_eq!("POST /api/v2/write?org=home&bucket=energy&precision=ns HTTP/1.1", head[0]);
+        assert!(head.iter().any(|h| h == "Authorization: Token secret"), "{:?}", head);
+        assert_eq!("a value=1i\nb value=2i", body);
+    }
+
+    #[test]
+    fn http_errors() {
+        for (status, kind) in [("400 Bad Request", io::ErrorKind::InvalidData),
+                               ("429 Too Many Requests", io::ErrorKind::Other),
+                               ("503 Service Unavailable", io::ErrorKind::Other)] {
+            let (url, server) = serve_once(status);
+            let mut writer = HttpWriter::new(&url, "home", "energy", "secret");
+            let e = writer.write(&["a value=1i".to_string()]).unwrap_err();
+            server.join().unwrap();
+            assert_eq!(kind, e.kind(), "{}: {}", status, e);
+        }
+    }
+
+    #[test]
+    fn flush_drops_rejected_batch() {
+        struct RejectingWriter {
+            written: Vec<String>,
+        }
+
+        impl LineWriter for RejectingWriter {
+            fn write(&mut self, lines: &[String]) -> io::Result<()> {
+                if lines.iter().any(|l| l.starts_with("bad")) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "status 400"));
+                }
+                self.written.extend_from_slice(lines);
+                Ok(())
+            }
+        }
+
+        let mut buffer = LineBuffer { lines: VecDeque::new(), capacity: 10 };
+        for line in ["a", "bad", "c"] {
+            buffer.push(line.to_string());
+        }
+        let mut writer = RejectingWriter { written: Vec::new() };
+        buffer.flush(&mut writer, 1).unwrap();
+        assert!(buffer.lines.is_empty());
+        assert_eq!(vec!["a", "c"], writer.written);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::output::{Event, Measurement, Sink};
+
+/// Writes the readings to the log.
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::Identified(info) => {
+                log::info!("meter identified: {:?}, module version {}", info.identification, info.module_version);
+            }
+            Event::Reading(r) => {
+                match &r.measurement {
+                    Measurement::InstantaneousPower(w) => {
+                        log::info!("Power consumption: {}W", w);
+                    }
+                    Measurement::InstantaneousCurrent(a) => {
+                        log::info!("Current: {}", a);
+                    }
+                    Measurement::CumulativeEnergy(e) => {
+                        log::info!("Cumulative Power consumption at {}: {:.2}kWh", r.timestamp, e);
+                    }
+                    Measurement::ReverseCumulativeEnergy(e) => {
+                        log::info!("Cumulative Power generation at {}: {:.2}kWh", r.timestamp, e);
+                    }
+                }
+            }
+            Event::Alert(a) if a.firing => log::warn!("alert {} {}: {}", a.rule, a.condition, a.message),
+            Event::Alert(a) => log::info!("alert {} {} resolved: {}", a.rule, a.condition, a.message),
+            Event::ReadError(_) | Event::StateChanged { .. } | Event::Scanned { .. } => {}
+        }
+    }
+}
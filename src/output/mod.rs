@@ -0,0 +1,49 @@
+mod api;
+mod event;
+mod history;
+mod home_assistant;
+mod influxdb;
+mod logger;
+mod modbus;
+mod mqtt;
+mod prometheus;
+mod stream;
+mod webhook;
+
+pub use api::HttpApi;
+pub use event::{Event, Measurement, MeterInfo, NewestReadings, Reading};
+#[cfg(test)]
+pub use event::test_util;
+pub use history::HistoryRecorder;
+pub use influxdb::InfluxDbWriter;
+pub use logger::LogSink;
+pub use modbus::ModbusServer;
+pub use mqtt::MqttPublisher;
+pub use prometheus::PrometheusExporter;
+pub use webhook::WebhookNotifier;
+
+/// Receives the readings and the connection state changes from the monitor.
+///
+/// `handle` is called on the polling thread, so sinks doing I/O have to hand the events over to their own thread
+/// instead of blocking it.
+pub trait Sink {
+    fn handle(&mut self, event: &Event);
+}
+
+/// Every sink enabled in the configuration.
+#[derive(Default)]
+pub struct Outputs {
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Outputs {
+    pub fn add(&mut self, sink: impl Sink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn dispatch(&mut self, event: &Event) {
+        for sink in &mut self.sinks {
+            sink.handle(event);
+        }
+    }
+}
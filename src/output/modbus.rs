@@ -0,0 +1,289 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::{DateTime, Local};
+
+use crate::output::{Event, Measurement, NewestReadings, Sink};
+use crate::wisun_module::{ConnectionState, PhaseCurrent};
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+const MAX_READ_REGISTERS: u16 = 125;
+const MBAP_HEADER_SIZE: usize = 7;
+const REGISTER_COUNT: usize = 11;
+
+/// Register values of a quantity which has not been read yet.
+const UNAVAILABLE_I32: i32 = i32::MIN;
+const UNAVAILABLE_U32: u32 = u32::MAX;
+const UNAVAILABLE_I16: i16 = i16::MIN;
+const UNAVAILABLE_U16: u16 = u16::MAX;
+
+/// Latest values mapped to the registers.
+struct Values {
+    power: Option<i32>,
+    energy: Option<f64>,
+    reverse_energy: Option<f64>,
+    current_r: Option<PhaseCurrent>,
+    current_t: Option<PhaseCurrent>,
+    last_success: Option<DateTime<Local>>,
+    newest: NewestReadings,
+    state: ConnectionState,
+    read_errors: u64,
+}
+
+impl Values {
+    fn new() -> Self {
+        Values {
+            power: None,
+            energy: None,
+            reverse_energy: None,
+            current_r: None,
+            current_t: None,
+            last_success: None,
+            newest: NewestReadings::default(),
+            state: ConnectionState::Disconnected,
+            read_errors: 0,
+        }
+    }
+
+    fn update(&mut self, event: &Event) {
+        match event {
+            Event::Reading(r) => {
+                self.last_success = Some(r.received_at);
+                if !self.newest.accept(r) {
+                    return;
+                }
+                match r.measurement {
+                    Measurement::InstantaneousPower(w) => self.power = Some(w),
+                    Measurement::InstantaneousCurrent(c) => {
+                        self.current_r = Some(c.r_phase);
+                        self.current_t = c.t_phase;
+                    }
+                    Measurement::CumulativeEnergy(e) => self.energy = Some(e),
+                    Measurement::ReverseCumulativeEnergy(e) => self.reverse_energy = Some(e),
+                }
+            }
+            Event::ReadError(_) => self.read_errors += 1,
+            Event::StateChanged { current, .. } => self.state = *current,
+            Event::Identified(_) | Event::Scanned { .. } | Event::Alert(_) => {}
+        }
+    }
+
+    /// Contents of the registers at `now`, as documented on `ModbusServer`.
+    fn registers(&self, now: DateTime<Local>) -> [u16; REGISTER_COUNT] {
+        let power = self.power.unwrap_or(UNAVAILABLE_I32) as u32;
+        let energy = |e: Option<f64>| e.map(|kwh| (kwh * 1000.0).round() as u32).unwrap_or(UNAVAILABLE_U32);
+        let (energy, reverse_energy) = (energy(self.energy), energy(self.reverse_energy));
+        let current = |c: Option<PhaseCurrent>| match c {
+            Some(PhaseCurrent::Value(a)) => (a * 10.0).round() as i16 as u16,
+            Some(PhaseCurrent::Overflow) => i16::MAX as u16,
+            Some(PhaseCurrent::Underflow) => (i16::MIN + 1) as u16,
+            None => UNAVAILABLE_I16 as u16,
+        };
+        let age = match self.last_success {
+            Some(t) => (now - t).num_seconds().clamp(0, UNAVAILABLE_U16 as i64 - 1) as u16,
+            None => UNAVAILABLE_U16,
+        };
+        let state = match self.state {
+            ConnectionState::Disconnected => 0,
+            ConnectionState::Connecting => 1,
+            ConnectionState::Connected => 2,
+            ConnectionState::Lost => 3,
+        };
+        [
+            (power >> 16) as u16, power as u16,
+            (energy >> 16) as u16, energy as u16,
+            (reverse_energy >> 16) as u16, reverse_energy as u16,
+            current(self.current_r), current(self.current_t),
+            age, state, self.read_errors.min(u16::MAX as u64) as u16,
+        ]
+    }
+}
+
+/// Response PDU to the request PDU `request`, which is an exception response for unsupported requests.
+fn respond(registers: &[u16], request: &[u8]) -> Vec<u8> {
+    let function = match request.first() {
+        Some(f) => *f,
+        None => return vec![0x80, ILLEGAL_FUNCTION],
+    };
+    let exception = |code| vec![function | 0x80, code];
+    if function != READ_HOLDING_REGISTERS && function != READ_INPUT_REGISTERS {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    if request.len() != 5 {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let address = u16::from_be_bytes([request[1], request[2]]) as usize;
+    let quantity = u16::from_be_bytes([request[3], request[4]]);
+    if quantity == 0 || quantity > MAX_READ_REGISTERS {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let quantity = quantity as usize;
+    if address + quantity > registers.len() {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    }
+    let mut response = vec![function, (quantity * 2) as u8];
+    for r in &registers[address..address + quantity] {
+        response.extend_from_slice(&r.to_be_bytes());
+    }
+    response
+}
+
+/// Answer the requests of a client until it disconnects.
+fn serve(mut stream: TcpStream, values: &Mutex<Values>) -> io::Result<()> {
+    let mut header = [0u8; MBAP_HEADER_SIZE];
+    loop {
+        if let Err(e) = stream.read_exact(&mut header) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(()),
+                _ => Err(e),
+            };
+        }
+        // The length counts the unit identifier, which is the last byte of the header.
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if length < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid length {}", length)));
+        }
+        let mut request = vec![0u8; length - 1];
+        stream.read_exact(&mut request)?;
+        if header[2..4] != [0, 0] {
+            // Not Modbus.
+            continue;
+        }
+
+        let registers = values.lock().unwrap().registers(Local::now());
+        let pdu = respond(&registers, &request);
+        let mut response = Vec::with_capacity(MBAP_HEADER_SIZE + pdu.len());
+        response.extend_from_slice(&header[..4]);
+        response.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        response.push(header[6]);
+        response.extend_from_slice(&pdu);
+        stream.write_all(&response)?;
+    }
+}
+
+/// Serves the latest readings as Modbus TCP registers, read with function 0x03 (holding registers) or 0x04 (input
+/// registers) by any unit identifier.
+///
+/// | address | type | value                                                               |
+/// |---------|------|---------------------------------------------------------------------|
+/// | 0-1     | i32  | instantaneous power in W                                            |
+/// | 2-3     | u32  | normal direction cumulative energy in Wh                            |
+/// | 4-5     | u32  | reverse direction cumulative energy in Wh                           |
+/// | 6       | i16  | R phase current in 0.1 A                                            |
+/// | 7       | i16  | T phase current in 0.1 A                                            |
+/// | 8       | u16  | seconds since the last successful reading                           |
+/// | 9       | u16  | connection state: 0 disconnected, 1 connecting, 2 connected, 3 lost |
+/// | 10      | u16  | number of failed readings, saturating                               |
+///
+/// 32-bit values are big-endian, the high word first. Values which have not been read yet are the minimum of the
+/// signed types and the maximum of the unsigned ones: 0x80000000, 0xFFFFFFFF, 0x8000 and 0xFFFF.
+/// Currents out of the range of the meter are 0x7FFF (overflow) and 0x8001 (underflow).
+pub struct ModbusServer {
+    values: Arc<Mutex<Values>>,
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ModbusServer {
+    pub fn start<A: ToSocketAddrs>(listen: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen)?;
+        let local_addr = listener.local_addr()?;
+        let values = Arc::new(Mutex::new(Values::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let v = values.clone();
+        let s = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if s.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        log::warn!("failed to accept a Modbus client: {}", e);
+                        continue;
+                    }
+                };
+                let v = v.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, &v) {
+                        log::debug!("a Modbus client disconnected: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(ModbusServer { values, local_addr, stopped })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Sink for ModbusServer {
+    fn handle(&mut self, event: &Event) {
+        self.values.lock().unwrap().update(event);
+    }
+}
+
+impl Drop for ModbusServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wake up the listener so that it sees the flag.
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::{reading, time};
+    use crate::wisun_module::InstantaneousCurrent;
+
+    use super::*;
+
+    #[test]
+    fn map_registers() {
+        let mut values = Values::new();
+        assert_eq!([0x8000, 0x0000, 0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF, 0x8000, 0x8000, 0xFFFF, 0, 0], values.registers(time(1700000000)));
+
+        values.update(&Event::StateChanged { previous: ConnectionState::Connecting, current: ConnectionState::Connected });
+        values.update(&reading(Measurement::InstantaneousPower(-526)));
+        values.update(&reading(Measurement::CumulativeEnergy(12345.6)));
+        values.update(&reading(Measurement::ReverseCumulativeEnergy(0.5)));
+        values.update(&reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(-1.5),
+            t_phase: Some(PhaseCurrent::Overflow),
+        })));
+        values.update(&Event::ReadError("TimeoutError"));
+        let now = time(1700000030);
+        assert_eq!([0xFFFF, 0xFDF2, 0x00BC, 0x6100, 0x0000, 0x01F4, 0xFFF1, 0x7FFF, 30, 2, 1], values.registers(now));
+    }
+
+    #[test]
+    fn exceptions() {
+        let registers = [1u16, 2, 3];
+        assert_eq!(vec![0x04, 0x04, 0x00, 0x02, 0x00, 0x03], respond(&registers, &[0x04, 0x00, 0x01, 0x00, 0x02]));
+        assert_eq!(vec![0x83, ILLEGAL_DATA_ADDRESS], respond(&registers, &[0x03, 0x00, 0x02, 0x00, 0x02]));
+        assert_eq!(vec![0x83, ILLEGAL_DATA_VALUE], respond(&registers, &[0x03, 0x00, 0x00, 0x00, 0x00]));
+        assert_eq!(vec![0x86, ILLEGAL_FUNCTION], respond(&registers, &[0x06, 0x00, 0x00, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn read_over_tcp() {
+        let mut server = ModbusServer::start("127.0.0.1:0").unwrap();
+        server.handle(&reading(Measurement::InstantaneousPower(526)));
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(&[0x12, 0x34, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x02]).unwrap();
+        let mut response = [0u8; 13];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!([0x12, 0x34, 0x00, 0x00, 0x00, 0x07, 0x01, 0x03, 0x04, 0x00, 0x00, 0x02, 0x0E], response);
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Client, Event as MqttEvent, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+
+use crate::config::MqttOutputConfig;
+use crate::output::{Event, Measurement, Reading, Sink};
+use crate::output::home_assistant::discovery_messages;
+use crate::wisun_module::PhaseCurrent;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_CHANNEL_CAPACITY: usize = 100;
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Topics the readings are published to, `<base_topic>/<name>` unless configured otherwise.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Topics {
+    pub power: String,
+    pub current: String,
+    pub energy: String,
+    pub reverse_energy: String,
+    pub connection: String,
+    /// `online` while the receiver is connected to the broker, `offline` (the last will) otherwise.
+    pub status: String,
+    pub alert: String,
+}
+
+impl Topics {
+    pub fn new(config: &MqttOutputConfig) -> Self {
+        let topic = |configured: &Option<String>, name: &str| {
+            configured.clone().unwrap_or_else(|| format!("{}/{}", config.base_topic, name))
+        };
+        Topics {
+            power: topic(&config.topics.power, "power"),
+            current: topic(&config.topics.current, "current"),
+            energy: topic(&config.topics.energy, "energy"),
+            reverse_energy: topic(&config.topics.reverse_energy, "reverse_energy"),
+            connection: topic(&config.topics.connection, "connection"),
+            status: topic(&config.topics.status, "status"),
+            alert: topic(&config.topics.alert, "alert"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Message>,
+    connected: bool,
+}
+
+/// Messages waiting for the broker. The oldest ones are dropped once `capacity` is reached.
+struct MessageQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    capacity: usize,
+}
+
+impl MessageQueue {
+    fn push(&self, message: Message) {
+        let mut state = self.state.lock().unwrap();
+        if state.messages.len() >= self.capacity {
+            state.messages.pop_front();
+            log::warn!("MQTT queue is full, dropping the oldest message");
+        }
+        state.messages.push_back(message);
+        self.changed.notify_all();
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().connected = connected;
+        self.changed.notify_all();
+    }
+
+    /// Wait until the broker is connected and a message is queued.
+    fn pop(&self) -> Message {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.connected {
+                if let Some(m) = state.messages.pop_front() {
+                    return m;
+                }
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// Publishes the readings to an MQTT broker.
+///
+/// The readings are queued while the broker is unreachable and published once the connection is established again.
+pub struct MqttPublisher {
+    topics: Topics,
+    discovery_prefix: Option<String>,
+    queue: Arc<MessageQueue>,
+}
+
+impl MqttPublisher {
+    pub fn start(config: &MqttOutputConfig) -> Self {
+        MqttPublisher::start_with_reconnect_delay(config, RECONNECT_DELAY)
+    }
+
+    fn start_with_reconnect_delay(config: &MqttOutputConfig, reconnect_delay: Duration) -> Self {
+        let topics = Topics::new(config);
+        let qos = qos(config.qos);
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(&topics.status, OFFLINE, qos, true));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        let (client, mut connection) = Client::new(options, REQUEST_CHANNEL_CAPACITY);
+        let queue = Arc::new(MessageQueue {
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+            capacity: config.queue_size,
+        });
+
+        let q = queue.clone();
+        let c = client.clone();
+        let status = topics.status.clone();
+        let address = format!("{}:{}", config.host, config.port);
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("connected to the MQTT broker {}", address);
+                        if let Err(e) = c.try_publish(&status, qos, true, ONLINE) {
+                            log::warn!("failed to publish the status: {:?}", e);
+                        }
+                        q.set_connected(true);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("MQTT connection to {} failed: {:?}", address, e);
+                        q.set_connected(false);
+                        thread::sleep(reconnect_delay);
+                    }
+                }
+            }
+        });
+
+        let q = queue.clone();
+        thread::spawn(move || loop {
+            let message = q.pop();
+            if let Err(e) = client.publish(&message.topic, qos, message.retain, message.payload) {
+                log::warn!("failed to publish to {}: {:?}", message.topic, e);
+            }
+        });
+
+        MqttPublisher {
+            topics,
+            discovery_prefix: config.home_assistant.as_ref().map(|h| h.discovery_prefix.clone()),
+            queue,
+        }
+    }
+}
+
+impl Sink for MqttPublisher {
+    fn handle(&mut self, event: &Event) {
+        if let (Event::Identified(info), Some(prefix)) = (event, &self.discovery_prefix) {
+            for message in discovery_messages(prefix, &self.topics, info) {
+                self.queue.push(message);
+            }
+        }
+        if let Some(message) = message(&self.topics, event) {
+            self.queue.push(message);
+        }
+    }
+}
+
+fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+fn phase_current(current: &PhaseCurrent) -> Value {
+    match current.ampere() {
+        Some(a) => json!(a),
+        None => Value::Null,
+    }
+}
+
+/// Convert an event to the message published for it.
+/// Cumulative values and the connection state are retained, instantaneous values are not.
+fn message(topics: &Topics, event: &Event) -> Option<Message> {
+    let (topic, payload, retain) = match event {
+        Event::Reading(Reading { timestamp, measurement, .. }) => {
+            let timestamp = timestamp.to_rfc3339();
+            match measurement {
+                Measurement::InstantaneousPower(w) => {
+                    (&topics.power, json!({"timestamp": timestamp, "value": w, "unit": "W"}), false)
+                }
+                Measurement::InstantaneousCurrent(c) => {
+                    let t = c.t_phase.as_ref().map(phase_current).unwrap_or(Value::Null);
+                    (&topics.current, json!({"timestamp": timestamp, "r": phase_current(&c.r_phase), "t": t, "unit": "A"}), false)
+                }
+                Measurement::CumulativeEnergy(e) => {
+                    (&topics.energy, json!({"timestamp": timestamp, "value": e, "unit": "kWh"}), true)
+                }
+                Measurement::ReverseCumulativeEnergy(e) => {
+                    (&topics.reverse_energy, json!({"timestamp": timestamp, "value": e, "unit": "kWh"}), true)
+                }
+            }
+        }
+        Event::StateChanged { current, .. } => {
+            (&topics.connection, json!({"timestamp": chrono::Local::now().to_rfc3339(), "state": current.as_str()}), true)
+        }
+        Event::Alert(a) => (&topics.alert, a.to_json(), false),
+        Event::Identified(_) | Event::ReadError(_) | Event::Scanned { .. } => return None,
+    };
+    Some(Message {
+        topic: topic.clone(),
+        payload: payload.to_string(),
+        retain,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck, Publish};
+
+    use crate::alert::Alert;
+    use crate::output::test_util::{reading, time};
+    use crate::wisun_module::{ConnectionState, InstantaneousCurrent};
+
+    use super::*;
+
+    fn config(port: u16) -> MqttOutputConfig {
+        MqttOutputConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            ..MqttOutputConfig::default()
+        }
+    }
+
+    /// Accept a single client, acknowledge its packets and return them until `publishes` PUBLISH packets arrived.
+    fn broker(listener: TcpListener, publishes: usize) -> (Packet, Vec<Publish>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buffer = BytesMut::new();
+        let mut connect = None;
+        let mut received = Vec::new();
+        while received.len() < publishes {
+            match rumqttc::read(&mut buffer, 1 << 20) {
+                Ok(Packet::Connect(c)) => {
+                    connect = Some(Packet::Connect(c));
+                    write_packet(&mut stream, |b| ConnAck::new(ConnectReturnCode::Success, false).write(b));
+                }
+                Ok(Packet::Publish(p)) => {
+                    let pkid = p.pkid;
+                    received.push(p);
+                    write_packet(&mut stream, |b| PubAck::new(pkid).write(b));
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    let mut chunk = [0u8; 1024];
+                    let n = stream.read(&mut chunk).unwrap();
+                    assert!(n > 0, "client disconnected");
+                    buffer.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+        (connect.unwrap(), received)
+    }
+
+    fn write_packet<F>(stream: &mut TcpStream, write: F)
+        where F: FnOnce(&mut BytesMut) -> Result<usize, rumqttc::Error> {
+        let mut buffer = BytesMut::new();
+        write(&mut buffer).unwrap();
+        stream.write_all(&buffer).unwrap();
+    }
+
+    #[test]
+    fn topics() {
+        let mut config = config(1883);
+        config.base_topic = "home/meter".to_string();
+        config.topics.power = Some("power/now".to_string());
+        let topics = Topics::new(&config);
+        assert_eq!("power/now", topics.power);
+        assert_eq!("home/meter/energy", topics.energy);
+        assert_eq!("home/meter/status", topics.status);
+    }
+
+    #[test]
+    fn messages() {
+        let topics = Topics::new(&config(1883));
+        let timestamp = time(1700000000).to_rfc3339();
+
+        let power = message(&topics, &reading(Measurement::InstantaneousPower(526))).unwrap();
+        assert_eq!("smart_meter/power", power.topic);
+        assert!(!power.retain);
+        assert_eq!(json!({"timestamp": timestamp, "value": 526, "unit": "W"}),
+                   serde_json::from_str::<Value>(&power.payload).unwrap());
+
+        let energy = message(&topics, &reading(Measurement::CumulativeEnergy(12345.6))).unwrap();
+        assert_eq!("smart_meter/energy", energy.topic);
+        assert!(energy.retain);
+        assert_eq!(json!({"timestamp": timestamp, "value": 12345.6, "unit": "kWh"}),
+                   serde_json::from_str::<Value>(&energy.payload).unwrap());
+
+        let current = message(&topics, &reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(5.0),
+            t_phase: Some(PhaseCurrent::Overflow),
+        }))).unwrap();
+        assert_eq!(json!({"timestamp": timestamp, "r": 5.0, "t": null, "unit": "A"}),
+                   serde_json::from_str::<Value>(&current.payload).unwrap());
+
+        let state = message(&topics, &Event::StateChanged {
+            previous: ConnectionState::Connected,
+            current: ConnectionState::Lost,
+        }).unwrap();
+        assert_eq!("smart_meter/connection", state.topic);
+        assert!(state.retain);
+        assert_eq!("lost", serde_json::from_str::<Value>(&state.payload).unwrap()["state"]);
+
+        assert_eq!(None, message(&topics, &Event::ReadError("TimeoutError")));
+
+        let alert = message(&topics, &Event::Alert(Alert {
+            rule: "breaker_r_phase".to_string(),
+            condition: "overload",
+            firing: true,
+            timestamp: time(1700000000),
+            message: "R phase current is 38.0 A, 95% of the 40 A contract".to_string(),
+        })).unwrap();
+        assert_eq!("smart_meter/alert", alert.topic);
+        assert!(!alert.retain);
+        assert_eq!("firing", serde_json::from_str::<Value>(&alert.payload).unwrap()["status"]);
+    }
+
+    #[test]
+    fn queue_drops_oldest() {
+        let queue = MessageQueue {
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+            capacity: 2,
+        };
+        for topic in ["a", "b", "c"] {
+            queue.push(Message { topic: topic.to_string(), payload: String::new(), retain: false });
+        }
+        queue.set_connected(true);
+        assert_eq!("b", queue.pop().topic);
+        assert_eq!("c", queue.pop().topic);
+    }
+
+    #[test]
+    fn publish_after_broker_comes_up() {
+        // Reserve a port and leave it closed until the readings are queued.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut publisher = MqttPublisher::start_with_reconnect_delay(&config(port), Duration::from_millis(50));
+        publisher.handle(&reading(Measurement::InstantaneousPower(526)));
+        publisher.handle(&reading(Measurement::CumulativeEnergy(12345.6)));
+        thread::sleep(Duration::from_millis(200));
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let (connect, publishes) = broker(listener, 3);
+
+        match connect {
+            Packet::Connect(c) => {
+                let will = c.last_will.unwrap();
+                assert_eq!("smart_meter/status", will.topic);
+                assert_eq!(OFFLINE.as_bytes(), &will.message[..]);
+                assert!(will.retain);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+        let received: Vec<(&str, bool)> = publishes.iter().map(|p| (p.topic.as_str(), p.retain)).collect();
+        assert_eq!(vec![("smart_meter/status", true), ("smart_meter/power", false), ("smart_meter/energy", true)], received);
+        assert_eq!(ONLINE.as_bytes(), &publishes[0].payload[..]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tiny_http::{Header, Response, Server};
+
+use crate::output::{Event, Measurement, NewestReadings, Sink};
+use crate::wisun_module::{ConnectionState, CumulativeEnergy, PhaseCurrent};
+
+/// Latest values exposed on `/metrics`.
+#[derive(Default)]
+struct Metrics {
+    power: Option<i32>,
+    energy: Option<f64>,
+    reverse_energy: Option<f64>,
+    r_phase_current: Option<f64>,
+    t_phase_current: Option<f64>,
+    last_success: Option<i64>,
+    newest: NewestReadings,
+    read_errors: BTreeMap<&'static str, u64>,
+    connected: bool,
+    has_connected: bool,
+    reconnects: u64,
+}
+
+impl Metrics {
+    fn update(&mut self, event: &Event) {
+        match event {
+            Event::Reading(r) => {
+                self.last_success = Some(r.received_at.timestamp());
+                if !self.newest.accept(r) {
+                    return;
+                }
+                match &r.measurement {
+                    Measurement::InstantaneousPower(w) => self.power = Some(*w),
+                    Measurement::InstantaneousCurrent(c) => {
+                        self.r_phase_current = c.r_phase.ampere();
+                        self.t_phase_current = c.t_phase.as_ref().and_then(PhaseCurrent::ampere);
+                    }
+                    Measurement::CumulativeEnergy(e) => self.energy = Some(*e),
+                    Measurement::ReverseCumulativeEnergy(e) => self.reverse_energy = Some(*e),
+                }
+            }
+            Event::ReadError(name) => {
+                *self.read_errors.entry(name).or_insert(0) += 1;
+            }
+            Event::Identified(_) | Event::Scanned { .. } | Event::Alert(_) => {}
+            Event::StateChanged { current, .. } => {
+                self.connected = *current == ConnectionState::Connected;
+                if self.connected {
+                    if self.has_connected {
+                        self.reconnects += 1;
+                    }
+                    self.has_connected = true;
+                }
+            }
+        }
+    }
+
+    /// Format the metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut text = String::new();
+        gauge(&mut text, "smart_meter_instantaneous_power_watts", "Instantaneous electric power (0xE7).",
+              &[("", self.power.map(|w| w as f64))]);
+        gauge(&mut text, "smart_meter_cumulative_energy_kwh", "Cumulative electric energy (0xE0, 0xE3).",
+              &[("direction=\"normal\"", self.energy), ("direction=\"reverse\"", self.reverse_energy)]);
+        let net = self.energy.zip(self.reverse_energy)
+            .map(|(normal_direction, reverse_direction)| CumulativeEnergy { normal_direction, reverse_direction }.net());
+        gauge(&mut text, "smart_meter_net_energy_kwh", "Cumulative energy bought minus energy sold.",
+              &[("", net)]);
+        gauge(&mut text, "smart_meter_current_amperes", "Instantaneous current per phase (0xE8).",
+              &[("phase=\"r\"", self.r_phase_current), ("phase=\"t\"", self.t_phase_current)]);
+        gauge(&mut text, "smart_meter_last_success_timestamp_seconds", "Time of the last successful read.",
+              &[("", self.last_success.map(|t| t as f64))]);
+        gauge(&mut text, "smart_meter_connected", "Whether the PANA session with the meter is established.",
+              &[("", Some(if self.connected { 1.0 } else { 0.0 }))]);
+
+        let _ = writeln!(text, "# HELP smart_meter_read_errors_total Failed reads by error.");
+        let _ = writeln!(text, "# TYPE smart_meter_read_errors_total counter");
+        for (name, count) in &self.read_errors {
+            let _ = writeln!(text, "smart_meter_read_errors_total{{error=\"{}\"}} {}", name, count);
+        }
+        let _ = writeln!(text, "# HELP smart_meter_reconnects_total Reconnections after the session was lost.");
+        let _ = writeln!(text, "# TYPE smart_meter_reconnects_total counter");
+        let _ = writeln!(text, "smart_meter_reconnects_total {}", self.reconnects);
+        text
+    }
+}
+
+/// Write a gauge, leaving out the samples which have not been read yet.
+fn gauge(text: &mut String, name: &str, help: &str, samples: &[(&str, Option<f64>)]) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        if let Some(v) = value {
+            match *labels {
+                "" => { let _ = writeln!(text, "{} {}", name, v); }
+                l => { let _ = writeln!(text, "{}{{{}}} {}", name, l, v); }
+            }
+        }
+    }
+}
+
+/// Serves the readings on `http://<listen>/metrics` for Prometheus to scrape.
+pub struct PrometheusExporter {
+    metrics: Arc<Mutex<Metrics>>,
+    server: Arc<Server>,
+}
+
+impl PrometheusExporter {
+    pub fn start(listen: &str) -> io::Result<Self> {
+        let server = Arc::new(Server::http(listen)
+            .map_err(io::Error::other)?);
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+
+        let s = server.clone();
+        let m = metrics.clone();
+        thread::spawn(move || {
+            for request in s.incoming_requests() {
+                let response = if request.url() == "/metrics" {
+                    let body = m.lock().unwrap().render();
+                    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+                    Response::from_string(body).with_header(content_type)
+                } else {
+                    Response::from_string("not found").with_status_code(404)
+                };
+                if let Err(e) = request.respond(response) {
+                    log::debug!("failed to respond to a scrape: {:?}", e);
+                }
+            }
+        });
+        Ok(PrometheusExporter { metrics, server })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+}
+
+impl Sink for PrometheusExporter {
+    fn handle(&mut self, event: &Event) {
+        self.metrics.lock().unwrap().update(event);
+    }
+}
+
+impl Drop for PrometheusExporter {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use crate::output::test_util::reading;
+    use crate::wisun_module::InstantaneousCurrent;
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn state_changed(previous: ConnectionState, current: ConnectionState) -> Event {
+        Event::StateChanged { previous, current }
+    }
+
+    #[test]
+    fn serve_metrics() {
+        let mut exporter = PrometheusExporter::start("127.0.0.1:0").unwrap();
+        let addr = exporter.local_addr().unwrap();
+
+        exporter.handle(&state_changed(ConnectionState::Disconnected, ConnectionState::Connecting));
+        exporter.handle(&state_changed(ConnectionState::Connecting, ConnectionState::Connected));
+        exporter.handle(&reading(Measurement::InstantaneousPower(526)));
+        exporter.handle(&reading(Measurement::CumulativeEnergy(12345.6)));
+        exporter.handle(&reading(Measurement::InstantaneousCurrent(InstantaneousCurrent {
+            r_phase: PhaseCurrent::Value(5.0),
+            t_phase: Some(PhaseCurrent::Overflow),
+        })));
+        exporter.handle(&Event::ReadError("TimeoutError"));
+        exporter.handle(&Event::ReadError("TimeoutError"));
+        exporter.handle(&Event::ReadError("SessionLostError"));
+        exporter.handle(&state_changed(ConnectionState::Connected, ConnectionState::Lost));
+        exporter.handle(&state_changed(ConnectionState::Lost, ConnectionState::Connecting));
+        exporter.handle(&state_changed(ConnectionState::Connecting, ConnectionState::Connected));
+
+        let response = get(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("text/plain; version=0.0.4"), "{}", response);
+        for line in [
+            "smart_meter_instantaneous_power_watts 526\n",
+            "smart_meter_cumulative_energy_kwh{direction=\"normal\"} 12345.6\n",
+            "smart_meter_current_amperes{phase=\"r\"} 5\n",
+            "smart_meter_connected 1\n",
+            "smart_meter_read_errors_total{error=\"SessionLostError\"} 1\n",
+            "smart_meter_read_errors_total{error=\"TimeoutError\"} 2\n",
+            "smart_meter_reconnects_total 1\n",
+        ] {
+            assert!(response.contains(line), "{} not in {}", line, response);
+        }
+        assert!(response.contains("smart_meter_last_success_timestamp_seconds 1700000000\n"), "{}", response);
+        assert!(!response.contains("direction=\"reverse\""), "{}", response);
+        assert!(!response.contains("\nsmart_meter_net_energy_kwh "), "{}", response);
+        assert!(!response.contains("phase=\"t\""), "{}", response);
+    }
+
+    #[test]
+    fn net_energy() {
+        let mut metrics = Metrics::default();
+        metrics.update(&reading(Measurement::CumulativeEnergy(12345.5)));
+        metrics.update(&reading(Measurement::ReverseCumulativeEnergy(2345.25)));
+        let text = metrics.render();
+        assert!(text.contains("smart_meter_net_energy_kwh 10000.25\n"), "{}", text);
+    }
+
+    #[test]
+    fn not_found() {
+        let exporter = PrometheusExporter::start("127.0.0.1:0").unwrap();
+        let response = get(exporter.local_addr().unwrap(), "/");
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    }
+}
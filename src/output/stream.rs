@@ -0,0 +1,126 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Local;
+use serde_json::{json, Value};
+
+use crate::output::api::phase_current;
+use crate::output::{Event, Measurement, Reading};
+
+/// Events kept for a subscriber which does not read them fast enough. Newer events are dropped beyond this.
+const SUBSCRIBER_QUEUE_SIZE: usize = 64;
+/// Interval of the comments keeping idle connections open, which also detects closed connections.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Format an event as a Server-Sent Event, or `None` for events which are not streamed.
+fn server_sent_event(event: &Event) -> Option<String> {
+    let (name, data) = match event {
+        Event::Reading(Reading { timestamp, measurement, .. }) => {
+            let timestamp = timestamp.to_rfc3339();
+            let data = match measurement {
+                Measurement::InstantaneousPower(w) => {
+                    json!({"timestamp": timestamp, "quantity": "power", "value": w, "unit": "W"})
+                }
+                Measurement::InstantaneousCurrent(c) => {
+                    let t = c.t_phase.as_ref().map(phase_current).unwrap_or(Value::Null);
+                    json!({"timestamp": timestamp, "quantity": "current", "r": phase_current(&c.r_phase), "t": t, "unit": "A"})
+                }
+                Measurement::CumulativeEnergy(e) => {
+                    json!({"timestamp": timestamp, "quantity": "energy", "value": e, "unit": "kWh"})
+                }
+                Measurement::ReverseCumulativeEnergy(e) => {
+                    json!({"timestamp": timestamp, "quantity": "reverse_energy", "value": e, "unit": "kWh"})
+                }
+            };
+            ("reading", data)
+        }
+        Event::StateChanged { previous, current } => {
+            ("state", json!({"timestamp": Local::now().to_rfc3339(), "previous": previous.as_str(), "state": current.as_str()}))
+        }
+        Event::Alert(a) => ("alert", a.to_json()),
+        Event::Identified(_) | Event::ReadError(_) | Event::Scanned { .. } => return None,
+    };
+    Some(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+/// Clients of the live stream, each with its own bounded queue so that a slow client never blocks the polling thread.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Mutex<Vec<SyncSender<String>>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE_SIZE);
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Queue the event for every subscriber, dropping it for the ones whose queue is full
+    /// and forgetting the ones which have disconnected.
+    pub fn publish(&self, event: &Event) {
+        let message = match server_sent_event(event) {
+            Some(m) => m,
+            None => return,
+        };
+        self.senders.lock().unwrap().retain(|s| match s.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                log::debug!("a stream client is too slow, dropping an event");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// Write the Server-Sent Events to a client until it disconnects.
+pub fn serve_events<W: Write>(mut writer: W, events: Receiver<String>) -> io::Result<()> {
+    writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+    writer.flush()?;
+    loop {
+        match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(message) => writer.write_all(message.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        writer.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::{reading, time};
+    use crate::wisun_module::ConnectionState;
+
+    use super::*;
+
+    #[test]
+    fn format_events() {
+        let timestamp = time(1700000000).to_rfc3339();
+        assert_eq!(Some(format!("event: reading\ndata: {}\n\n",
+                                json!({"timestamp": timestamp, "quantity": "power", "value": 526, "unit": "W"}))),
+                   server_sent_event(&reading(Measurement::InstantaneousPower(526))));
+        let state = server_sent_event(&Event::StateChanged { previous: ConnectionState::Connected, current: ConnectionState::Lost }).unwrap();
+        assert!(state.starts_with("event: state\ndata: {"), "{}", state);
+        assert!(state.contains("\"state\":\"lost\""), "{}", state);
+        assert_eq!(None, server_sent_event(&Event::ReadError("TimeoutError")));
+    }
+
+    #[test]
+    fn slow_and_closed_subscribers() {
+        let subscribers = Subscribers::default();
+        let slow = subscribers.subscribe();
+        let closed = subscribers.subscribe();
+        drop(closed);
+        for w in 0..SUBSCRIBER_QUEUE_SIZE as i32 + 10 {
+            subscribers.publish(&reading(Measurement::InstantaneousPower(w)));
+        }
+        assert_eq!(1, subscribers.senders.lock().unwrap().len());
+        let received: Vec<String> = slow.try_iter().collect();
+        assert_eq!(SUBSCRIBER_QUEUE_SIZE, received.len());
+        assert!(received[0].contains("\"value\":0}"), "{}", received[0]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Local;
+use serde_json::Value;
+
+use crate::alert::AlertRule;
+use crate::config::AlertConfig;
+use crate::output::{Event, Sink};
+
+/// How often the rules are evaluated without new events, so that `no_reading` fires while nothing is read.
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(1);
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+fn post(url: &str, body: &Value) -> io::Result<()> {
+    ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        .map(|_| ())
+        .map_err(io::Error::other)
+}
+
+/// Evaluates the alert rules on their own thread and posts their alerts, as well as the alerts raised elsewhere,
+/// as JSON to the webhooks.
+pub struct WebhookNotifier {
+    sender: Sender<Event>,
+}
+
+impl WebhookNotifier {
+    pub fn start(config: &AlertConfig) -> Self {
+        WebhookNotifier::start_with_retry_delay(config, INITIAL_RETRY_DELAY)
+    }
+
+    fn start_with_retry_delay(config: &AlertConfig, retry_delay: Duration) -> Self {
+        let now = Local::now();
+        let rules: Vec<AlertRule> = config.rules.iter().map(|r| AlertRule::new(r, now)).collect();
+        let (sender, events) = mpsc::channel();
+        let (alert_sender, alerts) = mpsc::channel();
+        thread::spawn(move || evaluate(events, rules, alert_sender));
+        let webhooks = config.webhooks.clone();
+        let max_attempts = config.max_attempts;
+        thread::spawn(move || deliver(alerts, webhooks, max_attempts, retry_delay));
+        WebhookNotifier { sender }
+    }
+}
+
+fn evaluate(events: Receiver<Event>, mut rules: Vec<AlertRule>, alerts: Sender<Value>) {
+    loop {
+        match events.recv_timeout(EVALUATION_INTERVAL) {
+            Ok(Event::Alert(alert)) => {
+                let _ = alerts.send(alert.to_json());
+            }
+            Ok(event) => {
+                for rule in &mut rules {
+                    rule.observe(&event, Local::now());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        for rule in &mut rules {
+            if let Some(alert) = rule.evaluate(Local::now()) {
+                log::info!("alert {}: {}", alert.rule, alert.message);
+                let _ = alerts.send(alert.to_json());
+            }
+        }
+    }
+}
+
+/// Post every alert to every webhook, retrying with a doubling delay up to `max_attempts` times.
+fn deliver(alerts: Receiver<Value>, webhooks: Vec<String>, max_attempts: u32, initial_retry_delay: Duration) {
+    for alert in alerts {
+        for url in &webhooks {
+            let mut retry_delay = initial_retry_delay;
+            for attempt in 1..=max_attempts {
+                match post(url, &alert) {
+                    Ok(()) => break,
+                    Err(e) if attempt == max_attempts => {
+                        log::warn!("failed to post an alert to {}, giving up: {}", url, e);
+                    }
+                    Err(e) => {
+                        log::warn!("failed to post an alert to {}, retrying in {:?}: {}", url, retry_delay, e);
+                        thread::sleep(retry_delay);
+                        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Sink for WebhookNotifier {
+    fn handle(&mut self, event: &Event) {
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use crate::config::{AlertCondition, AlertRuleConfig};
+    use crate::output::Measurement;
+    use crate::output::test_util::received;
+
+    use super::*;
+
+    /// Accept `count` requests, answering the first `failures` with 500, and return the bodies.
+    fn serve(listener: TcpListener, count: usize, failures: usize) -> Vec<String> {
+        (0..count).map(|i| {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let status = if i < failures { "500 Internal Server Error" } else { "204 No Content" };
+            write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            String::from_utf8(body).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn post_with_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AlertConfig {
+            webhooks: vec![format!("http://{}/hook", listener.local_addr().unwrap())],
+            max_attempts: 3,
+            rules: vec![AlertRuleConfig {
+                name: "high_power".to_string(),
+                condition: AlertCondition::PowerAbove,
+                limit: Some(4000),
+                hysteresis: 0,
+                duration: 0,
+            }],
+        };
+        let server = thread::spawn(move || serve(listener, 2, 1));
+        let mut notifier = WebhookNotifier::start_with_retry_delay(&config, Duration::from_millis(10));
+        notifier.handle(&received(Measurement::InstantaneousPower(4100), Local::now(), Local::now()));
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        let alert: Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!("high_power", alert["rule"]);
+        assert_eq!("firing", alert["status"]);
+    }
+}
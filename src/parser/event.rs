@@ -29,6 +29,22 @@ pub enum EventKind {
     FinishedActiveScan = 0x22,
     ErrorOnPanaConnection = 0x24,
     EstablishedPanaConnection = 0x25,
+    SessionTerminationRequested = 0x26,
+    SessionTerminated = 0x27,
+    SessionTerminationTimeout = 0x28,
+    SessionExpired = 0x29,
+}
+
+impl EventKind {
+    /// Whether the event means the PANA session is no longer available.
+    pub fn is_session_lost(&self) -> bool {
+        matches!(self,
+            EventKind::ErrorOnPanaConnection |
+            EventKind::SessionTerminationRequested |
+            EventKind::SessionTerminated |
+            EventKind::SessionTerminationTimeout |
+            EventKind::SessionExpired)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -38,11 +54,13 @@ pub struct EventBody {
     // TODO: Add param if necessary
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct PanDescBody {
     pub channel: u8,
     pub pan_id: u16,
     pub addr: [u8; 8],
+    /// Link quality of the beacon received from the coordinator.
+    pub lqi: u8,
 }
 
 impl WiSunEvent {
@@ -145,10 +163,20 @@ impl WiSunEvent {
             Err(_) => return ParseResult::Err(format!("malformed addr: {}", addr_str)),
         };
 
+        let lqi = match pan_data.get("LQI") {
+            Some(l) => l,
+            None => return ParseResult::Err("failed to get LQI.".to_string())
+        };
+        let lqi = match u8::from_str_radix(lqi, 16) {
+            Ok(l) => l,
+            Err(e) => return ParseResult::Err(format!("failed to parse LQI: {}", e)),
+        };
+
         ParseResult::Ok(WiSunEvent::PanDesc(PanDescBody {
             channel,
             pan_id,
             addr,
+            lqi,
         }))
     }
 
@@ -262,6 +290,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_session_expired() {
+        let even_body = EventBody {
+            kind: EventKind::SessionExpired,
+            sender: "FE80:0000:0000:0000:1234:5678:90AB:CDEF".parse().unwrap(),
+        };
+        assert_eq!(
+            WiSunEvent::parse("EVENT 29 FE80:0000:0000:0000:1234:5678:90AB:CDEF"),
+            ParseResult::Ok(WiSunEvent::Event(even_body))
+        );
+    }
+
     #[test]
     fn parse_pan_desc_single_line() {
         assert_eq!(WiSunEvent::parse("EPANDESC"), ParseResult::More);
@@ -299,6 +339,7 @@ mod test {
                        channel: 0x20,
                        pan_id: 0x3077,
                        addr: [0x12, 0x34, 0x56, 0x78, 0x90, 0xAB, 0xCD, 0xEF],
+                       lqi: 0x73,
                    })));
     }
 
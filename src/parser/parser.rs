@@ -99,6 +99,7 @@ mod test {
                             channel: 0x20,
                             pan_id: 0x3077,
                             addr: [0x12, 0x34, 0x56, 0x78, 0x90, 0xAB, 0xCD, 0xEF],
+                            lqi: 0x73,
                         }
                     )
                 )
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+/// Keeps track of tasks which have to run periodically, each with its own interval.
+pub struct Schedule<K: Copy> {
+    tasks: Vec<(K, Duration, Instant)>,
+}
+
+impl<K: Copy> Schedule<K> {
+    /// All tasks are due immediately.
+    pub fn new(tasks: Vec<(K, Duration)>, now: Instant) -> Self {
+        Schedule {
+            tasks: tasks.into_iter().map(|(k, interval)| (k, interval, now)).collect(),
+        }
+    }
+
+    /// Return the tasks due at `now`, and schedule their next run one interval later.
+    pub fn take_due(&mut self, now: Instant) -> Vec<K> {
+        let mut due = Vec::new();
+        for (key, interval, next) in &mut self.tasks {
+            if *next <= now {
+                due.push(*key);
+                // Skip the runs missed while the previous ones were running late.
+                while *next <= now {
+                    *next += *interval;
+                }
+            }
+        }
+        due
+    }
+
+    /// Time of the next run of any task.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.tasks.iter().map(|(_, _, next)| *next).min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn take_due() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(vec![("power", Duration::from_secs(10)), ("energy", Duration::from_secs(60))], start);
+        assert_eq!(vec!["power", "energy"], schedule.take_due(start));
+        assert_eq!(Vec::<&str>::new(), schedule.take_due(start + Duration::from_secs(5)));
+        assert_eq!(Some(start + Duration::from_secs(10)), schedule.next_due());
+        assert_eq!(vec!["power"], schedule.take_due(start + Duration::from_secs(10)));
+        assert_eq!(vec!["power", "energy"], schedule.take_due(start + Duration::from_secs(65)));
+        assert_eq!(Some(start + Duration::from_secs(70)), schedule.next_due());
+    }
+
+    #[test]
+    fn empty() {
+        let mut schedule: Schedule<u8> = Schedule::new(vec![], Instant::now());
+        assert!(schedule.take_due(Instant::now()).is_empty());
+        assert_eq!(None, schedule.next_due());
+    }
+}
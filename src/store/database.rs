@@ -0,0 +1,353 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::{params, Connection};
+
+use crate::store::errors::{Error, Result};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    timestamp INTEGER NOT NULL,
+    quantity TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp, quantity);
+CREATE TABLE IF NOT EXISTS connection_events (
+    timestamp INTEGER NOT NULL,
+    kind TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS connection_events_timestamp ON connection_events (timestamp);
+";
+
+/// What a sample measures.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Quantity {
+    /// Instantaneous electric power in W.
+    Power,
+    /// Normal direction cumulative electric energy in kWh.
+    Energy,
+    /// Reverse direction cumulative electric energy in kWh.
+    ReverseEnergy,
+    /// R phase current in A.
+    CurrentR,
+    /// T phase current in A.
+    CurrentT,
+}
+
+impl Quantity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantity::Power => "power",
+            Quantity::Energy => "energy",
+            Quantity::ReverseEnergy => "reverse_energy",
+            Quantity::CurrentR => "current_r",
+            Quantity::CurrentT => "current_t",
+        }
+    }
+
+    /// Cumulative values are downsampled to their last value instead of their mean.
+    fn is_cumulative(&self) -> bool {
+        matches!(self, Quantity::Energy | Quantity::ReverseEnergy)
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "power" => Ok(Quantity::Power),
+            "energy" => Ok(Quantity::Energy),
+            "reverse_energy" => Ok(Quantity::ReverseEnergy),
+            "current_r" => Ok(Quantity::CurrentR),
+            "current_t" => Ok(Quantity::CurrentT),
+            _ => Err(Error::InvalidRecordError { kind: "quantity", name: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sample {
+    pub timestamp: DateTime<Local>,
+    pub quantity: Quantity,
+    pub value: f64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectionEventKind {
+    /// The PANA session was established.
+    Join,
+    /// The established session was lost.
+    Loss,
+    /// The PAN was looked up again by scanning.
+    Rescan,
+}
+
+impl ConnectionEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionEventKind::Join => "join",
+            ConnectionEventKind::Loss => "loss",
+            ConnectionEventKind::Rescan => "rescan",
+        }
+    }
+}
+
+impl FromStr for ConnectionEventKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "join" => Ok(ConnectionEventKind::Join),
+            "loss" => Ok(ConnectionEventKind::Loss),
+            "rescan" => Ok(ConnectionEventKind::Rescan),
+            _ => Err(Error::InvalidRecordError { kind: "connection event", name: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ConnectionEvent {
+    pub timestamp: DateTime<Local>,
+    pub kind: ConnectionEventKind,
+}
+
+/// How long the history is kept.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Retention {
+    /// Age after which samples are downsampled.
+    pub raw: Duration,
+    /// Period covered by one downsampled sample. Samples are kept as they are when `None`.
+    pub downsample_interval: Option<Duration>,
+    /// Age after which samples and connection events are deleted. They are kept forever when `None`.
+    pub max_age: Option<Duration>,
+}
+
+/// History of the readings and the connection events, stored in SQLite with one second resolution.
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        // Let other connections query the history while samples are written.
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        Database::init(connection)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Database::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Database { connection })
+    }
+
+    pub fn insert_samples(&mut self, samples: &[Sample]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT INTO samples (timestamp, quantity, value) VALUES (?1, ?2, ?3)")?;
+            for sample in samples {
+                statement.execute(params![sample.timestamp.timestamp(), sample.quantity.as_str(), sample.value])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn insert_connection_event(&self, event: &ConnectionEvent) -> Result<()> {
+        self.connection.execute("INSERT INTO connection_events (timestamp, kind) VALUES (?1, ?2)",
+                                params![event.timestamp.timestamp(), event.kind.as_str()])?;
+        Ok(())
+    }
+
+    /// Samples taken from `from` (inclusive) to `to` (exclusive), of every quantity when `quantity` is `None`.
+    /// At most `limit` samples are returned, the oldest first.
+    pub fn samples(&self, quantity: Option<Quantity>, from: DateTime<Local>, to: DateTime<Local>,
+                   limit: Option<usize>) -> Result<Vec<Sample>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT timestamp, quantity, value FROM samples \
+             WHERE timestamp >= ?1 AND timestamp < ?2 AND (?3 IS NULL OR quantity = ?3) \
+             ORDER BY timestamp, quantity LIMIT ?4")?;
+        // A negative limit means no limit in SQLite.
+        let limit = limit.map_or(-1, |l| l as i64);
+        let rows = statement.query_map(params![from.timestamp(), to.timestamp(), quantity.map(|q| q.as_str()), limit],
+                                       |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, f64>(2)?)))?;
+        rows.map(|row| {
+            let (timestamp, quantity, value) = row?;
+            Ok(Sample { timestamp: local_time(timestamp)?, quantity: quantity.parse()?, value })
+        }).collect()
+    }
+
+    /// Connection events from `from` (inclusive) to `to` (exclusive).
+    pub fn connection_events(&self, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<ConnectionEvent>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT timestamp, kind FROM connection_events WHERE timestamp >= ?1 AND timestamp < ?2 ORDER BY timestamp")?;
+        let rows = statement.query_map(params![from.timestamp(), to.timestamp()],
+                                       |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        rows.map(|row| {
+            let (timestamp, kind) = row?;
+            Ok(ConnectionEvent { timestamp: local_time(timestamp)?, kind: kind.parse()? })
+        }).collect()
+    }
+
+    /// Downsample the samples older than `retention.raw` and delete the records older than `retention.max_age`.
+    ///
+    /// Downsampled samples are stored at the start of their interval, so running this again leaves them as they are.
+    pub fn apply_retention(&mut self, retention: &Retention, now: DateTime<Local>) -> Result<()> {
+        let now = now.timestamp();
+        let transaction = self.connection.transaction()?;
+        if let Some(max_age) = retention.max_age {
+            let cutoff = now - max_age.as_secs() as i64;
+            transaction.execute("DELETE FROM samples WHERE timestamp < ?1", params![cutoff])?;
+            transaction.execute("DELETE FROM connection_events WHERE timestamp < ?1", params![cutoff])?;
+        }
+        if let Some(interval) = retention.downsample_interval {
+            let interval = interval.as_secs().max(1) as i64;
+            // Only whole intervals are downsampled, so that no interval is split into two samples.
+            let cutoff = (now - retention.raw.as_secs() as i64).div_euclid(interval) * interval;
+            let downsampled = {
+                let mut statement = transaction.prepare(
+                    "SELECT (timestamp / ?1) * ?1 AS start, quantity, AVG(value), \
+                     (SELECT value FROM samples AS last WHERE last.quantity = samples.quantity \
+                      AND last.timestamp >= (samples.timestamp / ?1) * ?1 \
+                      AND last.timestamp < (samples.timestamp / ?1) * ?1 + ?1 \
+                      ORDER BY last.timestamp DESC LIMIT 1) \
+                     FROM samples WHERE timestamp < ?2 GROUP BY start, quantity")?;
+                let rows = statement.query_map(params![interval, cutoff], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, f64>(2)?, row.get::<_, f64>(3)?))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<(i64, String, f64, f64)>>>()?
+            };
+            transaction.execute("DELETE FROM samples WHERE timestamp < ?1", params![cutoff])?;
+            {
+                let mut statement = transaction.prepare(
+                    "INSERT INTO samples (timestamp, quantity, value) VALUES (?1, ?2, ?3)")?;
+                for (start, quantity, mean, last) in downsampled {
+                    let value = if Quantity::from_str(&quantity)?.is_cumulative() { last } else { mean };
+                    statement.execute(params![start, quantity, value])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn local_time(timestamp: i64) -> Result<DateTime<Local>> {
+    Local.timestamp_opt(timestamp, 0).single()
+        .ok_or_else(|| Error::InvalidRecordError { kind: "timestamp", name: timestamp.to_string() })
+}
+
+/// A database file in the temporary directory, deleted with its `-wal` and `-shm` files when dropped.
+#[cfg(test)]
+pub struct TempDatabase {
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDatabase {
+    pub fn new(name: &str) -> Self {
+        TempDatabase { path: crate::test_util::temp_path(&format!("{}.db", name)) }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::test_util::time;
+
+    use super::*;
+
+    const HOUR: i64 = 3600;
+    const NOW: i64 = 1700000000 / HOUR * HOUR;
+
+    fn sample(timestamp: i64, quantity: Quantity, value: f64) -> Sample {
+        Sample { timestamp: time(timestamp), quantity, value }
+    }
+
+    fn event(timestamp: i64, kind: ConnectionEventKind) -> ConnectionEvent {
+        ConnectionEvent { timestamp: time(timestamp), kind }
+    }
+
+    #[test]
+    fn query_time_range() {
+        let mut db = Database::open_in_memory().unwrap();
+        db.insert_samples(&[
+            sample(NOW - 20, Quantity::Power, 500.0),
+            sample(NOW - 10, Quantity::Power, 526.0),
+            sample(NOW - 10, Quantity::Energy, 12345.6),
+            sample(NOW, Quantity::Power, 530.0),
+        ]).unwrap();
+        db.insert_connection_event(&event(NOW - 30, ConnectionEventKind::Rescan)).unwrap();
+        db.insert_connection_event(&event(NOW - 25, ConnectionEventKind::Join)).unwrap();
+        db.insert_connection_event(&event(NOW, ConnectionEventKind::Loss)).unwrap();
+
+        assert_eq!(vec![sample(NOW - 10, Quantity::Energy, 12345.6), sample(NOW - 10, Quantity::Power, 526.0)],
+                   db.samples(None, time(NOW - 10), time(NOW), None).unwrap());
+        assert_eq!(vec![sample(NOW - 20, Quantity::Power, 500.0), sample(NOW - 10, Quantity::Power, 526.0)],
+                   db.samples(Some(Quantity::Power), time(NOW - 60), time(NOW), None).unwrap());
+        assert_eq!(vec![sample(NOW - 20, Quantity::Power, 500.0), sample(NOW - 10, Quantity::Energy, 12345.6)],
+                   db.samples(None, time(NOW - 60), time(NOW + 1), Some(2)).unwrap());
+        assert_eq!(vec![event(NOW - 30, ConnectionEventKind::Rescan), event(NOW - 25, ConnectionEventKind::Join)],
+                   db.connection_events(time(NOW - 60), time(NOW)).unwrap());
+    }
+
+    #[test]
+    fn downsample() {
+        let mut db = Database::open_in_memory().unwrap();
+        db.insert_samples(&[
+            sample(NOW - 3 * HOUR + 10, Quantity::Power, 100.0),
+            sample(NOW - 3 * HOUR + 20, Quantity::Power, 300.0),
+            sample(NOW - 3 * HOUR + 10, Quantity::Energy, 10.0),
+            sample(NOW - 3 * HOUR + 20, Quantity::Energy, 10.5),
+            sample(NOW - 2 * HOUR + 10, Quantity::Power, 400.0),
+            sample(NOW - 10, Quantity::Power, 500.0),
+            sample(NOW - 5, Quantity::Power, 600.0),
+        ]).unwrap();
+        let retention = Retention {
+            raw: Duration::from_secs(HOUR as u64),
+            downsample_interval: Some(Duration::from_secs(HOUR as u64)),
+            max_age: None,
+        };
+        let expected = vec![
+            sample(NOW - 3 * HOUR, Quantity::Energy, 10.5),
+            sample(NOW - 3 * HOUR, Quantity::Power, 200.0),
+            sample(NOW - 2 * HOUR, Quantity::Power, 400.0),
+            sample(NOW - 10, Quantity::Power, 500.0),
+            sample(NOW - 5, Quantity::Power, 600.0),
+        ];
+        db.apply_retention(&retention, time(NOW)).unwrap();
+        assert_eq!(expected, db.samples(None, time(0), time(NOW), None).unwrap());
+        db.apply_retention(&retention, time(NOW)).unwrap();
+        assert_eq!(expected, db.samples(None, time(0), time(NOW), None).unwrap());
+    }
+
+    #[test]
+    fn delete_old_records() {
+        let mut db = Database::open_in_memory().unwrap();
+        db.insert_samples(&[sample(NOW - 2 * HOUR, Quantity::CurrentR, 5.0), sample(NOW - 10, Quantity::CurrentR, 6.0)]).unwrap();
+        db.insert_connection_event(&event(NOW - 2 * HOUR, ConnectionEventKind::Join)).unwrap();
+        db.apply_retention(&Retention {
+            raw: Duration::from_secs(HOUR as u64),
+            downsample_interval: None,
+            max_age: Some(Duration::from_secs(HOUR as u64)),
+        }, time(NOW)).unwrap();
+        assert_eq!(vec![sample(NOW - 10, Quantity::CurrentR, 6.0)], db.samples(None, time(0), time(NOW), None).unwrap());
+        assert!(db.connection_events(time(0), time(NOW)).unwrap().is_empty());
+    }
+}
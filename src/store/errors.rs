@@ -0,0 +1,15 @@
+use thiserror::Error as ThisError;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("database error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("unknown {kind} {name} in the database")]
+    InvalidRecordError {
+        kind: &'static str,
+        name: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
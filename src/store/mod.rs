@@ -0,0 +1,7 @@
+mod database;
+mod errors;
+
+pub use database::{ConnectionEvent, ConnectionEventKind, Database, Quantity, Retention, Sample};
+pub use errors::Result;
+#[cfg(test)]
+pub use database::TempDatabase;
@@ -0,0 +1,8 @@
+//! Helpers shared by the tests of several modules.
+
+use std::path::PathBuf;
+
+/// A path in the temporary directory, unique to this test process and `name`.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("smart_meter_receiver_{}_{}", std::process::id(), name))
+}
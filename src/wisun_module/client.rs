@@ -1,15 +1,26 @@
+use std::collections::VecDeque;
 use std::net::Ipv6Addr;
+use std::path::Path;
 use std::thread::sleep;
 
 use std::time::{Duration, SystemTime};
-use crate::echonet::{EchonetObject, EchonetPacket, EchonetProperty, EchonetService, EchonetSmartMeterProperty, EchonetSuperClassProperty, Edata, Property, PropertyMap};
+use chrono::{Days, Local, NaiveDate, NaiveTime};
+use crate::echonet::mra::{ClassSchema, Coefficient, Value};
+use crate::echonet::{EchonetObject, EchonetPacket, EchonetProperty, EchonetService, EchonetSmartMeterProperty, EchonetSuperClassProperty, Edata, Property, PropertyCode, PropertyMap};
 
 use crate::parser::{Parser, ParseResult, SerialMessage, WiSunEvent, WiSunModuleParser};
 use crate::parser::event::{EventKind, PanDescBody};
 use crate::serial::{Connection, Error as SerialError};
 use crate::wisun_module::errors::{Error, Result};
+use crate::wisun_module::pan_cache::PanCache;
+use crate::wisun_module::types::{CumulativeEnergy, CumulativeEnergyLogEntry, InstantaneousCurrent, MeterIdentification, Notification, PhaseCurrent, ReadResult, WriteResult};
 
 const ECHONET_PORT: u16 = 3610;
+const MAX_ENERGY_LOG_DAY: u8 = 99;
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(20);
+
+type NotificationHandler = Box<dyn FnMut(&Notification) + Send>;
+type ScanHandler = Box<dyn FnMut(&PanDescBody) + Send>;
 
 pub struct WiSunClient<T: Connection> {
     serial_connection: T,
@@ -17,6 +28,15 @@ pub struct WiSunClient<T: Connection> {
     message_buffer: Vec<SerialMessage>,
     address: Option<Ipv6Addr>,
     property_map: Option<PropertyMap>,
+    set_property_map: Option<PropertyMap>,
+    energy_coefficient: Option<Coefficient>,
+    notifications: VecDeque<EchonetPacket<PropertyCode>>,
+    notification_handlers: Vec<NotificationHandler>,
+    scan_handlers: Vec<ScanHandler>,
+    pan: Option<PanDescBody>,
+    pan_cache: Option<PanCache>,
+    session_lost: bool,
+    response_timeout: Duration,
 }
 
 impl<T: Connection> WiSunClient<T> {
@@ -27,17 +47,61 @@ impl<T: Connection> WiSunClient<T> {
             message_buffer: Vec::new(),
             address: None,
             property_map: None,
+            set_property_map: None,
+            energy_coefficient: None,
+            notifications: VecDeque::new(),
+            notification_handlers: Vec::new(),
+            scan_handlers: Vec::new(),
+            pan: None,
+            pan_cache: None,
+            session_lost: false,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
         };
         client.ensure_echoback_off()?;
         Ok(client)
     }
 
+    /// Replace the serial connection, e.g. after the device was reopened.
+    /// The PANA session has to be established again with `connect`.
+    pub fn reset_connection(&mut self, serial_connection: T) -> Result<()> {
+        self.serial_connection = serial_connection;
+        self.serial_parser = WiSunModuleParser::new();
+        self.message_buffer.clear();
+        self.address = None;
+        self.session_lost = false;
+        self.ensure_echoback_off()
+    }
+
+    /// How long to wait for the smart meter to answer an ECHONET Lite request.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.response_timeout = timeout;
+    }
+
+    /// Whether the module reported that the PANA session was terminated or failed since the last `connect`.
+    pub fn is_session_lost(&self) -> bool {
+        self.session_lost
+    }
+
+    #[cfg(test)]
+    pub(crate) fn serial_connection_mut(&mut self) -> &mut T {
+        &mut self.serial_connection
+    }
+
+
     fn get_message(&mut self) -> Result<bool> {
         loop {
             match self.serial_connection.read_line() {
                 Ok(line) => {
                     match self.serial_parser.add_line(line.as_str()) {
                         ParseResult::Ok(m) => {
+                            if let Some(packet) = parse_notification(&m) {
+                                log::debug!("notification received: {:?}", packet);
+                                self.notifications.push_back(packet);
+                                return Ok(false);
+                            }
+                            if let SerialMessage::Event(WiSunEvent::Event(e)) = &m {
+                                self.update_session_state(&e.kind);
+                            }
                             self.message_buffer.push(m);
                             return Ok(true);
                         }
@@ -65,6 +129,15 @@ impl<T: Connection> WiSunClient<T> {
         }
     }
 
+    fn update_session_state(&mut self, kind: &EventKind) {
+        if *kind == EventKind::EstablishedPanaConnection {
+            self.session_lost = false;
+        } else if kind.is_session_lost() {
+            log::warn!("PANA session lost: {:?}", kind);
+            self.session_lost = true;
+        }
+    }
+
     pub fn flush_messages(&mut self) {
         // TODO: read line
         log::debug!("flushing messages");
@@ -124,6 +197,7 @@ impl<T: Connection> WiSunClient<T> {
                     if ioe.kind() == std::io::ErrorKind::TimedOut {
                         continue;
                     }
+                    return Err(Error::SerialError(SerialError::IoError(ioe)));
                 }
                 _ => { continue; }
             }
@@ -158,18 +232,88 @@ impl<T: Connection> WiSunClient<T> {
         Err(Error::CommandError("Unexpected msg".to_string()))
     }
 
+    /// Connect to the smart meter, joining the PAN found last time (or stored in the scan cache) if any
+    /// and scanning only when joining it fails.
     pub fn connect(&mut self, bid: &str, password: &str) -> Result<()> {
+        self.set_password(password)?;
+        self.set_bid(bid)?;
+        if let Some(pan) = self.pan.clone() {
+            // Only a failure to join shows that the PAN is stale; errors after the PANA session is established keep it.
+            match self.join_network(&pan) {
+                Ok(()) => {
+                    return self.complete_join(&pan);
+                }
+                Err(e) => {
+                    if e.is_io_error() {
+                        return Err(e);
+                    }
+                    log::info!("failed to join the known PAN: {:?}, scanning again", e);
+                    if let Err(e) = self.invalidate_pan_cache() {
+                        log::warn!("failed to invalidate the PAN cache: {:?}", e);
+                    }
+                }
+            }
+        }
+        let pan = self.scan()?;
+        self.join_pan(&pan)
+    }
+
+    /// Connect to the smart meter after scanning, ignoring the PAN found last time.
+    pub fn connect_with_scan(&mut self, bid: &str, password: &str) -> Result<()> {
         self.set_password(password)?;
         self.set_bid(bid)?;
         let pan = self.scan()?;
+        self.join_pan(&pan)
+    }
+
+    /// Persist the PAN found by scanning to `path` so that `connect` can skip scanning after a restart.
+    pub fn set_pan_cache(&mut self, path: &Path) {
+        let cache = PanCache::new(path);
+        if let Some(pan) = cache.load() {
+            log::debug!("cached pan: {:?}", pan);
+            self.pan = Some(pan);
+        }
+        self.pan_cache = Some(cache);
+    }
+
+    /// Forget the PAN found last time, so that the next `connect` scans again.
+    pub fn invalidate_pan_cache(&mut self) -> Result<()> {
+        self.pan = None;
+        match &self.pan_cache {
+            Some(c) => c.invalidate().map_err(Error::CacheError),
+            None => Ok(()),
+        }
+    }
+
+    fn join_pan(&mut self, pan: &PanDescBody) -> Result<()> {
+        self.join_network(pan)?;
+        self.complete_join(pan)
+    }
+
+    /// Establish the PANA session with the smart meter of `pan`.
+    fn join_network(&mut self, pan: &PanDescBody) -> Result<()> {
         let channel = format!("{:X}", pan.channel);
         let pan_id = format!("{:X}", pan.pan_id);
         self.set_register("S2", channel.as_str())?;
         self.set_register("S3", pan_id.as_str())?;
         let ip = self.get_ip(&pan.addr);
+        self.session_lost = false;
         self.join(&ip)?;
         self.address = Some(ip);
+        Ok(())
+    }
+
+    /// Read the property map of the joined smart meter and remember `pan` for the next connection.
+    fn complete_join(&mut self, pan: &PanDescBody) -> Result<()> {
         self.get_property_map()?;
+        if self.pan.as_ref() != Some(pan) {
+            if let Some(cache) = &self.pan_cache {
+                if let Err(e) = cache.save(pan) {
+                    log::warn!("failed to save the scan result: {:?}", e);
+                }
+            }
+            self.pan = Some(pan.clone());
+        }
         Ok(())
     }
 
@@ -187,34 +331,54 @@ impl<T: Connection> WiSunClient<T> {
         self.wait_ok()
     }
 
+    /// List every PAN answering a single active scan.
+    /// `duration` is the scan duration parameter of `SKSCAN`; each channel is scanned for 0.01 * (2^`duration` + 1) seconds.
+    pub fn scan_pans(&mut self, bid: &str, duration: u8) -> Result<Vec<PanDescBody>> {
+        self.set_bid(bid)?;
+        self.active_scan(duration)
+    }
+
+    /// Register a handler called with the PAN found whenever `connect` has to scan for the smart meter.
+    pub fn subscribe_scans<F>(&mut self, handler: F)
+        where F: FnMut(&PanDescBody) + Send + 'static {
+        self.scan_handlers.push(Box::new(handler));
+    }
+
     fn scan(&mut self) -> Result<PanDescBody> {
         for i in 4..10 {
-            // Start scanning -> Wait for scan finish -> Look for EPANDESC
-            self.flush_messages();
-            let line = format!("SKSCAN 2 FFFFFFFF {}", i);
-            self.serial_connection.write_line(line.as_str())?;
-            self.wait_ok()?;
-            self.wait_fn(|m| -> bool{
-                match m {
-                    SerialMessage::Event(WiSunEvent::Event(e)) => {
-                        e.kind == EventKind::FinishedActiveScan
-                    }
-                    _ => false,
+            if let Some(pan) = self.active_scan(i)?.into_iter().next() {
+                for handler in &mut self.scan_handlers {
+                    handler(&pan);
                 }
-            }, err_when_fail, None)?;
-            let desc = self.search_on_buffer(&|m| -> bool{
-                match m {
-                    SerialMessage::Event(WiSunEvent::PanDesc(_)) => true,
-                    _ => false,
-                }
-            });
-            if let Some(SerialMessage::Event(WiSunEvent::PanDesc(body))) = desc {
-                return Ok(body);
+                return Ok(pan);
             }
         }
         Err(Error::ScanError("pan not found".to_string()))
     }
 
+    fn active_scan(&mut self, duration: u8) -> Result<Vec<PanDescBody>> {
+        // Start scanning -> Wait for scan finish -> Look for EPANDESC
+        self.flush_messages();
+        let line = format!("SKSCAN 2 FFFFFFFF {:X}", duration);
+        self.serial_connection.write_line(line.as_str())?;
+        self.wait_ok()?;
+        self.wait_fn(|m| -> bool{
+            match m {
+                SerialMessage::Event(WiSunEvent::Event(e)) => {
+                    e.kind == EventKind::FinishedActiveScan
+                }
+                _ => false,
+            }
+        }, err_when_fail, None)?;
+        let mut pans = Vec::new();
+        while let Some(SerialMessage::Event(WiSunEvent::PanDesc(body))) = self.search_on_buffer(&|m| -> bool{
+            matches!(m, SerialMessage::Event(WiSunEvent::PanDesc(_)))
+        }) {
+            pans.push(body);
+        }
+        Ok(pans)
+    }
+
     fn join(&mut self, addr: &Ipv6Addr) -> Result<()> {
         let line = format!("SKJOIN {}", ipv6_addr_full_string(addr));
         self.serial_connection.write_line(line.as_str())?;
@@ -258,34 +422,91 @@ impl<T: Connection> WiSunClient<T> {
         ip.into()
     }
 
-    fn get_properties<P: EchonetProperty>(&mut self, props: &[P]) -> Result<EchonetPacket<P>> {
+    /// Read `props` of the smart meter with one Get request (ESV 0x62). Every property has to be in the property map.
+    pub fn get_properties<P: EchonetProperty>(&mut self, props: &[P]) -> Result<EchonetPacket<P>> {
         self.check_property_exists(props)?;
+        self.get_object_properties(EchonetObject::SMART_METER, props)
+    }
+
+    /// Read `props` of any object of the meter's node, such as the node profile (0x0EF001), with one Get request.
+    /// The instance code 0x00 addresses every instance of the class, and the response comes from one of them.
+    pub fn get_object_properties<P: EchonetProperty>(&mut self, object: EchonetObject, props: &[P]) -> Result<EchonetPacket<P>> {
+        let properties = props.iter()
+            .map(|p| Property { epc: *p, data: Vec::new() })
+            .collect();
+        self.request_properties(object, EchonetService::ReadPropertyRequest, properties)
+    }
+
+    /// Read `props` of the meter with one Get request (0x62), returning the result of each property.
+    ///
+    /// When the meter cannot answer some of the properties, it replies with Get_SNA (0x52) and leaves them empty,
+    /// while the others still hold their values.
+    pub fn read_properties<P: EchonetProperty>(&mut self, props: &[P]) -> Result<Vec<ReadResult<P>>> {
+        let packet = self.get_properties(props)?;
+        let refused = packet.data.echonet_service == EchonetService::ReadPropertyFailResponse;
+        Ok(packet.data.properties.into_iter()
+            .map(|p| if refused && p.data.is_empty() { ReadResult::Refused(p.epc) } else { ReadResult::Value(p) })
+            .collect())
+    }
+
+    /// Write `props` to the meter with one SetC request (0x61), such as the day (0xE5) or the date and time (0xED)
+    /// of the cumulative energy log to retrieve.
+    ///
+    /// The meter may reject some of the properties and write the others. It answers accepted properties without
+    /// data, and returns the data of the rejected ones. A property missing from the Set property map (0x9E) of the
+    /// meter is not sent.
+    pub fn set_properties<P: EchonetProperty>(&mut self, props: Vec<Property<P>>) -> Result<Vec<WriteResult<P>>> {
+        if let Some(map) = &self.set_property_map {
+            if let Some(p) = props.iter().find(|p| !map.has_property(p.epc)) {
+                return Err(Error::CommandError(format!("Property {:?} cannot be set.", p.epc)));
+            }
+        }
+        let packet = self.request_properties(EchonetObject::SMART_METER, EchonetService::WritePropertyRequest, props)?;
+        match packet.data.echonet_service {
+            EchonetService::WritePropertyResponse | EchonetService::WritePropertyFailResponse => {}
+            s => {
+                return Err(Error::CommandError(format!("unexpected response {:?} to a write", s)));
+            }
+        }
+        Ok(packet.data.properties.iter()
+            .map(|p| WriteResult { epc: p.epc, accepted: p.data.is_empty() })
+            .collect())
+    }
+
+    fn request_properties<P: EchonetProperty>(&mut self, object: EchonetObject, service: EchonetService,
+                                              properties: Vec<Property<P>>) -> Result<EchonetPacket<P>> {
+        if self.session_lost {
+            return Err(Error::SessionLostError());
+        }
         let transaction_id = rand::random();
         let packet = EchonetPacket::new(transaction_id, Edata {
-            source_object: EchonetObject::HemsController,
-            destination_object: EchonetObject::SmartMeter,
-            echonet_service: EchonetService::ReadPropertyRequest,
-            properties: props.iter()
-                .map(|p| Property { epc: *p, data: Vec::new() })
-                .collect(),
+            source_object: EchonetObject::HEMS_CONTROLLER,
+            destination_object: object,
+            echonet_service: service,
+            properties: properties.into_iter().map(Property::into_raw).collect(),
+            read_properties: Vec::new(),
         });
         self.send_udp(&packet.dump())?;
-        let packet = self.wait_echonet_packet(|p: &EchonetPacket<P>| -> bool{
+        if self.session_lost {
+            return Err(Error::SessionLostError());
+        }
+        let response_timeout = self.response_timeout;
+        let packet = self.wait_echonet_packet(|p: &EchonetPacket<PropertyCode>| -> bool{
             if p.transaction_id != transaction_id {
                 return false;
             }
             let edata = &p.data;
-            if edata.destination_object != EchonetObject::HemsController || edata.source_object != EchonetObject::SmartMeter {
+            if !edata.destination_object.matches(&EchonetObject::HEMS_CONTROLLER) || !edata.source_object.matches(&object) {
                 return false;
             }
             true
-        }, Duration::from_secs(20))?;
+        }, response_timeout)?;
 
-        Ok(packet)
+        Ok(typed_packet(packet))
     }
 
     fn check_property_exists<P: EchonetProperty>(&self, props: &[P]) -> Result<()> {
-        if props.len() == 1 && props[0].into() == EchonetSuperClassProperty::GetPropertyMap.into() {
+        if props.len() == 1 && props[0].into() == u8::from(EchonetSuperClassProperty::GetPropertyMap) {
             return Ok(());
         }
 
@@ -304,19 +525,144 @@ impl<T: Connection> WiSunClient<T> {
     }
 
     pub fn get_power_consumption(&mut self) -> Result<i32> {
-        let packet = self.get_properties(&[EchonetSmartMeterProperty::InstantaneousElectricPower])?;
+        let props = self.read_properties(&[EchonetSmartMeterProperty::InstantaneousElectricPower])?;
+        let value = decoded_value(&props, EchonetSmartMeterProperty::InstantaneousElectricPower, &Coefficient::ONE)?;
+        Ok(number(EchonetSmartMeterProperty::InstantaneousElectricPower, &value)? as i32)
+    }
 
-        let property = match packet.get_property(EchonetSmartMeterProperty::InstantaneousElectricPower).map(|p| p.get_i32()) {
-            Some(Some(p)) => p,
-            Some(None) => {
-                return Err(Error::CommandError("malformed property".to_string()));
+    /// Register a handler called for every property notified by the smart meter (ESV 0x73/0x74).
+    /// Handlers are invoked from `poll_notifications`.
+    pub fn subscribe_notifications<F>(&mut self, handler: F)
+        where F: FnMut(&Notification) + Send + 'static {
+        self.notification_handlers.push(Box::new(handler));
+    }
+
+    /// Read messages from the module for `timeout` and pass received notifications to the subscribed handlers.
+    /// Notifications requiring a response (INFC) are answered with ESV 0x7A.
+    pub fn poll_notifications(&mut self, timeout: Duration) -> Result<()> {
+        let start = SystemTime::now();
+        loop {
+            self.dispatch_notifications()?;
+            if SystemTime::now() > start + timeout {
+                return Ok(());
             }
-            None => {
-                return Err(Error::CommandError("unknown error".to_string()));
+            match self.get_message() {
+                Ok(_) => {}
+                Err(Error::SerialError(SerialError::IoError(ioe))) if ioe.kind() == std::io::ErrorKind::TimedOut => {
+                    sleep(Duration::from_millis(1));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
             }
+        }
+    }
+
+    /// A property which cannot be decoded is skipped, so that it does not drop the rest of the notifications.
+    ///
+    /// The energy coefficient is read before the first notification is taken from the queue, so that no request is
+    /// sent to the meter in the middle of dispatching them.
+    fn dispatch_notifications(&mut self) -> Result<()> {
+        if self.energy_coefficient.is_none() && self.notifications.iter().any(has_fixed_time_energy) {
+            if let Err(e) = self.get_energy_coefficient() {
+                log::warn!("failed to read the energy coefficient: {:?}", e);
+            }
+        }
+        while let Some(packet) = self.notifications.pop_front() {
+            if packet.data.echonet_service == EchonetService::PropertyNotificationResponseRequired {
+                if let Err(e) = self.respond_notification(&packet) {
+                    log::warn!("failed to respond to the notification: {:?}", e);
+                }
+            }
+            for property in packet.data.properties {
+                let mut notifications = Vec::new();
+                match self.decode_notification(&property) {
+                    Ok(n) => notifications.extend(n),
+                    Err(e) => log::warn!("failed to decode the notified property {:?}: {:?}", property.epc, e),
+                }
+                notifications.insert(0, Notification::Property(property));
+                for notification in &notifications {
+                    for handler in &mut self.notification_handlers {
+                        handler(notification);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn respond_notification(&mut self, packet: &EchonetPacket<PropertyCode>) -> Result<()> {
+        let response = EchonetPacket::new(packet.transaction_id, Edata {
+            source_object: EchonetObject::HEMS_CONTROLLER,
+            destination_object: packet.data.source_object,
+            echonet_service: EchonetService::PropertyNotificationResponse,
+            properties: packet.data.properties.iter()
+                .map(|p| Property { epc: p.epc, data: Vec::new() })
+                .collect(),
+            read_properties: Vec::new(),
+        });
+        self.send_udp(&response.dump())
+    }
+
+    /// Decode the properties which have a notification of their own.
+    fn decode_notification(&self, property: &Property<PropertyCode>) -> Result<Option<Notification>> {
+        let coefficient = || self.energy_coefficient.clone()
+            .ok_or_else(|| Error::CommandError("the energy coefficient has not been read".to_string()));
+        match property.epc.smart_meter() {
+            Some(p @ EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyAtFixedTime) => {
+                Ok(Some(Notification::NormalDirectionCumulativeEnergy(parse_fixed_time_energy(p, &property.data, &coefficient()?)?)))
+            }
+            Some(p @ EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergyAtFixedTime) => {
+                Ok(Some(Notification::ReverseDirectionCumulativeEnergy(parse_fixed_time_energy(p, &property.data, &coefficient()?)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The unit (0xE1) and the coefficient (0xD3) never change, so they are retrieved only once.
+    fn get_energy_coefficient(&mut self) -> Result<Coefficient> {
+        if let Some(coefficient) = &self.energy_coefficient {
+            return Ok(coefficient.clone());
+        }
+        let prop = EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyAtFixedTime;
+        let props = self.read_properties(&coefficient_properties(prop))?;
+        let coefficient = coefficient(&props, prop)?;
+        self.energy_coefficient = Some(coefficient.clone());
+        Ok(coefficient)
+    }
+
+    pub fn get_instantaneous_current(&mut self) -> Result<InstantaneousCurrent> {
+        let props = self.read_properties(&[EchonetSmartMeterProperty::InstantaneousCurrent])?;
+        parse_instantaneous_current(&property_value(&props, EchonetSmartMeterProperty::InstantaneousCurrent)?.data)
+    }
+
+    /// Read the manufacturer code (0x8A) and, when the meter implements it, the identification number (0x83).
+    pub fn get_identification(&mut self) -> Result<MeterIdentification> {
+        let mut props = vec![EchonetSuperClassProperty::ManufacturerCode];
+        if self.property_map.as_ref().is_some_and(|m| m.has_property(EchonetSuperClassProperty::IdentificationNumber)) {
+            props.push(EchonetSuperClassProperty::IdentificationNumber);
+        }
+        let packet = self.get_properties(&props)?;
+
+        let manufacturer_code = match packet.get_property(EchonetSuperClassProperty::ManufacturerCode) {
+            Some(p) if p.data.len() == 3 => hex::encode_upper(&p.data),
+            Some(_) => return Err(Error::CommandError("malformed property".to_string())),
+            None => return Err(Error::CommandError("unknown error".to_string())),
         };
+        // The first byte only tells the protocol (0xFE: ECHONET Lite).
+        let identification_number = packet.get_property(EchonetSuperClassProperty::IdentificationNumber)
+            .filter(|p| p.data.len() > 1)
+            .map(|p| hex::encode_upper(&p.data[1..]));
+        Ok(MeterIdentification {
+            manufacturer_code,
+            identification_number,
+            mac_address: self.pan.as_ref().map(|p| hex::encode_upper(p.addr)),
+        })
+    }
 
-        Ok(property)
+    /// Properties the smart meter supports for Get, retrieved when connecting.
+    pub fn property_map(&self) -> Option<&PropertyMap> {
+        self.property_map.as_ref()
     }
 
     pub fn get_property_map(&mut self) -> Result<()> {
@@ -327,7 +673,12 @@ impl<T: Connection> WiSunClient<T> {
         match prop {
             Some(Ok(m)) => {
                 log::debug!("property id list: {:X?}", m.get_property_ids());
+                let settable = m.has_property(EchonetSuperClassProperty::SetPropertyMap);
                 self.property_map = Some(m);
+                self.set_property_map = None;
+                if settable {
+                    self.get_set_property_map();
+                }
                 Ok(())
             }
             Some(Err(e)) => Err(e.into()),
@@ -335,51 +686,82 @@ impl<T: Connection> WiSunClient<T> {
         }
     }
 
+    /// The Set property map (0x9E) only lets `set_properties` reject a property early, so failing to read it is not
+    /// an error.
+    fn get_set_property_map(&mut self) {
+        let map = self.read_properties(&[EchonetSuperClassProperty::SetPropertyMap]).and_then(|props| {
+            Ok(PropertyMap::parse(&property_value(&props, EchonetSuperClassProperty::SetPropertyMap)?.data)?)
+        });
+        match map {
+            Ok(m) => {
+                log::debug!("settable property id list: {:X?}", m.get_property_ids());
+                self.set_property_map = Some(m);
+            }
+            Err(e) => log::warn!("failed to read the Set property map: {:?}", e),
+        }
+    }
+
     pub fn get_cumulative_electric_energy(&mut self) -> Result<f64> {
-        let props = self.get_properties(
-            &[EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergy,
-                EchonetSmartMeterProperty::UnitForCumulativeElectricEnergy,
-                EchonetSmartMeterProperty::Coefficient])?;
+        self.get_cumulative_energy(EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergy)
+    }
 
-        let base = match props.get_property(EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergy).map(|p| p.get_u32()) {
-            Some(Some(p)) => p,
-            Some(None) => {
-                return Err(Error::CommandError("malformed property".to_string()));
-            }
-            None => {
-                return Err(Error::CommandError("unknown error".to_string()));
-            }
-        };
-        let unit = match props.get_property(EchonetSmartMeterProperty::UnitForCumulativeElectricEnergy).map(|p| p.data[0]) {
-            Some(0x00) => 1.0,
-            Some(0x01) => 0.1,
-            Some(0x02) => 0.01,
-            Some(0x03) => 0.001,
-            Some(0x04) => 0.0001,
-            Some(0x0A) => 10.0,
-            Some(0x0B) => 100.0,
-            Some(0x0C) => 1000.0,
-            Some(0x0D) => 10000.0,
-            None => {
-                return Err(Error::CommandError("unknown error".to_string()));
-            }
-            Some(b) => {
-                return Err(Error::CommandError(format!("unexpected unit {:X}", b)));
-            }
-        };
+    pub fn get_reverse_cumulative_electric_energy(&mut self) -> Result<f64> {
+        self.get_cumulative_energy(EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergy)
+    }
 
-        let coefficient = match props.get_property(EchonetSmartMeterProperty::Coefficient).map(|p| p.get_u32()) {
-            Some(Some(p)) => p,
-            Some(None) => {
-                return Err(Error::CommandError("malformed property".to_string()));
-            }
-            None => {
-                return Err(Error::CommandError("unknown error".to_string()));
-            }
-        };
-        log::debug!("base: {}, unit: {}, coefficient: {}",base,unit,coefficient);
+    /// Retrieve both the imported (normal direction) and the exported (reverse direction) cumulative energy at once.
+    pub fn get_cumulative_electric_energies(&mut self) -> Result<CumulativeEnergy> {
+        let normal = EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergy;
+        let reverse = EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergy;
+        let mut read = vec![normal, reverse];
+        read.extend(coefficient_properties(normal));
+        let props = self.read_properties(&read)?;
+
+        let coefficient = coefficient(&props, normal)?;
+        Ok(CumulativeEnergy {
+            normal_direction: number(normal, &decoded_value(&props, normal, &coefficient)?)?,
+            reverse_direction: number(reverse, &decoded_value(&props, reverse, &coefficient)?)?,
+        })
+    }
+
+    fn get_cumulative_energy(&mut self, prop: EchonetSmartMeterProperty) -> Result<f64> {
+        let mut read = vec![prop];
+        read.extend(coefficient_properties(prop));
+        let props = self.read_properties(&read)?;
+
+        let coefficient = coefficient(&props, prop)?;
+        number(prop, &decoded_value(&props, prop, &coefficient)?)
+    }
+
+    /// Retrieve the half-hourly cumulative electric energy (normal direction) of the day `day` days ago.
+    pub fn get_cumulative_electric_energy_log(&mut self, day: u8) -> Result<Vec<CumulativeEnergyLogEntry>> {
+        self.get_cumulative_energy_log(EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyLog1, day)
+    }
 
-        Ok((base as f64) * unit * (coefficient as f64))
+    /// Retrieve the half-hourly cumulative electric energy (reverse direction) of the day `day` days ago.
+    pub fn get_reverse_cumulative_electric_energy_log(&mut self, day: u8) -> Result<Vec<CumulativeEnergyLogEntry>> {
+        self.get_cumulative_energy_log(EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergyLog1, day)
+    }
+
+    fn get_cumulative_energy_log(&mut self, prop: EchonetSmartMeterProperty, day: u8) -> Result<Vec<CumulativeEnergyLogEntry>> {
+        if day > MAX_ENERGY_LOG_DAY {
+            return Err(Error::CommandError(format!("day MUST BE less than or equal to {}", MAX_ENERGY_LOG_DAY)));
+        }
+        let written = self.set_properties(vec![Property {
+            epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1,
+            data: vec![day],
+        }])?;
+        if written.iter().any(|w| !w.accepted) {
+            return Err(Error::CommandError(format!("meter rejected the day {} of the cumulative energy log", day)));
+        }
+        let mut read = vec![prop];
+        read.extend(coefficient_properties(prop));
+        let props = self.read_properties(&read)?;
+
+        let data = &property_value(&props, prop)?.data;
+        let coefficient = coefficient(&props, prop)?;
+
+        parse_cumulative_energy_log(prop, data, Local::now().date_naive(), &coefficient)
     }
 
     fn send_udp(&mut self, data: &[u8]) -> Result<()> {
@@ -401,8 +783,10 @@ impl<T: Connection> WiSunClient<T> {
         self.wait_ok()
     }
 
-    fn wait_echonet_packet<F, P: EchonetProperty>(&mut self, pred: F, timeout: Duration) -> Result<EchonetPacket<P>>
-        where F: Fn(&EchonetPacket<P>) -> bool {
+    /// Wait for a packet matching `pred`. Packets are parsed with `PropertyCode`, so that a response holding a property
+    /// the receiver does not know is still received.
+    fn wait_echonet_packet<F>(&mut self, pred: F, timeout: Duration) -> Result<EchonetPacket<PropertyCode>>
+        where F: Fn(&EchonetPacket<PropertyCode>) -> bool {
         let msg = self.wait_fn(|m| -> bool{
             match m {
                 SerialMessage::Event(WiSunEvent::RxUdp(p)) => {
@@ -416,7 +800,7 @@ impl<T: Connection> WiSunClient<T> {
                 }
                 _ => false,
             }
-        }, err_when_fail, Some(timeout))?;
+        }, err_when_fail_or_session_lost, Some(timeout))?;
         if let SerialMessage::Event(WiSunEvent::RxUdp(p)) = msg {
             return Ok(EchonetPacket::parse(p.data.as_slice())?);
         }
@@ -424,6 +808,162 @@ impl<T: Connection> WiSunClient<T> {
     }
 }
 
+/// The response to a request of `P` properties, leaving out the properties which are not `P`.
+fn typed_packet<P: EchonetProperty>(packet: EchonetPacket<PropertyCode>) -> EchonetPacket<P> {
+    let typed = |properties: Vec<Property<PropertyCode>>| properties.into_iter()
+        .filter_map(|p| {
+            let epc = p.epc;
+            let property = p.typed();
+            if property.is_none() {
+                log::warn!("ignore the property 0x{:02X} of the response", epc.0);
+            }
+            property
+        })
+        .collect();
+    EchonetPacket::new(packet.transaction_id, Edata {
+        source_object: packet.data.source_object,
+        destination_object: packet.data.destination_object,
+        echonet_service: packet.data.echonet_service,
+        properties: typed(packet.data.properties),
+        read_properties: typed(packet.data.read_properties),
+    })
+}
+
+/// The value of `prop` read by `read_properties`.
+fn property_value<P: EchonetProperty>(props: &[ReadResult<P>], prop: P) -> Result<&Property<P>> {
+    match props.iter().find(|r| r.epc() == prop) {
+        Some(ReadResult::Value(p)) => Ok(p),
+        Some(ReadResult::Refused(_)) => Err(Error::PropertyRefusedError(prop.into())),
+        None => Err(Error::CommandError(format!("property {:?} missing from the response", prop))),
+    }
+}
+
+/// The value of `prop` read by `read_properties`, decoded with the schema of the smart meter and multiplied by
+/// `coefficient`.
+fn decoded_value(props: &[ReadResult<EchonetSmartMeterProperty>], prop: EchonetSmartMeterProperty, coefficient: &Coefficient) -> Result<Value> {
+    Ok(ClassSchema::smart_meter().decode_scaled(prop.into(), &property_value(props, prop)?.data, coefficient)?)
+}
+
+/// The properties the numbers of `prop` are multiplied by in the schema of the smart meter, such as the coefficient
+/// (0xD3) and the unit (0xE1) of the cumulative energies.
+fn coefficient_properties(prop: EchonetSmartMeterProperty) -> Vec<EchonetSmartMeterProperty> {
+    ClassSchema::smart_meter().property(prop.into())
+        .map(|p| p.coefficient.iter().filter_map(|epc| PropertyCode(*epc).smart_meter()).collect())
+        .unwrap_or_default()
+}
+
+/// The coefficient of `prop` from its coefficient properties read by `read_properties`.
+fn coefficient(props: &[ReadResult<EchonetSmartMeterProperty>], prop: EchonetSmartMeterProperty) -> Result<Coefficient> {
+    let values = coefficient_properties(prop).into_iter()
+        .map(|p| Ok((u8::from(p), property_value(props, p)?.data.as_slice())))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ClassSchema::smart_meter().coefficient(&values)?)
+}
+
+/// The number `value` of `prop` holds, failing when the meter has not measured it or reports an overflow.
+fn number(prop: EchonetSmartMeterProperty, value: &Value) -> Result<f64> {
+    match value {
+        Value::Number { value, .. } => Ok(*value),
+        v if v.is_no_data() => Err(Error::NoDataError(prop.into())),
+        v => Err(Error::CommandError(format!("unexpected value {} of {:?}", v, prop))),
+    }
+}
+
+/// A cumulative energy, which is `None` when the meter has not measured it.
+fn energy(prop: EchonetSmartMeterProperty, value: &Value) -> Result<Option<f64>> {
+    if value.is_no_data() {
+        return Ok(None);
+    }
+    number(prop, value).map(Some)
+}
+
+/// Parse the historical data of cumulative electric energy (0xE2 or 0xE4).
+/// The first 2 bytes are the number of days before `today`, followed by 48 values measured every 30 minutes from 0:00.
+fn parse_cumulative_energy_log(prop: EchonetSmartMeterProperty, data: &[u8], today: NaiveDate, coefficient: &Coefficient)
+                               -> Result<Vec<CumulativeEnergyLogEntry>> {
+    let value = ClassSchema::smart_meter().decode_scaled(prop.into(), data, coefficient)?;
+    let (day, energies) = match (value.element("day"), value.element("energy")) {
+        (Some(Value::Number { value: day, .. }), Some(Value::Array(energies))) => (*day as u64, energies),
+        _ => {
+            return Err(Error::CommandError("malformed property".to_string()));
+        }
+    };
+    let date = match today.checked_sub_days(Days::new(day)) {
+        Some(d) => d,
+        None => {
+            return Err(Error::CommandError(format!("unexpected day {}", day)));
+        }
+    };
+    let start = date.and_time(NaiveTime::MIN);
+
+    energies.iter()
+        .enumerate()
+        .map(|(i, e)| Ok(CumulativeEnergyLogEntry {
+            timestamp: start + chrono::Duration::minutes(30 * i as i64),
+            energy: energy(prop, e)?,
+        }))
+        .collect()
+}
+
+/// Parse the cumulative energy measured at fixed time (0xEA or 0xEB):
+/// date (YYYY:MM:DD), time (hh:mm:ss) and the cumulative energy value.
+fn parse_fixed_time_energy(prop: EchonetSmartMeterProperty, data: &[u8], coefficient: &Coefficient) -> Result<CumulativeEnergyLogEntry> {
+    let value = ClassSchema::smart_meter().decode_scaled(prop.into(), data, coefficient)?;
+    match (value.element("dateAndTime"), value.element("energy")) {
+        (Some(Value::DateTime(timestamp)), Some(e)) => Ok(CumulativeEnergyLogEntry {
+            timestamp: *timestamp,
+            energy: energy(prop, e)?,
+        }),
+        _ => Err(Error::CommandError("malformed property".to_string())),
+    }
+}
+
+/// Notifications are parsed with `PropertyCode`, so that the super class properties the meter may announce,
+/// such as the operation status, and properties unknown to the receiver do not discard the whole notification.
+/// Whether `packet` holds a cumulative energy at fixed time, which is decoded with the energy coefficient.
+fn has_fixed_time_energy(packet: &EchonetPacket<PropertyCode>) -> bool {
+    packet.data.properties.iter().any(|p| matches!(p.epc.smart_meter(),
+        Some(EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyAtFixedTime
+             | EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergyAtFixedTime)))
+}
+
+fn parse_notification(message: &SerialMessage) -> Option<EchonetPacket<PropertyCode>> {
+    let data = match message {
+        SerialMessage::Event(WiSunEvent::RxUdp(p)) if p.source_port == ECHONET_PORT => &p.data,
+        _ => {
+            return None;
+        }
+    };
+    let packet = EchonetPacket::<PropertyCode>::parse(data).ok()?;
+    match packet.data.echonet_service {
+        EchonetService::PropertyNotification | EchonetService::PropertyNotificationResponseRequired
+        if packet.data.source_object.is_same_class(&EchonetObject::SMART_METER) => Some(packet),
+        _ => None,
+    }
+}
+
+/// Parse the instantaneous current (0xE8): R and T phase values in amperes, the T phase having no data in
+/// single-phase 2-wire systems.
+fn parse_instantaneous_current(data: &[u8]) -> Result<InstantaneousCurrent> {
+    let prop = EchonetSmartMeterProperty::InstantaneousCurrent;
+    let value = ClassSchema::smart_meter().decode(prop.into(), data)?;
+    match (value.element("rPhase"), value.element("tPhase")) {
+        (Some(r), Some(t)) => Ok(InstantaneousCurrent {
+            r_phase: phase_current(prop, r)?,
+            t_phase: if t.is_no_data() { None } else { Some(phase_current(prop, t)?) },
+        }),
+        _ => Err(Error::CommandError("malformed property".to_string())),
+    }
+}
+
+fn phase_current(prop: EchonetSmartMeterProperty, value: &Value) -> Result<PhaseCurrent> {
+    match value {
+        Value::State(s) if s == "overflow" => Ok(PhaseCurrent::Overflow),
+        Value::State(s) if s == "underflow" => Ok(PhaseCurrent::Underflow),
+        v => number(prop, v).map(PhaseCurrent::Value),
+    }
+}
+
 fn create_send_udp_base(addr: &Ipv6Addr, security_bit: u8, data_length: usize) -> String {
     format!("SKSENDTO 1 {} {:04X} {} {:04X} ", ipv6_addr_full_string(addr), ECHONET_PORT, security_bit, data_length)
 }
@@ -435,6 +975,15 @@ fn err_when_fail(m: &SerialMessage) -> Option<String> {
     }
 }
 
+fn err_when_fail_or_session_lost(m: &SerialMessage) -> Option<String> {
+    match m {
+        SerialMessage::Event(WiSunEvent::Event(e)) if e.kind.is_session_lost() => {
+            Some(format!("PANA session lost: {:?}", e.kind))
+        }
+        _ => err_when_fail(m),
+    }
+}
+
 fn ipv6_addr_full_string(ip: &Ipv6Addr) -> String {
     let seg = &ip.segments();
     format!("{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}",
@@ -443,6 +992,7 @@ fn ipv6_addr_full_string(ip: &Ipv6Addr) -> String {
 
 #[cfg(test)]
 mod test {
+    use std::collections::VecDeque;
     use std::net::Ipv6Addr;
     use std::str::FromStr;
     use crate::parser::WiSunModuleParser;
@@ -464,6 +1014,15 @@ mod test {
             message_buffer: Vec::new(),
             address: None,
             property_map: None,
+            set_property_map: None,
+            energy_coefficient: None,
+            notifications: VecDeque::new(),
+            notification_handlers: Vec::new(),
+            scan_handlers: Vec::new(),
+            pan: None,
+            pan_cache: None,
+            session_lost: false,
+            response_timeout: super::DEFAULT_RESPONSE_TIMEOUT,
         }
     }
 
@@ -664,10 +1223,599 @@ mod test {
                 channel: 0x2F,
                 pan_id: 0x3077,
                 addr: [0x12, 0x34, 0x56, 0x78, 0x90, 0xAB, 0xCD, 0xEF],
+                lqi: 0x73,
             }, cli.scan().unwrap());
         }
     }
 
+    mod emulator_test {
+        use std::fs;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        use chrono::{Days, Local, NaiveDate};
+
+        use crate::echonet::{EchonetObject, EchonetSmartMeterProperty, EchonetSuperClassProperty, Property, PropertyCode};
+        use crate::test_util::temp_path;
+        use crate::wisun_module::Notification;
+        use crate::wisun_module::emulator::{ModuleEmulator, SmartMeterEmulator};
+        use crate::wisun_module::errors::Error;
+        use crate::wisun_module::client::WiSunClient;
+        use crate::wisun_module::types::{ReadResult, WriteResult};
+
+        const BID: &str = "00112233445566778899AABBCCDDEEFF";
+        const PASSWORD: &str = "0123456789AB";
+
+        fn connected_client(emulator: ModuleEmulator) -> WiSunClient<ModuleEmulator> {
+            let mut cli = WiSunClient::new(emulator).unwrap();
+            cli.connect(BID, PASSWORD).unwrap();
+            cli
+        }
+
+        fn scan_count(cli: &WiSunClient<ModuleEmulator>) -> usize {
+            cli.serial_connection.written_lines.iter()
+                .filter(|l| l.starts_with("SKSCAN"))
+                .count()
+        }
+
+        #[test]
+        fn connect_with_cached_pan() {
+            let path = temp_path("cached_pan");
+            fs::write(&path, "channel=2F\npan_id=3077\naddr=C0F9450040213077\n").unwrap();
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            cli.set_pan_cache(&path);
+            cli.connect(BID, PASSWORD).unwrap();
+            assert_eq!(0, scan_count(&cli));
+            assert!(cli.serial_connection.is_joined());
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn connect_scans_when_cached_pan_is_stale() {
+            let path = temp_path("stale_pan");
+            fs::write(&path, "channel=21\npan_id=1234\naddr=C0F9450040213077\n").unwrap();
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            cli.set_pan_cache(&path);
+            cli.connect(BID, PASSWORD).unwrap();
+            assert_eq!(1, scan_count(&cli));
+            assert!(cli.serial_connection.is_joined());
+            assert_eq!("channel=2F\npan_id=3077\naddr=C0F9450040213077\n", fs::read_to_string(&path).unwrap());
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn connect_saves_scan_result() {
+            let path = temp_path("saved_pan");
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            cli.set_pan_cache(&path);
+            cli.connect(BID, PASSWORD).unwrap();
+            assert_eq!(1, scan_count(&cli));
+            assert_eq!("channel=2F\npan_id=3077\naddr=C0F9450040213077\n", fs::read_to_string(&path).unwrap());
+
+            cli.invalidate_pan_cache().unwrap();
+            assert!(!path.exists());
+            cli.connect(BID, PASSWORD).unwrap();
+            assert_eq!(2, scan_count(&cli));
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn connect_keeps_cached_pan_after_joining() {
+            let path = temp_path("kept_pan");
+            fs::write(&path, "channel=2F\npan_id=3077\naddr=C0F9450040213077\n").unwrap();
+            let mut emulator = ModuleEmulator::default();
+            emulator.meter.set_silent(true);
+            let mut cli = WiSunClient::new(emulator).unwrap();
+            cli.set_response_timeout(Duration::from_millis(10));
+            cli.set_pan_cache(&path);
+            assert!(matches!(cli.connect(BID, PASSWORD), Err(Error::TimeoutError())));
+            assert_eq!(0, scan_count(&cli));
+            assert!(path.exists());
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn connect_scans_when_cache_cannot_be_invalidated() {
+            let path = temp_path("undeletable_pan");
+            fs::write(&path, "channel=21\npan_id=1234\naddr=C0F9450040213077\n").unwrap();
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            cli.set_pan_cache(&path);
+            // A directory in place of the cache file cannot be removed as a file.
+            fs::remove_file(&path).unwrap();
+            fs::create_dir(&path).unwrap();
+            cli.connect(BID, PASSWORD).unwrap();
+            assert_eq!(1, scan_count(&cli));
+            assert!(cli.serial_connection.is_joined());
+            fs::remove_dir(&path).unwrap();
+        }
+
+        #[test]
+        fn notify_scans() {
+            let path = temp_path("notified_pan");
+            let scanned = Arc::new(Mutex::new(Vec::new()));
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            cli.set_pan_cache(&path);
+            let s = scanned.clone();
+            cli.subscribe_scans(move |pan| s.lock().unwrap().push((pan.channel, pan.pan_id)));
+            cli.connect(BID, PASSWORD).unwrap();
+            cli.connect(BID, PASSWORD).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(vec![(0x2F, 0x3077)], *scanned.lock().unwrap());
+        }
+
+        #[test]
+        fn get_version() {
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            assert_eq!("1.2.10".to_string(), cli.get_version().unwrap());
+        }
+
+        #[test]
+        fn connect() {
+            let cli = connected_client(ModuleEmulator::default());
+            assert!(cli.serial_connection.is_joined());
+            assert_eq!(Some("2F"), cli.serial_connection.get_register("S2"));
+            assert_eq!(Some("3077"), cli.serial_connection.get_register("S3"));
+            assert!(cli.property_map.is_some());
+        }
+
+        #[test]
+        fn scan_pans() {
+            let mut emulator = ModuleEmulator::default();
+            emulator.add_neighbour_pan(0x21, 0x1234, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77], 0x2A);
+            let mut cli = WiSunClient::new(emulator).unwrap();
+            let pans = cli.scan_pans(BID, 0x0A).unwrap();
+            assert_eq!(vec!["SKSCAN 2 FFFFFFFF A"], cli.serial_connection.written_lines.iter()
+                .filter(|l| l.starts_with("SKSCAN"))
+                .collect::<Vec<&String>>());
+            assert_eq!(vec![(0x2F, 0x3077, 0x73), (0x21, 0x1234, 0x2A)],
+                       pans.iter().map(|p| (p.channel, p.pan_id, p.lqi)).collect::<Vec<(u8, u16, u8)>>());
+            assert_eq!([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77], pans[1].addr);
+            assert!(!cli.serial_connection.is_joined());
+        }
+
+        #[test]
+        fn scan_pans_finds_nothing() {
+            let mut emulator = ModuleEmulator::default();
+            emulator.set_scan_duration_required(7);
+            let mut cli = WiSunClient::new(emulator).unwrap();
+            assert!(cli.scan_pans(BID, 4).unwrap().is_empty());
+        }
+
+        #[test]
+        fn connect_after_long_scan() {
+            let mut emulator = ModuleEmulator::default();
+            emulator.set_scan_duration_required(7);
+            let cli = connected_client(emulator);
+            let scans: Vec<&String> = cli.serial_connection.written_lines.iter()
+                .filter(|l| l.starts_with("SKSCAN"))
+                .collect();
+            assert_eq!(vec!["SKSCAN 2 FFFFFFFF 4", "SKSCAN 2 FFFFFFFF 5", "SKSCAN 2 FFFFFFFF 6", "SKSCAN 2 FFFFFFFF 7"], scans);
+        }
+
+        #[test]
+        fn connect_fails_without_meter() {
+            let mut emulator = ModuleEmulator::default();
+            emulator.set_scan_duration_required(0x0F);
+            let mut cli = WiSunClient::new(emulator).unwrap();
+            match cli.connect(BID, PASSWORD) {
+                Err(Error::ScanError(_)) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+
+        #[test]
+        fn connect_fails_with_wrong_password() {
+            let mut cli = WiSunClient::new(ModuleEmulator::default()).unwrap();
+            match cli.connect(BID, "WRONGPASSWORD") {
+                Err(Error::CommandError(_)) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+            assert!(!cli.serial_connection.is_joined());
+        }
+
+        #[test]
+        fn get_identification() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            let id = cli.get_identification().unwrap();
+            assert_eq!("000016", id.manufacturer_code);
+            assert_eq!(Some("00001600000000000000000000000001".to_string()), id.identification_number);
+            assert_eq!(Some("C0F9450040213077".to_string()), id.mac_address);
+            assert_eq!("00001600000000000000000000000001", id.unique_id());
+        }
+
+        #[test]
+        fn get_identification_without_number() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.remove_property(0x83);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let id = cli.get_identification().unwrap();
+            assert_eq!(None, id.identification_number);
+            assert_eq!("C0F9450040213077", id.unique_id());
+        }
+
+        #[test]
+        fn get_power_consumption() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_property(0xE7, &[0xFF, 0xFF, 0xFF, 0x9C]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            assert_eq!(-100, cli.get_power_consumption().unwrap());
+            cli.serial_connection.meter.set_property(0xE7, &[0x00, 0x00, 0x02, 0x0E]);
+            assert_eq!(526, cli.get_power_consumption().unwrap());
+        }
+
+        #[test]
+        fn get_cumulative_electric_energy() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_property(0xE0, &[0x00, 0x01, 0xE2, 0x40]);
+            meter.set_property(0xE1, &[0x02]);
+            meter.set_property(0xD3, &[0x00, 0x00, 0x00, 0x0A]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let energy = cli.get_cumulative_electric_energy().unwrap();
+            assert!((energy - 12345.6).abs() < 1e-6);
+        }
+
+        #[test]
+        fn get_cumulative_electric_energy_log() {
+            let mut meter = SmartMeterEmulator::new();
+            let mut values = vec![0xFFFFFFFE; 48];
+            values[0] = 123456;
+            values[47] = 123556;
+            meter.set_energy_log(0xE2, 2, &values);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let log = cli.get_cumulative_electric_energy_log(2).unwrap();
+            assert_eq!(48, log.len());
+            assert!((log[0].energy.unwrap() - 12345.6).abs() < 1e-6);
+            assert!((log[47].energy.unwrap() - 12355.6).abs() < 1e-6);
+            assert_eq!(None, log[1].energy);
+            assert_eq!(Local::now().date_naive() - Days::new(2), log[0].timestamp.date());
+        }
+
+        #[test]
+        fn get_reverse_cumulative_electric_energy() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_property(0xE3, &[0x00, 0x00, 0x30, 0x39]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let energy = cli.get_reverse_cumulative_electric_energy().unwrap();
+            assert!((energy - 1234.5).abs() < 1e-6);
+        }
+
+        #[test]
+        fn get_cumulative_electric_energies() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_property(0xE0, &[0x00, 0x01, 0xE2, 0x40]);
+            meter.set_property(0xE3, &[0x00, 0x00, 0x30, 0x39]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let energy = cli.get_cumulative_electric_energies().unwrap();
+            assert!((energy.normal_direction - 12345.6).abs() < 1e-6);
+            assert!((energy.reverse_direction - 1234.5).abs() < 1e-6);
+            assert!((energy.net() - 11111.1).abs() < 1e-6);
+        }
+
+        #[test]
+        fn get_reverse_cumulative_electric_energy_log() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_energy_log(0xE4, 0, &[10; 48]);
+            meter.set_energy_log(0xE2, 0, &[20; 48]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let log = cli.get_reverse_cumulative_electric_energy_log(0).unwrap();
+            assert!(log.iter().all(|e| (e.energy.unwrap() - 1.0).abs() < 1e-6));
+        }
+
+        #[test]
+        fn get_instantaneous_current() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.set_property(0xE8, &[0x00, 0x32, 0x7F, 0xFE]);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            let current = cli.get_instantaneous_current().unwrap();
+            assert!((current.r_phase.ampere().unwrap() - 5.0).abs() < 1e-6);
+            assert_eq!(None, current.t_phase);
+        }
+
+        #[test]
+        fn receive_notification() {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let mut cli = connected_client(ModuleEmulator::default());
+            let r = received.clone();
+            cli.subscribe_notifications(move |n| r.lock().unwrap().push(n.clone()));
+            cli.serial_connection.push_notification(0x0001, false, &[
+                (0xEA, hex::decode("07E703011200000001E240").unwrap()),
+                (0xE7, vec![0x00, 0x00, 0x01, 0x00]),
+                (0x80, vec![0x30]),
+                (0xF5, vec![0x01]),
+            ]);
+            cli.poll_notifications(Duration::from_millis(10)).unwrap();
+
+            let received = received.lock().unwrap();
+            assert_eq!(5, received.len());
+            assert_eq!(Notification::Property(Property {
+                epc: EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyAtFixedTime.into(),
+                data: hex::decode("07E703011200000001E240").unwrap(),
+            }), received[0]);
+            match &received[1] {
+                Notification::NormalDirectionCumulativeEnergy(e) => {
+                    assert_eq!(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().and_hms_opt(18, 0, 0).unwrap(), e.timestamp);
+                    assert!((e.energy.unwrap() - 12345.6).abs() < 1e-6);
+                }
+                n => panic!("unexpected notification {:?}", n),
+            }
+            assert_eq!(Notification::Property(Property {
+                epc: EchonetSmartMeterProperty::InstantaneousElectricPower.into(),
+                data: vec![0x00, 0x00, 0x01, 0x00],
+            }), received[2]);
+            assert_eq!(Notification::Property(Property {
+                epc: EchonetSuperClassProperty::OperationStatus.into(),
+                data: vec![0x30],
+            }), received[3]);
+            assert_eq!(Notification::Property(Property { epc: PropertyCode(0xF5), data: vec![0x01] }), received[4]);
+            assert!(cli.serial_connection.meter.notification_responses.is_empty());
+        }
+
+        #[test]
+        fn skip_malformed_notification() {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let mut cli = connected_client(ModuleEmulator::default());
+            let r = received.clone();
+            cli.subscribe_notifications(move |n| r.lock().unwrap().push(n.clone()));
+            cli.serial_connection.push_notification(0x0001, true, &[
+                (0xEA, vec![0x07, 0xE7]),
+                (0xEA, hex::decode("07E703011200000001E240").unwrap()),
+            ]);
+            cli.poll_notifications(Duration::from_millis(10)).unwrap();
+
+            // Both properties are passed as they were received, but only the valid one is decoded.
+            let received = received.lock().unwrap();
+            assert_eq!(3, received.len());
+            assert!(matches!(received[0], Notification::Property(_)), "{:?}", received[0]);
+            assert!(matches!(received[1], Notification::Property(_)), "{:?}", received[1]);
+            assert!(matches!(received[2], Notification::NormalDirectionCumulativeEnergy(_)), "{:?}", received[2]);
+            assert_eq!(1, cli.serial_connection.meter.notification_responses.len());
+        }
+
+        #[test]
+        fn read_coefficient_before_dispatch() {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let mut cli = connected_client(ModuleEmulator::default());
+            let r = received.clone();
+            cli.subscribe_notifications(move |n| r.lock().unwrap().push(n.clone()));
+            cli.serial_connection.push_notification(0x0001, false, &[(0xEA, hex::decode("07E703011200000001E240").unwrap())]);
+            cli.serial_connection.push_notification(0x0002, false, &[(0xEB, hex::decode("07E703011200000000000A").unwrap())]);
+            let sent = cli.serial_connection.written_lines.len();
+            cli.poll_notifications(Duration::from_millis(10)).unwrap();
+
+            // One request reads the coefficient for both notifications.
+            assert_eq!(1, cli.serial_connection.written_lines[sent..].iter().filter(|l| l.starts_with("SKSENDTO")).count());
+            let received = received.lock().unwrap();
+            assert_eq!(4, received.len(), "{:?}", received);
+            assert!(matches!(received[1], Notification::NormalDirectionCumulativeEnergy(_)), "{:?}", received[1]);
+            assert!(matches!(received[3], Notification::ReverseDirectionCumulativeEnergy(_)), "{:?}", received[3]);
+        }
+
+        #[test]
+        fn respond_decode_notification() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            cli.serial_connection.push_notification(0x1234, true, &[(0xE7, vec![0x00, 0x00, 0x01, 0x00])]);
+            cli.poll_notifications(Duration::from_millis(10)).unwrap();
+            assert_eq!(vec![hex::decode("1081123405FF010288017A01E700").unwrap()],
+                       cli.serial_connection.meter.notification_responses);
+        }
+
+        #[test]
+        fn set_properties() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            let written = cli.set_properties(vec![
+                Property { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1, data: vec![100] },
+                Property { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog2, data: hex::decode("07E7030112000C").unwrap() },
+            ]).unwrap();
+            assert_eq!(vec![
+                WriteResult { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1, accepted: false },
+                WriteResult { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog2, accepted: true },
+            ], written);
+            let packet = cli.get_object_properties(EchonetObject::SMART_METER, &[EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog2]).unwrap();
+            assert_eq!(hex::decode("07E7030112000C").unwrap(), packet.data.properties[0].data);
+        }
+
+        #[test]
+        fn set_property_not_in_map() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            assert!(cli.set_property_map.as_ref().is_some_and(|m| m.has_property(EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1)));
+            let sent = cli.serial_connection.written_lines.len();
+            let result = cli.set_properties(vec![
+                Property { epc: EchonetSmartMeterProperty::DayForCumulativeElectricEnergyLog1, data: vec![1] },
+                Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: vec![0, 0, 0, 1] },
+            ]);
+            match result {
+                Err(Error::CommandError(m)) => assert_eq!("Property InstantaneousElectricPower cannot be set.", m),
+                r => panic!("unexpected result {:?}", r),
+            }
+            assert_eq!(sent, cli.serial_connection.written_lines.len());
+        }
+
+        #[test]
+        fn get_cumulative_electric_energy_log_out_of_range() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            assert!(cli.get_cumulative_electric_energy_log(100).is_err());
+        }
+
+        #[test]
+        fn read_partially_refused_properties() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            cli.serial_connection.meter.remove_property(0xE3);
+            let results = cli.read_properties(&[EchonetSmartMeterProperty::InstantaneousElectricPower,
+                EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergy]).unwrap();
+            assert_eq!(vec![
+                ReadResult::Value(Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: vec![0x00, 0x00, 0x02, 0x0E] }),
+                ReadResult::Refused(EchonetSmartMeterProperty::ReverseDirectionCumulativeElectricEnergy),
+            ], results);
+            assert!(matches!(cli.get_reverse_cumulative_electric_energy(), Err(Error::PropertyRefusedError(0xE3))));
+            assert!(cli.get_cumulative_electric_energy().is_ok());
+        }
+
+        #[test]
+        fn get_power_consumption_without_data() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            cli.serial_connection.meter.set_property(0xE7, &[0x7F, 0xFF, 0xFF, 0xFE]);
+            assert!(matches!(cli.get_power_consumption(), Err(Error::NoDataError(0xE7))));
+            cli.serial_connection.meter.set_property(0xE0, &[0xFF, 0xFF, 0xFF, 0xFE]);
+            assert!(matches!(cli.get_cumulative_electric_energy(), Err(Error::NoDataError(0xE0))));
+        }
+
+        #[test]
+        fn get_refused_power_and_current() {
+            let mut cli = connected_client(ModuleEmulator::default());
+            cli.serial_connection.meter.remove_property(0xE7);
+            cli.serial_connection.meter.remove_property(0xE8);
+            assert!(matches!(cli.get_power_consumption(), Err(Error::PropertyRefusedError(0xE7))));
+            assert!(matches!(cli.get_instantaneous_current(), Err(Error::PropertyRefusedError(0xE8))));
+        }
+
+        #[test]
+        fn get_property_not_in_map() {
+            let mut meter = SmartMeterEmulator::new();
+            meter.remove_property(0xE7);
+            let mut cli = connected_client(ModuleEmulator::new(meter));
+            assert!(cli.get_power_consumption().is_err());
+        }
+    }
+
+    mod parse_cumulative_energy_log_test {
+        use chrono::{NaiveDate, NaiveDateTime};
+
+        use crate::echonet::EchonetSmartMeterProperty;
+        use crate::echonet::mra::{ClassSchema, Coefficient};
+        use crate::wisun_module::client::parse_cumulative_energy_log;
+
+        const LOG: EchonetSmartMeterProperty = EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyLog1;
+
+        fn log_data(day: u16) -> Vec<u8> {
+            let mut data = day.to_be_bytes().to_vec();
+            for i in 0..48u32 {
+                data.extend((1000 + i).to_be_bytes());
+            }
+            data
+        }
+
+        #[test]
+        fn parse() {
+            let today = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+            let mut data = log_data(1);
+            data[6..10].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFE]);
+            let tenth = ClassSchema::smart_meter().coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x01]), (0xE1, &[0x01])]).unwrap();
+            let log = parse_cumulative_energy_log(LOG, &data, today, &tenth).unwrap();
+            assert_eq!(48, log.len());
+            assert_eq!(NaiveDateTime::parse_from_str("2023-02-28 00:00", "%Y-%m-%d %H:%M").unwrap(), log[0].timestamp);
+            assert!((log[0].energy.unwrap() - 100.0).abs() < 1e-6);
+            assert_eq!(None, log[1].energy);
+            assert_eq!(NaiveDateTime::parse_from_str("2023-02-28 23:30", "%Y-%m-%d %H:%M").unwrap(), log[47].timestamp);
+            assert!((log[47].energy.unwrap() - 104.7).abs() < 1e-6);
+        }
+
+        #[test]
+        fn parse_error_on_short_data() {
+            let today = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+            let data = log_data(0);
+            assert!(parse_cumulative_energy_log(LOG, &data[..100], today, &Coefficient::ONE).is_err());
+        }
+    }
+
+    mod parse_fixed_time_energy_test {
+        use chrono::NaiveDate;
+
+        use crate::echonet::EchonetSmartMeterProperty;
+        use crate::echonet::mra::{ClassSchema, Coefficient};
+        use crate::wisun_module::client::parse_fixed_time_energy;
+
+        const ENERGY: EchonetSmartMeterProperty = EchonetSmartMeterProperty::NormalDirectionCumulativeElectricEnergyAtFixedTime;
+
+        fn tenth() -> Coefficient {
+            ClassSchema::smart_meter().coefficient(&[(0xD3, &[0x00, 0x00, 0x00, 0x01]), (0xE1, &[0x01])]).unwrap()
+        }
+
+        #[test]
+        fn parse() {
+            let data = hex::decode("07E7030112000000000064").unwrap();
+            let energy = parse_fixed_time_energy(ENERGY, &data, &tenth()).unwrap();
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().and_hms_opt(18, 0, 0).unwrap(), energy.timestamp);
+            assert!((energy.energy.unwrap() - 10.0).abs() < 1e-6);
+        }
+
+        #[test]
+        fn no_data() {
+            let data = hex::decode("07E70301120000FFFFFFFE").unwrap();
+            assert_eq!(None, parse_fixed_time_energy(ENERGY, &data, &tenth()).unwrap().energy);
+        }
+
+        #[test]
+        fn invalid_date() {
+            let data = hex::decode("07E70D01120000000000FF").unwrap();
+            assert!(parse_fixed_time_energy(ENERGY, &data, &tenth()).is_err());
+        }
+    }
+
+    mod parse_instantaneous_current_test {
+        use crate::wisun_module::client::parse_instantaneous_current;
+        use crate::wisun_module::errors::Error;
+        use crate::wisun_module::types::PhaseCurrent;
+
+        #[test]
+        fn three_phase() {
+            let current = parse_instantaneous_current(&[0x00, 0x32, 0x01, 0x02]).unwrap();
+            assert!((current.r_phase.ampere().unwrap() - 5.0).abs() < 1e-6);
+            assert!((current.t_phase.unwrap().ampere().unwrap() - 25.8).abs() < 1e-6);
+        }
+
+        #[test]
+        fn single_phase() {
+            let current = parse_instantaneous_current(&[0x00, 0x0A, 0x7F, 0xFE]).unwrap();
+            assert!((current.r_phase.ampere().unwrap() - 1.0).abs() < 1e-6);
+            assert_eq!(None, current.t_phase);
+        }
+
+        #[test]
+        fn negative() {
+            let current = parse_instantaneous_current(&[0xFF, 0xF6, 0x00, 0x00]).unwrap();
+            assert!((current.r_phase.ampere().unwrap() + 1.0).abs() < 1e-6);
+        }
+
+        #[test]
+        fn overflow_and_underflow() {
+            let current = parse_instantaneous_current(&[0x7F, 0xFF, 0x80, 0x00]).unwrap();
+            assert_eq!(PhaseCurrent::Overflow, current.r_phase);
+            assert_eq!(Some(PhaseCurrent::Underflow), current.t_phase);
+        }
+
+        #[test]
+        fn no_data() {
+            assert!(matches!(parse_instantaneous_current(&[0x7F, 0xFE, 0x7F, 0xFE]), Err(Error::NoDataError(0xE8))));
+        }
+
+        #[test]
+        fn malformed() {
+            assert!(parse_instantaneous_current(&[0x00, 0x32]).is_err());
+        }
+    }
+
+    mod typed_packet_test {
+        use crate::echonet::{EchonetObject, EchonetPacket, EchonetService, EchonetSmartMeterProperty, Edata, Property, PropertyCode};
+        use crate::wisun_module::client::typed_packet;
+
+        #[test]
+        fn skip_unknown_properties() {
+            let packet = EchonetPacket::new(1, Edata {
+                source_object: EchonetObject::SMART_METER,
+                destination_object: EchonetObject::HEMS_CONTROLLER,
+                echonet_service: EchonetService::ReadPropertyResponse,
+                properties: vec![Property { epc: PropertyCode(0xE7), data: vec![0x00, 0x00, 0x02, 0x0E] },
+                                 Property { epc: PropertyCode(0xF5), data: vec![0x01] }],
+                read_properties: Vec::new(),
+            });
+            let packet: EchonetPacket<EchonetSmartMeterProperty> = typed_packet(packet);
+            assert_eq!(1, packet.transaction_id);
+            assert_eq!(EchonetService::ReadPropertyResponse, packet.data.echonet_service);
+            assert_eq!(vec![Property { epc: EchonetSmartMeterProperty::InstantaneousElectricPower, data: vec![0x00, 0x00, 0x02, 0x0E] }],
+                       packet.data.properties);
+        }
+    }
+
     #[test]
     fn ipv6_addr_full_string_test() {
         let ip = Ipv6Addr::from_str("FE80:0000:0000:0000:1234:5678:90AB:CDEF").unwrap();
@@ -0,0 +1,722 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::Ipv6Addr;
+
+use crate::echonet::PropertyMap;
+use crate::serial::{Connection, Error as SerialError};
+use crate::serial::errors::Result;
+
+const ECHONET_PORT: u16 = 3610;
+
+/// Emulates a BP35A1 (SKSTACK-IP) module connected to a low-voltage smart meter.
+///
+/// Every command written to the emulator queues the lines the real module would answer with,
+/// and `read_line` returns a timeout error once the queue is drained, just like the serial port does.
+pub struct ModuleEmulator {
+    output: VecDeque<String>,
+    echoback: bool,
+    version: String,
+    mac_address: [u8; 8],
+    password: Option<String>,
+    route_b_id: Option<String>,
+    registers: BTreeMap<String, String>,
+    scan_duration_required: u8,
+    neighbour_pans: Vec<(u8, u16, [u8; 8], u8)>,
+    joined: bool,
+    unplugged: bool,
+    pub meter: SmartMeterEmulator,
+    pub written_lines: Vec<String>,
+}
+
+/// Emulates the ECHONET Lite low-voltage smart meter object (0x028801) on the other side of the B-route.
+pub struct SmartMeterEmulator {
+    pub channel: u8,
+    pub pan_id: u16,
+    pub mac_address: [u8; 8],
+    pub route_b_id: String,
+    pub password: String,
+    properties: BTreeMap<u8, Vec<u8>>,
+    settable_properties: BTreeSet<u8>,
+    energy_logs: BTreeMap<(u8, u8), Vec<u32>>,
+    pub notification_responses: Vec<Vec<u8>>,
+    silent: bool,
+}
+
+impl ModuleEmulator {
+    pub fn new(meter: SmartMeterEmulator) -> Self {
+        ModuleEmulator {
+            output: VecDeque::new(),
+            echoback: true,
+            version: "1.2.10".to_string(),
+            mac_address: [0x00, 0x1D, 0x12, 0x90, 0x12, 0x34, 0x56, 0x78],
+            password: None,
+            route_b_id: None,
+            registers: BTreeMap::new(),
+            scan_duration_required: 4,
+            neighbour_pans: Vec::new(),
+            joined: false,
+            unplugged: false,
+            meter,
+            written_lines: Vec::new(),
+        }
+    }
+
+    /// The meter is only found by `SKSCAN` with a duration of at least `duration`.
+    pub fn set_scan_duration_required(&mut self, duration: u8) {
+        self.scan_duration_required = duration;
+    }
+
+    /// Let `SKSCAN` also find a PAN of another coordinator, which cannot be joined.
+    pub fn add_neighbour_pan(&mut self, channel: u8, pan_id: u16, mac_address: [u8; 8], lqi: u8) {
+        self.neighbour_pans.push((channel, pan_id, mac_address, lqi));
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.joined
+    }
+
+    pub fn get_register(&self, reg: &str) -> Option<&str> {
+        self.registers.get(reg).map(|v| v.as_str())
+    }
+
+    /// Terminate the PANA session, reporting it with the event `event` (e.g. 0x27 or 0x29).
+    pub fn lose_session(&mut self, event: u8) {
+        self.joined = false;
+        let line = format!("EVENT {:02X} {}", event, ipv6_addr_full_string(&self.meter.ip_address()));
+        self.push_line(line.as_str());
+    }
+
+    /// Make every following read and write fail as if the device was removed.
+    pub fn unplug(&mut self) {
+        self.unplugged = true;
+    }
+
+    /// Queue a raw line as if the module printed it unsolicited.
+    pub fn push_line(&mut self, line: &str) {
+        self.output.push_back(line.to_string());
+    }
+
+    pub fn ip_address(&self) -> Ipv6Addr {
+        link_local_address(&self.mac_address)
+    }
+
+    /// Let the meter send a property notification, INF (0x73) or INFC (0x74) when `response_required`.
+    pub fn push_notification(&mut self, transaction_id: u16, response_required: bool, properties: &[(u8, Vec<u8>)]) {
+        let mut frame = vec![0x10, 0x81];
+        frame.extend(transaction_id.to_be_bytes());
+        frame.extend([0x02, 0x88, 0x01, 0x05, 0xFF, 0x01]);
+        frame.push(if response_required { 0x74 } else { 0x73 });
+        frame.push(properties.len() as u8);
+        for (epc, data) in properties {
+            frame.push(*epc);
+            frame.push(data.len() as u8);
+            frame.extend(data);
+        }
+        self.push_rx_udp(&frame);
+    }
+
+    fn push_rx_udp(&mut self, frame: &[u8]) {
+        let line = format!("ERXUDP {} {} {:04X} {:04X} {} 1 {:04X} {}",
+                           ipv6_addr_full_string(&self.meter.ip_address()),
+                           ipv6_addr_full_string(&self.ip_address()),
+                           ECHONET_PORT,
+                           ECHONET_PORT,
+                           hex::encode_upper(self.meter.mac_address),
+                           frame.len(),
+                           hex::encode_upper(frame));
+        self.push_line(line.as_str());
+    }
+
+    fn handle_command(&mut self, line: &str) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            return;
+        }
+        match parts[0] {
+            "SKVER" => self.sk_ver(&parts),
+            "SKSREG" => self.sk_sreg(&parts),
+            "SKSETPWD" => self.sk_set_pwd(&parts),
+            "SKSETRBID" => self.sk_set_rbid(&parts),
+            "SKSCAN" => self.sk_scan(&parts),
+            "SKJOIN" => self.sk_join(&parts),
+            _ => self.fail("ER04"),
+        }
+    }
+
+    fn ok(&mut self) {
+        self.push_line("OK");
+    }
+
+    fn fail(&mut self, code: &str) {
+        self.push_line(format!("FAIL {}", code).as_str());
+    }
+
+    fn sk_ver(&mut self, parts: &[&str]) {
+        if parts.len() != 1 {
+            return self.fail("ER06");
+        }
+        let line = format!("EVER {}", self.version);
+        self.push_line(line.as_str());
+        self.ok();
+    }
+
+    fn sk_sreg(&mut self, parts: &[&str]) {
+        match parts.len() {
+            2 => {
+                let value = self.registers.get(parts[1]).cloned().unwrap_or_default();
+                self.push_line(format!("ESREG {}", value).as_str());
+                self.ok();
+            }
+            3 => {
+                if parts[1] == "SFE" {
+                    self.echoback = parts[2] != "0";
+                }
+                self.registers.insert(parts[1].to_string(), parts[2].to_string());
+                self.ok();
+            }
+            _ => self.fail("ER06"),
+        }
+    }
+
+    fn sk_set_pwd(&mut self, parts: &[&str]) {
+        if parts.len() != 3 {
+            return self.fail("ER06");
+        }
+        match usize::from_str_radix(parts[1], 16) {
+            Ok(len) if len == parts[2].len() && (1..=32).contains(&len) => {
+                self.password = Some(parts[2].to_string());
+                self.ok();
+            }
+            _ => self.fail("ER06"),
+        }
+    }
+
+    fn sk_set_rbid(&mut self, parts: &[&str]) {
+        if parts.len() != 2 || parts[1].len() != 32 {
+            return self.fail("ER06");
+        }
+        self.route_b_id = Some(parts[1].to_string());
+        self.ok();
+    }
+
+    fn sk_scan(&mut self, parts: &[&str]) {
+        if parts.len() != 4 {
+            return self.fail("ER06");
+        }
+        let duration = match u8::from_str_radix(parts[3], 16) {
+            Ok(d) if d <= 0x0E => d,
+            _ => return self.fail("ER06"),
+        };
+        self.ok();
+        let own_address = ipv6_addr_full_string(&self.ip_address());
+        if duration >= self.scan_duration_required {
+            self.push_pan_desc(self.meter.channel, self.meter.pan_id, self.meter.mac_address, 0x73);
+        }
+        for (channel, pan_id, mac_address, lqi) in self.neighbour_pans.clone() {
+            self.push_pan_desc(channel, pan_id, mac_address, lqi);
+        }
+        self.push_line(format!("EVENT 22 {}", own_address).as_str());
+    }
+
+    fn push_pan_desc(&mut self, channel: u8, pan_id: u16, mac_address: [u8; 8], lqi: u8) {
+        let address = ipv6_addr_full_string(&link_local_address(&mac_address));
+        self.push_line(format!("EVENT 20 {}", address).as_str());
+        self.push_line("EPANDESC");
+        self.push_line(format!("  Channel:{:02X}", channel).as_str());
+        self.push_line("  Channel Page:09");
+        self.push_line(format!("  Pan ID:{:04X}", pan_id).as_str());
+        self.push_line(format!("  Addr:{}", hex::encode_upper(mac_address)).as_str());
+        self.push_line(format!("  LQI:{:02X}", lqi).as_str());
+        self.push_line("  PairID:01234567");
+    }
+
+    fn sk_join(&mut self, parts: &[&str]) {
+        if parts.len() != 2 {
+            return self.fail("ER06");
+        }
+        let addr: Ipv6Addr = match parts[1].parse() {
+            Ok(a) => a,
+            Err(_) => return self.fail("ER06"),
+        };
+        self.ok();
+        let meter_address = ipv6_addr_full_string(&addr);
+        self.push_line(format!("EVENT 21 {} 00", meter_address).as_str());
+        let expected_channel = format!("{:X}", self.meter.channel);
+        let expected_pan_id = format!("{:X}", self.meter.pan_id);
+        let accepted = addr == self.meter.ip_address()
+            && self.get_register("S2") == Some(expected_channel.as_str())
+            && self.get_register("S3") == Some(expected_pan_id.as_str())
+            && self.password.as_deref() == Some(self.meter.password.as_str())
+            && self.route_b_id.as_deref() == Some(self.meter.route_b_id.as_str());
+        if accepted {
+            self.joined = true;
+            self.push_line(format!("EVENT 25 {}", meter_address).as_str());
+        } else {
+            self.joined = false;
+            self.push_line(format!("EVENT 24 {}", meter_address).as_str());
+        }
+    }
+
+    fn sk_send_to(&mut self, data: &[u8]) {
+        // SKSENDTO <handle> <ipaddr> <port> <sec> <datalen> <data>
+        let mut header_end = 0;
+        let mut spaces = 0;
+        for (i, b) in data.iter().enumerate() {
+            if *b == b' ' {
+                spaces += 1;
+                if spaces == 6 {
+                    header_end = i + 1;
+                    break;
+                }
+            }
+        }
+        if spaces != 6 {
+            return self.fail("ER06");
+        }
+        let header = String::from_utf8_lossy(&data[..header_end]).to_string();
+        if self.echoback {
+            self.push_line(header.trim_end());
+        }
+        let parts: Vec<&str> = header.split_whitespace().collect();
+        let (addr, port, length): (Ipv6Addr, u16, usize) = match (parts[2].parse(), u16::from_str_radix(parts[3], 16), usize::from_str_radix(parts[5], 16)) {
+            (Ok(a), Ok(p), Ok(l)) => (a, p, l),
+            _ => return self.fail("ER06"),
+        };
+        if data.len() < header_end + length {
+            return self.fail("ER06");
+        }
+        let payload = &data[header_end..header_end + length];
+
+        let address = ipv6_addr_full_string(&addr);
+        self.push_line(format!("EVENT 21 {} 00", address).as_str());
+        self.ok();
+
+        if !self.joined || addr != self.meter.ip_address() || port != ECHONET_PORT {
+            return;
+        }
+        if let Some(response) = self.meter.handle_request(payload) {
+            self.push_rx_udp(&response);
+        }
+    }
+}
+
+impl Default for ModuleEmulator {
+    fn default() -> Self {
+        ModuleEmulator::new(SmartMeterEmulator::default())
+    }
+}
+
+impl Connection for ModuleEmulator {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.unplugged {
+            return Err(unplugged_error());
+        }
+        self.written_lines.push(line.to_string());
+        if self.echoback {
+            self.push_line(line);
+        }
+        self.handle_command(line);
+        Ok(())
+    }
+
+    fn write_byte(&mut self, data: &[u8]) -> Result<()> {
+        if self.unplugged {
+            return Err(unplugged_error());
+        }
+        self.written_lines.push(String::from_utf8_lossy(data).to_string());
+        if !data.starts_with(b"SKSENDTO ") {
+            self.fail("ER04");
+            return Ok(());
+        }
+        self.sk_send_to(data);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        if self.unplugged {
+            return Err(unplugged_error());
+        }
+        match self.output.pop_front() {
+            Some(line) => Ok(line),
+            None => Err(SerialError::IoError(IoError::new(IoErrorKind::TimedOut, "timeout"))),
+        }
+    }
+}
+
+impl SmartMeterEmulator {
+    pub fn new() -> Self {
+        let mut properties = BTreeMap::new();
+        // Super class properties
+        properties.insert(0x80, vec![0x30]);
+        properties.insert(0x81, vec![0x00]);
+        properties.insert(0x82, vec![0x00, 0x00, 0x4A, 0x00]);
+        properties.insert(0x83, vec![0xFE, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        properties.insert(0x88, vec![0x42]);
+        properties.insert(0x8A, vec![0x00, 0x00, 0x16]);
+        // Low voltage smart meter properties
+        properties.insert(0xD3, vec![0x00, 0x00, 0x00, 0x01]);
+        properties.insert(0xD7, vec![0x06]);
+        properties.insert(0xE0, vec![0x00, 0x01, 0xE2, 0x40]);
+        properties.insert(0xE1, vec![0x01]);
+        properties.insert(0xE3, vec![0x00, 0x00, 0x30, 0x39]);
+        properties.insert(0xE5, vec![0x00]);
+        properties.insert(0xE7, vec![0x00, 0x00, 0x02, 0x0E]);
+        properties.insert(0xE8, vec![0x00, 0x32, 0x00, 0x14]);
+        SmartMeterEmulator {
+            channel: 0x2F,
+            pan_id: 0x3077,
+            mac_address: [0xC0, 0xF9, 0x45, 0x00, 0x40, 0x21, 0x30, 0x77],
+            route_b_id: "00112233445566778899AABBCCDDEEFF".to_string(),
+            password: "0123456789AB".to_string(),
+            properties,
+            settable_properties: BTreeSet::from([0xE5, 0xED]),
+            energy_logs: BTreeMap::new(),
+            notification_responses: Vec::new(),
+            silent: false,
+        }
+    }
+
+    pub fn ip_address(&self) -> Ipv6Addr {
+        link_local_address(&self.mac_address)
+    }
+
+    pub fn set_property(&mut self, epc: u8, data: &[u8]) {
+        self.properties.insert(epc, data.to_vec());
+    }
+
+    /// Stop answering requests, e.g. to emulate a bad radio link.
+    pub fn set_silent(&mut self, silent: bool) {
+        self.silent = silent;
+    }
+
+    pub fn remove_property(&mut self, epc: u8) {
+        self.properties.remove(&epc);
+    }
+
+    /// Set the 48 half-hourly cumulative energy values reported by the log `epc` (0xE2 or 0xE4) for `day` days ago.
+    pub fn set_energy_log(&mut self, epc: u8, day: u8, values: &[u32]) {
+        self.energy_logs.insert((epc, day), values.to_vec());
+    }
+
+    fn energy_log(&self, epc: u8) -> Option<Vec<u8>> {
+        let day = *self.properties.get(&0xE5)?.first()?;
+        let mut bin = (day as u16).to_be_bytes().to_vec();
+        match self.energy_logs.get(&(epc, day)) {
+            Some(values) => values.iter().for_each(|v| bin.extend(v.to_be_bytes())),
+            None => (0..48).for_each(|_| bin.extend(0xFFFFFFFEu32.to_be_bytes())),
+        }
+        Some(bin)
+    }
+
+    fn set_property_by_request(&mut self, epc: u8, data: &[u8]) -> bool {
+        if !self.settable_properties.contains(&epc) {
+            return false;
+        }
+        match epc {
+            0xE5 if data.len() != 1 || data[0] > 99 => false,
+            0xED if data.len() != 7 => false,
+            _ => {
+                self.properties.insert(epc, data.to_vec());
+                true
+            }
+        }
+    }
+
+    /// Encode the Get property map (EPC 0x9F) from the properties this meter holds.
+    fn property_map(&self) -> Vec<u8> {
+        let mut epcs: Vec<u8> = self.properties.keys().copied().collect();
+        epcs.push(0x9E);
+        epcs.push(0x9F);
+        epcs.push(0xE2);
+        epcs.push(0xE4);
+        epcs.sort_unstable();
+        epcs.dedup();
+
+        let mut bin = vec![epcs.len() as u8];
+        if epcs.len() < 16 {
+            bin.extend(epcs);
+            return bin;
+        }
+        let mut map = [0u8; 16];
+        for epc in epcs {
+            map[(epc & 0x0F) as usize] |= 1 << ((epc >> 4) - 8);
+        }
+        bin.extend(map);
+        bin
+    }
+
+    fn get_property(&self, epc: u8) -> Option<Vec<u8>> {
+        match epc {
+            0x9E => Some(PropertyMap::new(self.settable_properties.iter().copied()).dump()),
+            0x9F => Some(self.property_map()),
+            0xE2 | 0xE4 => self.energy_log(epc),
+            _ => self.properties.get(&epc).cloned(),
+        }
+    }
+
+    /// Properties of the node profile (0x0EF001), whose only instance is the smart meter.
+    fn get_node_profile_property(&self, epc: u8) -> Option<Vec<u8>> {
+        match epc {
+            0x80 => Some(vec![0x30]),
+            0x82 => Some(vec![0x01, 0x0D, 0x01, 0x00]),
+            0x83 | 0x8A => self.properties.get(&epc).cloned(),
+            0xD3 => Some(vec![0x00, 0x00, 0x01]),
+            0xD6 => Some(vec![0x01, 0x02, 0x88, 0x01]),
+            0xD7 => Some(vec![0x01, 0x02, 0x88]),
+            _ => None,
+        }
+    }
+
+    /// Answer an ECHONET Lite frame addressed to the smart meter or the node profile, or `None` when the meter stays silent.
+    pub fn handle_request(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        if self.silent || frame.len() < 12 || frame[0] != 0x10 || frame[1] != 0x81 {
+            return None;
+        }
+        let tid = &frame[2..4];
+        let seoj = &frame[4..7];
+        let deoj = &frame[7..10];
+        let esv = frame[10];
+        let opc = frame[11] as usize;
+        // The instance code 0x00 addresses every instance, and the only instance answers.
+        let node_profile = deoj[..2] == [0x0E, 0xF0];
+        if !(node_profile || deoj[..2] == [0x02, 0x88]) || (deoj[2] != 0x00 && deoj[2] != 0x01) {
+            return None;
+        }
+        let object = [deoj[0], deoj[1], 0x01];
+
+        let mut pos = 12;
+        let mut requested = Vec::with_capacity(opc);
+        for _ in 0..opc {
+            if frame.len() < pos + 2 {
+                return None;
+            }
+            let pdc = frame[pos + 1] as usize;
+            if frame.len() < pos + 2 + pdc {
+                return None;
+            }
+            requested.push((frame[pos], frame[pos + 2..pos + 2 + pdc].to_vec()));
+            pos += 2 + pdc;
+        }
+
+        let (response_esv, properties) = match esv {
+            0x62 => {
+                let mut failed = false;
+                let properties: Vec<(u8, Vec<u8>)> = requested.iter()
+                    .map(|(epc, _)| match if node_profile { self.get_node_profile_property(*epc) } else { self.get_property(*epc) } {
+                        Some(data) => (*epc, data),
+                        None => {
+                            failed = true;
+                            (*epc, Vec::new())
+                        }
+                    })
+                    .collect();
+                (if failed { 0x52 } else { 0x72 }, properties)
+            }
+            // SetI is only answered when a property is rejected.
+            0x60 | 0x61 if !node_profile => {
+                let mut failed = false;
+                let properties: Vec<(u8, Vec<u8>)> = requested.iter()
+                    .map(|(epc, data)| if self.set_property_by_request(*epc, data) {
+                        (*epc, Vec::new())
+                    } else {
+                        failed = true;
+                        (*epc, data.clone())
+                    })
+                    .collect();
+                match (esv, failed) {
+                    (0x60, false) => return None,
+                    (0x60, true) => (0x50, properties),
+                    (_, false) => (0x71, properties),
+                    (_, true) => (0x51, properties),
+                }
+            }
+            0x7A => {
+                self.notification_responses.push(frame.to_vec());
+                return None;
+            }
+            _ => return None,
+        };
+
+        let mut bin = vec![0x10, 0x81];
+        bin.extend_from_slice(tid);
+        bin.extend_from_slice(&object);
+        bin.extend_from_slice(seoj);
+        bin.push(response_esv);
+        bin.push(properties.len() as u8);
+        for (epc, data) in properties {
+            bin.push(epc);
+            bin.push(data.len() as u8);
+            bin.extend(data);
+        }
+        Some(bin)
+    }
+}
+
+impl Default for SmartMeterEmulator {
+    fn default() -> Self {
+        SmartMeterEmulator::new()
+    }
+}
+
+fn unplugged_error() -> SerialError {
+    SerialError::IoError(IoError::new(IoErrorKind::BrokenPipe, "device removed"))
+}
+
+fn link_local_address(mac: &[u8; 8]) -> Ipv6Addr {
+    let mut ip: [u8; 16] = [0xFE, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    ip[8..].copy_from_slice(mac);
+    ip[8] ^= 0b00000010;
+    ip.into()
+}
+
+fn ipv6_addr_full_string(ip: &Ipv6Addr) -> String {
+    ip.segments().iter()
+        .map(|s| format!("{:04X}", s))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_all(emulator: &mut ModuleEmulator) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Ok(l) = emulator.read_line() {
+            lines.push(l);
+        }
+        lines
+    }
+
+    #[test]
+    fn echoback_until_disabled() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.write_line("SKSREG SFE 0").unwrap();
+        assert_eq!(vec!["SKSREG SFE 0", "OK"], read_all(&mut emulator));
+        emulator.write_line("SKVER").unwrap();
+        assert_eq!(vec!["EVER 1.2.10", "OK"], read_all(&mut emulator));
+    }
+
+    #[test]
+    fn timeout_when_empty() {
+        let mut emulator = ModuleEmulator::default();
+        match emulator.read_line() {
+            Err(SerialError::IoError(e)) => assert_eq!(IoErrorKind::TimedOut, e.kind()),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.write_line("SKSREG SFE 0").unwrap();
+        read_all(&mut emulator);
+        emulator.write_line("SKFOO").unwrap();
+        assert_eq!(vec!["FAIL ER04"], read_all(&mut emulator));
+    }
+
+    #[test]
+    fn scan_not_found_with_short_duration() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.write_line("SKSREG SFE 0").unwrap();
+        emulator.set_scan_duration_required(6);
+        read_all(&mut emulator);
+        emulator.write_line("SKSCAN 2 FFFFFFFF 5").unwrap();
+        assert_eq!(vec!["OK", "EVENT 22 FE80:0000:0000:0000:021D:1290:1234:5678"], read_all(&mut emulator));
+    }
+
+    #[test]
+    fn join_fails_with_wrong_password() {
+        let mut emulator = ModuleEmulator::default();
+        emulator.write_line("SKSREG SFE 0").unwrap();
+        emulator.write_line("SKSETPWD 4 ABCD").unwrap();
+        emulator.write_line("SKSETRBID 00112233445566778899AABBCCDDEEFF").unwrap();
+        emulator.write_line("SKSREG S2 2F").unwrap();
+        emulator.write_line("SKSREG S3 3077").unwrap();
+        read_all(&mut emulator);
+        emulator.write_line("SKJOIN FE80:0000:0000:0000:C2F9:4500:4021:3077").unwrap();
+        assert_eq!(vec!["OK",
+                        "EVENT 21 FE80:0000:0000:0000:C2F9:4500:4021:3077 00",
+                        "EVENT 24 FE80:0000:0000:0000:C2F9:4500:4021:3077"],
+                   read_all(&mut emulator));
+        assert!(!emulator.is_joined());
+    }
+
+    #[test]
+    fn meter_get_response() {
+        let mut meter = SmartMeterEmulator::new();
+        let request = hex::decode("1081123405FF010288016201E700").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(hex::decode("1081123402880105FF017201E7040000020E").unwrap(), response);
+    }
+
+    #[test]
+    fn node_profile_get_response() {
+        let mut meter = SmartMeterEmulator::new();
+        let request = hex::decode("1081123405FF010EF0006201D600").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(hex::decode("108112340EF00105FF017201D60401028801").unwrap(), response);
+        assert_eq!(None, meter.handle_request(&hex::decode("1081123405FF010288026201E700").unwrap()));
+    }
+
+    #[test]
+    fn meter_get_fail_response() {
+        let mut meter = SmartMeterEmulator::new();
+        let request = hex::decode("1081123405FF010288016202E700F000").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(hex::decode("1081123402880105FF015202E7040000020EF000").unwrap(), response);
+    }
+
+    #[test]
+    fn meter_set_response() {
+        let mut meter = SmartMeterEmulator::new();
+        let request = hex::decode("1081123405FF010288016101E50101").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(hex::decode("1081123402880105FF017101E500").unwrap(), response);
+
+        let request = hex::decode("1081123405FF010288016201E200").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(12 + 2 + 194, response.len());
+        assert_eq!(vec![0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFE], response[14..20].to_vec());
+    }
+
+    #[test]
+    fn meter_set_fail_response() {
+        let mut meter = SmartMeterEmulator::new();
+        let request = hex::decode("1081123405FF010288016102E50101E70100").unwrap();
+        let response = meter.handle_request(&request).unwrap();
+        assert_eq!(hex::decode("1081123402880105FF015102E500E70100").unwrap(), response);
+    }
+
+    #[test]
+    fn meter_set_without_response() {
+        let mut meter = SmartMeterEmulator::new();
+        assert_eq!(None, meter.handle_request(&hex::decode("1081123405FF010288016001E50101").unwrap()));
+        let response = meter.handle_request(&hex::decode("1081123405FF010288016001E50164").unwrap()).unwrap();
+        assert_eq!(hex::decode("1081123402880105FF015001E50164").unwrap(), response);
+    }
+
+    #[test]
+    fn meter_property_map_short() {
+        let mut meter = SmartMeterEmulator::new();
+        meter.remove_property(0x83);
+        meter.remove_property(0xD7);
+        meter.remove_property(0xE3);
+        let bin = meter.property_map();
+        assert_eq!(16, bin.len());
+        let map = PropertyMap::parse(&bin).unwrap();
+        assert_eq!(15, map.get_property_ids().len());
+        assert!(map.get_property_ids().contains(&0x9F));
+    }
+
+    #[test]
+    fn meter_property_map_long() {
+        let mut meter = SmartMeterEmulator::new();
+        meter.set_property(0x8C, &[0x00]);
+        let bin = meter.property_map();
+        assert_eq!(17, bin.len());
+        let map = PropertyMap::parse(&bin).unwrap();
+        assert_eq!(19, map.get_property_ids().len());
+        assert!(map.get_property_ids().contains(&0xE8));
+    }
+}
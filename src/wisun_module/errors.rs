@@ -13,6 +13,42 @@ pub enum Error {
     PacketParseError(#[from] crate::echonet::Error),
     #[error("timeout")]
     TimeoutError(),
+    #[error("PANA session lost")]
+    SessionLostError(),
+    #[error("not connected to the smart meter")]
+    NotConnectedError(),
+    #[error("failed to access the scan cache: {0}")]
+    CacheError(#[source] std::io::Error),
+    #[error("the meter refused property 0x{0:02X}")]
+    PropertyRefusedError(u8),
+    #[error("the meter has not measured property 0x{0:02X}")]
+    NoDataError(u8),
+}
+
+impl Error {
+    /// Whether the serial port itself failed, in which case it has to be opened again.
+    pub fn is_io_error(&self) -> bool {
+        match self {
+            Error::SerialError(SerialError::IoError(ioe)) => ioe.kind() != std::io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+
+    /// Name of the variant, used to tell errors apart in metrics.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Error::SerialError(_) => "SerialError",
+            Error::CommandError(_) => "CommandError",
+            Error::ScanError(_) => "ScanError",
+            Error::PacketParseError(_) => "PacketParseError",
+            Error::TimeoutError() => "TimeoutError",
+            Error::SessionLostError() => "SessionLostError",
+            Error::NotConnectedError() => "NotConnectedError",
+            Error::CacheError(_) => "CacheError",
+            Error::PropertyRefusedError(_) => "PropertyRefusedError",
+            Error::NoDataError(_) => "NoDataError",
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,5 +1,13 @@
 mod client;
 mod errors;
 mod mock;
+mod pan_cache;
+mod supervisor;
+mod types;
+#[cfg(test)]
+pub mod emulator;
 
 pub use client::WiSunClient;
+pub use errors::{Error, Result};
+pub use supervisor::{ConnectionState, Supervisor};
+pub use types::{CumulativeEnergy, InstantaneousCurrent, MeterIdentification, Notification, PhaseCurrent, ReadResult};
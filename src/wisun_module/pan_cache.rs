@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::parser::event::PanDescBody;
+
+/// Stores the PAN found by `SKSCAN` in a small text file:
+///
+/// ```text
+/// channel=2F
+/// pan_id=3077
+/// addr=C0F9450040213077
+/// ```
+///
+/// The LQI is not stored, since it only describes the beacon received during that scan.
+pub struct PanCache {
+    path: PathBuf,
+}
+
+impl PanCache {
+    pub fn new(path: &Path) -> Self {
+        PanCache { path: path.to_path_buf() }
+    }
+
+    /// Read the cached PAN. A missing or malformed file is treated as an empty cache.
+    pub fn load(&self) -> Option<PanDescBody> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(t) => t,
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    log::warn!("failed to read {}: {}", self.path.display(), e);
+                }
+                return None;
+            }
+        };
+        let pan = parse(&text);
+        if pan.is_none() {
+            log::warn!("ignoring malformed scan cache {}", self.path.display());
+        }
+        pan
+    }
+
+    pub fn save(&self, pan: &PanDescBody) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        let text = format!("channel={:02X}\npan_id={:04X}\naddr={}\n", pan.channel, pan.pan_id, hex::encode_upper(pan.addr));
+        fs::write(&self.path, text)
+    }
+
+    pub fn invalidate(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse(text: &str) -> Option<PanDescBody> {
+    let values: HashMap<&str, &str> = text.lines()
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    let channel = u8::from_str_radix(values.get("channel")?, 16).ok()?;
+    let pan_id = u16::from_str_radix(values.get("pan_id")?, 16).ok()?;
+    let addr: [u8; 8] = hex::decode(values.get("addr")?).ok()?.try_into().ok()?;
+    Some(PanDescBody { channel, pan_id, addr, lqi: 0 })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::temp_path;
+
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_path("save_and_load");
+        let cache = PanCache::new(&path);
+        let pan = PanDescBody {
+            channel: 0x2F,
+            pan_id: 0x3077,
+            addr: [0xC0, 0xF9, 0x45, 0x00, 0x40, 0x21, 0x30, 0x77],
+            lqi: 0,
+        };
+        cache.save(&pan).unwrap();
+        assert_eq!("channel=2F\npan_id=3077\naddr=C0F9450040213077\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(Some(pan), cache.load());
+
+        cache.invalidate().unwrap();
+        assert_eq!(None, cache.load());
+        cache.invalidate().unwrap();
+    }
+
+    #[test]
+    fn load_missing() {
+        assert_eq!(None, PanCache::new(&temp_path("missing")).load());
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(None, parse("channel=2F\npan_id=3077\n"));
+        assert_eq!(None, parse("channel=XX\npan_id=3077\naddr=C0F9450040213077\n"));
+        assert_eq!(None, parse("channel=2F\npan_id=3077\naddr=C0F94500\n"));
+    }
+}
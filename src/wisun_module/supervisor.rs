@@ -0,0 +1,318 @@
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+use crate::serial::{Connection, errors::Result as SerialResult};
+use crate::wisun_module::client::WiSunClient;
+use crate::wisun_module::errors::{Error, Result};
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(600);
+const DEFAULT_MAX_CONSECUTIVE_TIMEOUTS: u32 = 3;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    Lost,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Lost => "lost",
+        }
+    }
+}
+
+type StateHandler = Box<dyn FnMut(ConnectionState, ConnectionState) + Send>;
+
+/// Keeps a `WiSunClient` connected to the smart meter.
+///
+/// The session is considered lost when the module reports a PANA error or termination event,
+/// when requests time out repeatedly, or when the serial port fails.
+/// The next call to `run` then connects again, waiting with exponential backoff between failed attempts.
+pub struct Supervisor<T: Connection, F: FnMut() -> SerialResult<T>> {
+    open_connection: F,
+    client: WiSunClient<T>,
+    bid: String,
+    password: String,
+    state: ConnectionState,
+    state_handlers: Vec<StateHandler>,
+    reopen: bool,
+    rescan: bool,
+    consecutive_timeouts: u32,
+    max_consecutive_timeouts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl<T: Connection, F: FnMut() -> SerialResult<T>> Supervisor<T, F> {
+    pub fn new(mut open_connection: F, bid: &str, password: &str) -> Result<Self> {
+        let client = WiSunClient::new(open_connection()?)?;
+        Ok(Supervisor {
+            open_connection,
+            client,
+            bid: bid.to_string(),
+            password: password.to_string(),
+            state: ConnectionState::Disconnected,
+            state_handlers: Vec::new(),
+            reopen: false,
+            rescan: false,
+            consecutive_timeouts: 0,
+            max_consecutive_timeouts: DEFAULT_MAX_CONSECUTIVE_TIMEOUTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            backoff: DEFAULT_INITIAL_BACKOFF,
+            next_attempt: None,
+        })
+    }
+
+    /// Wait `initial` after the first failed attempt, doubling the wait up to `max` after each further failure.
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self.backoff = initial;
+    }
+
+    /// Number of timeouts in a row after which the session is considered lost.
+    pub fn set_max_consecutive_timeouts(&mut self, count: u32) {
+        self.max_consecutive_timeouts = count;
+    }
+
+    /// Scan for the PAN on every reconnection instead of joining the PAN found last time.
+    pub fn set_rescan(&mut self, rescan: bool) {
+        self.rescan = rescan;
+    }
+
+    /// Register a handler called with the previous and the new state on every state transition.
+    pub fn subscribe_state_changes<H>(&mut self, handler: H)
+        where H: FnMut(ConnectionState, ConnectionState) + Send + 'static {
+        self.state_handlers.push(Box::new(handler));
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn client(&mut self) -> &mut WiSunClient<T> {
+        &mut self.client
+    }
+
+    /// Run `op` on a connected client, connecting first if necessary.
+    pub fn run<R, O>(&mut self, op: O) -> Result<R>
+        where O: FnOnce(&mut WiSunClient<T>) -> Result<R> {
+        self.ensure_connected()?;
+        let result = op(&mut self.client);
+        self.inspect(&result);
+        result
+    }
+
+    fn ensure_connected(&mut self) -> Result<()> {
+        if self.state == ConnectionState::Connected {
+            return Ok(());
+        }
+        if let Some(t) = self.next_attempt {
+            if Instant::now() < t {
+                return Err(Error::NotConnectedError());
+            }
+        }
+
+        self.set_state(ConnectionState::Connecting);
+        match self.try_connect() {
+            Ok(()) => {
+                self.backoff = self.initial_backoff;
+                self.next_attempt = None;
+                self.consecutive_timeouts = 0;
+                self.set_state(ConnectionState::Connected);
+                Ok(())
+            }
+            Err(e) => {
+                log::warn!("failed to connect: {:?}, retrying in {:?}", e, self.backoff);
+                self.next_attempt = Some(Instant::now() + self.backoff);
+                self.backoff = min(self.backoff * 2, self.max_backoff);
+                if e.is_io_error() {
+                    self.reopen = true;
+                }
+                self.set_state(ConnectionState::Disconnected);
+                Err(e)
+            }
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<()> {
+        if self.reopen {
+            let connection = (self.open_connection)()?;
+            self.client.reset_connection(connection)?;
+            self.reopen = false;
+        }
+
+        if self.rescan {
+            return self.client.connect_with_scan(&self.bid, &self.password);
+        }
+        self.client.connect(&self.bid, &self.password)
+    }
+
+    fn inspect<R>(&mut self, result: &Result<R>) {
+        match result {
+            Ok(_) => {
+                self.consecutive_timeouts = 0;
+            }
+            Err(Error::TimeoutError()) => {
+                self.consecutive_timeouts += 1;
+                if self.consecutive_timeouts >= self.max_consecutive_timeouts {
+                    log::warn!("{} timeouts in a row", self.consecutive_timeouts);
+                    self.set_state(ConnectionState::Lost);
+                }
+            }
+            Err(e) if e.is_io_error() => {
+                log::warn!("serial connection failed: {:?}", e);
+                self.reopen = true;
+                self.set_state(ConnectionState::Lost);
+            }
+            Err(_) => {}
+        }
+        if self.client.is_session_lost() {
+            self.set_state(ConnectionState::Lost);
+        }
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state == state {
+            return;
+        }
+        let previous = self.state;
+        self.state = state;
+        log::info!("connection state changed: {:?} -> {:?}", previous, state);
+        for handler in &mut self.state_handlers {
+            handler(previous, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::wisun_module::emulator::ModuleEmulator;
+
+    use super::*;
+
+    const BID: &str = "00112233445566778899AABBCCDDEEFF";
+    const PASSWORD: &str = "0123456789AB";
+
+    fn new_supervisor(opened: Arc<AtomicUsize>) -> Supervisor<ModuleEmulator, impl FnMut() -> SerialResult<ModuleEmulator>> {
+        let mut supervisor = Supervisor::new(move || {
+            opened.fetch_add(1, Ordering::SeqCst);
+            Ok(ModuleEmulator::default())
+        }, BID, PASSWORD).unwrap();
+        supervisor.set_backoff(Duration::ZERO, Duration::ZERO);
+        supervisor.client().set_response_timeout(Duration::from_millis(10));
+        supervisor
+    }
+
+    fn record_states<C, G>(supervisor: &mut Supervisor<C, G>) -> Arc<Mutex<Vec<ConnectionState>>>
+        where C: Connection, G: FnMut() -> SerialResult<C> {
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let s = states.clone();
+        supervisor.subscribe_state_changes(move |_, new| s.lock().unwrap().push(new));
+        states
+    }
+
+    fn scan_count(supervisor: &mut Supervisor<ModuleEmulator, impl FnMut() -> SerialResult<ModuleEmulator>>) -> usize {
+        supervisor.client().serial_connection_mut().written_lines.iter()
+            .filter(|l| l.starts_with("SKSCAN"))
+            .count()
+    }
+
+    #[test]
+    fn connect_on_first_run() {
+        let mut supervisor = new_supervisor(Arc::new(AtomicUsize::new(0)));
+        let states = record_states(&mut supervisor);
+        assert_eq!(526, supervisor.run(|c| c.get_power_consumption()).unwrap());
+        assert_eq!(ConnectionState::Connected, supervisor.state());
+        assert_eq!(vec![ConnectionState::Connecting, ConnectionState::Connected], *states.lock().unwrap());
+    }
+
+    #[test]
+    fn reconnect_without_scan_after_session_lost() {
+        let mut supervisor = new_supervisor(Arc::new(AtomicUsize::new(0)));
+        supervisor.run(|c| c.get_power_consumption()).unwrap();
+        let states = record_states(&mut supervisor);
+        assert_eq!(1, scan_count(&mut supervisor));
+
+        supervisor.client().serial_connection_mut().lose_session(0x27);
+        match supervisor.run(|c| c.get_power_consumption()) {
+            Err(Error::SessionLostError()) | Err(Error::CommandError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(ConnectionState::Lost, supervisor.state());
+
+        assert_eq!(526, supervisor.run(|c| c.get_power_consumption()).unwrap());
+        assert_eq!(1, scan_count(&mut supervisor));
+        assert_eq!(vec![ConnectionState::Lost, ConnectionState::Connecting, ConnectionState::Connected], *states.lock().unwrap());
+    }
+
+    #[test]
+    fn rescan_when_enabled() {
+        let mut supervisor = new_supervisor(Arc::new(AtomicUsize::new(0)));
+        supervisor.set_rescan(true);
+        supervisor.run(|c| c.get_power_consumption()).unwrap();
+        supervisor.client().serial_connection_mut().lose_session(0x29);
+        assert!(supervisor.run(|c| c.get_power_consumption()).is_err());
+        supervisor.run(|c| c.get_power_consumption()).unwrap();
+        assert_eq!(2, scan_count(&mut supervisor));
+    }
+
+    #[test]
+    fn lost_after_consecutive_timeouts() {
+        let mut supervisor = new_supervisor(Arc::new(AtomicUsize::new(0)));
+        supervisor.set_max_consecutive_timeouts(2);
+        supervisor.run(|c| c.get_power_consumption()).unwrap();
+        supervisor.client().serial_connection_mut().meter.set_silent(true);
+
+        assert!(matches!(supervisor.run(|c| c.get_power_consumption()), Err(Error::TimeoutError())));
+        assert_eq!(ConnectionState::Connected, supervisor.state());
+        assert!(matches!(supervisor.run(|c| c.get_power_consumption()), Err(Error::TimeoutError())));
+        assert_eq!(ConnectionState::Lost, supervisor.state());
+
+        supervisor.client().serial_connection_mut().meter.set_silent(false);
+        assert_eq!(526, supervisor.run(|c| c.get_power_consumption()).unwrap());
+    }
+
+    #[test]
+    fn reopen_after_io_error() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = new_supervisor(opened.clone());
+        supervisor.run(|c| c.get_power_consumption()).unwrap();
+        assert_eq!(1, opened.load(Ordering::SeqCst));
+
+        supervisor.client().serial_connection_mut().unplug();
+        assert!(supervisor.run(|c| c.get_power_consumption()).is_err());
+        assert_eq!(ConnectionState::Lost, supervisor.state());
+
+        assert_eq!(526, supervisor.run(|c| c.get_power_consumption()).unwrap());
+        assert_eq!(2, opened.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn backoff_after_failure() {
+        let mut supervisor = Supervisor::new(|| {
+            let mut emulator = ModuleEmulator::default();
+            emulator.set_scan_duration_required(0x0F);
+            Ok(emulator)
+        }, BID, PASSWORD).unwrap();
+        supervisor.set_backoff(Duration::from_secs(3600), Duration::from_secs(7200));
+        assert!(matches!(supervisor.run(|c| c.get_power_consumption()), Err(Error::ScanError(_))));
+        assert_eq!(ConnectionState::Disconnected, supervisor.state());
+        assert!(matches!(supervisor.run(|c| c.get_power_consumption()), Err(Error::NotConnectedError())));
+        assert_eq!(Duration::from_secs(7200), supervisor.backoff);
+    }
+}
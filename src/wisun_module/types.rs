@@ -0,0 +1,131 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::NaiveDateTime;
+
+use crate::echonet::{EchonetProperty, Property, PropertyCode};
+
+/// A cumulative electric energy value measured every 30 minutes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CumulativeEnergyLogEntry {
+    pub timestamp: NaiveDateTime,
+    /// Cumulative electric energy in kWh, or `None` when the meter has no data for this slot.
+    pub energy: Option<f64>,
+}
+
+/// Cumulative electric energy in kWh measured in both directions.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CumulativeEnergy {
+    /// Energy bought from the grid.
+    pub normal_direction: f64,
+    /// Energy sold back to the grid, e.g. from solar panels.
+    pub reverse_direction: f64,
+}
+
+impl CumulativeEnergy {
+    /// Net consumption: imported energy minus exported energy.
+    pub fn net(&self) -> f64 {
+        self.normal_direction - self.reverse_direction
+    }
+}
+
+/// Outcome of one property of a Get request.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ReadResult<P: EchonetProperty> {
+    Value(Property<P>),
+    /// The meter refused the property, answering it without data in a Get_SNA (0x52).
+    Refused(P),
+}
+
+impl<P: EchonetProperty> ReadResult<P> {
+    pub fn epc(&self) -> P {
+        match self {
+            ReadResult::Value(p) => p.epc,
+            ReadResult::Refused(epc) => *epc,
+        }
+    }
+}
+
+/// Outcome of one property of a Set request.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WriteResult<P> {
+    pub epc: P,
+    /// Whether the meter wrote the value, rather than rejecting it.
+    pub accepted: bool,
+}
+
+/// Current of a single phase measured by the smart meter.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PhaseCurrent {
+    /// Current in amperes.
+    Value(f64),
+    Overflow,
+    Underflow,
+}
+
+impl PhaseCurrent {
+    pub fn ampere(&self) -> Option<f64> {
+        match self {
+            PhaseCurrent::Value(a) => Some(*a),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PhaseCurrent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PhaseCurrent::Value(a) => write!(f, "{:.1} A", a),
+            PhaseCurrent::Overflow => write!(f, "overflow"),
+            PhaseCurrent::Underflow => write!(f, "underflow"),
+        }
+    }
+}
+
+/// Instantaneous current (0xE8) of R and T phases.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InstantaneousCurrent {
+    pub r_phase: PhaseCurrent,
+    /// `None` for single-phase 2-wire systems.
+    pub t_phase: Option<PhaseCurrent>,
+}
+
+impl Display for InstantaneousCurrent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.t_phase {
+            Some(t) => write!(f, "R: {}, T: {}", self.r_phase, t),
+            None => write!(f, "{}", self.r_phase),
+        }
+    }
+}
+
+/// Data identifying the smart meter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MeterIdentification {
+    /// Manufacturer code (0x8A) as 6 hex digits.
+    pub manufacturer_code: String,
+    /// Identification number (0x83) as hex digits, if the meter implements it.
+    pub identification_number: Option<String>,
+    /// MAC address of the meter as 16 hex digits.
+    pub mac_address: Option<String>,
+}
+
+impl MeterIdentification {
+    /// An ID unique to the meter: the identification number, or the MAC address when the meter has none.
+    pub fn unique_id(&self) -> String {
+        self.identification_number.clone()
+            .or_else(|| self.mac_address.clone())
+            .unwrap_or_else(|| self.manufacturer_code.clone())
+    }
+}
+
+/// A property notified by the smart meter without request.
+///
+/// Every notified property is passed as `Property` as it was received, followed by its decoded value for 0xEA and 0xEB.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Notification {
+    /// Cumulative energy (normal direction) measured at the last 30-minute boundary (0xEA).
+    NormalDirectionCumulativeEnergy(CumulativeEnergyLogEntry),
+    /// Cumulative energy (reverse direction) measured at the last 30-minute boundary (0xEB).
+    ReverseDirectionCumulativeEnergy(CumulativeEnergyLogEntry),
+    Property(Property<PropertyCode>),
+}